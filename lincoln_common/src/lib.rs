@@ -10,6 +10,10 @@ mod permutation;
 pub use traits::{Access, AccessMut, AnyDebugDisplay, StringLike};
 pub use permutation::{AsPermutation, Permutation};
 pub use value::{Value, ContextExt, Context, wrap, unwrap, default_context };
+pub use value::{DropTracker, Leak, Linearity};
+pub use value::{
+    register_affine, register_copyable, register_droppable, register_linear, register_unrestricted,
+};
 
 /// Errors may occurs when working with values
 #[derive(Fail, Debug)]
@@ -43,6 +47,12 @@ pub enum ValueAccessError {
         expect, actual
     )]
     UnexpectedArgs { expect: u8, actual: u8 },
+
+    #[fail(display = "A value of type {} cannot be copied", _0)]
+    NotCopyable(&'static str),
+
+    #[fail(display = "A value of type {} cannot be dropped", _0)]
+    NotDroppable(&'static str),
 }
 
 #[cfg(test)]
@@ -81,6 +81,8 @@ impl AsPermutation for &Permutation {
     }
 }
 impl Permutation {
+    /// The maximum number of positions a permutation can perform on
+    pub const MAX_LEN: u8 = 20;
     /// Perform the permutation on a given set of values.
     /// 
     /// values: the values to permutate
@@ -36,6 +36,9 @@ impl Context for ContextImpl {
     fn len(&self) -> u8 {
         self.0.len() as u8
     }
+    fn get(&self, index: u8) -> Option<&dyn Value> {
+        self.0.get(index as usize).map(|v| &**v)
+    }
     /// Perform a permutation over the values.
     ///
     /// p: the permutation to perform.
@@ -83,4 +86,14 @@ mod test {
         assert_eq!(10i32, unwrap::<i32>(c.pop().unwrap()).unwrap());
         assert!(c.is_empty());
     }
+
+    #[test]
+    fn test_get() {
+        let mut c = ContextImpl(vec![]);
+        c.push(wrap(10i32));
+        c.push(wrap("s".to_string()));
+        assert_eq!("i32", c.get(0).unwrap().type_name());
+        assert_eq!("alloc::string::String", c.get(1).unwrap().type_name());
+        assert!(c.get(2).is_none());
+    }
 }
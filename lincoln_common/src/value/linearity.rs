@@ -0,0 +1,164 @@
+use super::Value;
+use core::cell::{Cell, RefCell};
+use core::fmt::{Display, Formatter};
+
+/// How a value may be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linearity {
+    /// Must be used exactly once
+    Linear,
+    /// May be dropped without being used, but not copied
+    Affine,
+    /// May be copied, and dropped without being used
+    Unrestricted,
+}
+impl Display for Linearity {
+    fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+        match self {
+            Linearity::Linear => write!(fmt, "linear"),
+            Linearity::Affine => write!(fmt, "affine"),
+            Linearity::Unrestricted => write!(fmt, "unrestricted"),
+        }
+    }
+}
+
+/// A linear value that disappeared without being used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leak {
+    pub type_name: &'static str,
+    pub value: String,
+}
+impl Display for Leak {
+    fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+        write!(fmt, "{} of type {}", self.value, self.type_name)
+    }
+}
+impl Leak {
+    /// Describe a value that disappeared
+    pub fn of(value: &dyn Value) -> Self {
+        Leak {
+            type_name: value.type_name(),
+            value: format!("{}", value),
+        }
+    }
+}
+
+thread_local! {
+    static NEXT_TRACKER: Cell<usize> = const { Cell::new(0) };
+    /// The drops recorded for each tracker alive, by tracker
+    static TRACKERS: RefCell<Vec<(usize, Vec<Leak>)>> = const { RefCell::new(vec![]) };
+}
+
+/// Records the linear values dropped on this thread while it is alive.
+///
+/// Trackers can be nested: each records the drops during its own lifetime.
+///
+pub struct DropTracker {
+    id: usize,
+}
+impl DropTracker {
+    /// Start recording
+    pub fn start() -> Self {
+        let id = NEXT_TRACKER.with(|next| {
+            let id = next.get();
+            next.set(id + 1);
+            id
+        });
+        TRACKERS.with(|trackers| trackers.borrow_mut().push((id, vec![])));
+        DropTracker { id }
+    }
+    /// Take the linear values dropped since the start or the last call
+    pub fn take_dropped(&self) -> Vec<Leak> {
+        TRACKERS
+            .try_with(|trackers| {
+                trackers
+                    .borrow_mut()
+                    .iter_mut()
+                    .find(|(id, _)| *id == self.id)
+                    .map(|(_, dropped)| core::mem::take(dropped))
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default()
+    }
+}
+impl Drop for DropTracker {
+    fn drop(&mut self) {
+        let _ =
+            TRACKERS.try_with(|trackers| trackers.borrow_mut().retain(|(id, _)| *id != self.id));
+    }
+}
+
+pub(super) fn is_tracking() -> bool {
+    TRACKERS
+        .try_with(|trackers| !trackers.borrow().is_empty())
+        .unwrap_or(false)
+}
+pub(super) fn record_drop(leak: Leak) {
+    let _ = TRACKERS.try_with(|trackers| {
+        for (_, dropped) in trackers.borrow_mut().iter_mut() {
+            dropped.push(leak.clone());
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DropTracker, Linearity};
+    use crate::value::{register_linear, wrap, Value};
+    use core::fmt::{Display, Formatter};
+
+    #[derive(Debug)]
+    struct Ticket(u32);
+    impl Display for Ticket {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "#{}", self.0)
+        }
+    }
+    #[derive(Debug)]
+    struct Coin(bool);
+    impl Display for Coin {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "coin")
+        }
+    }
+    impl Value for Coin {
+        fn take(&mut self) -> Box<dyn Value> {
+            Box::new(Coin(self.0))
+        }
+        fn copy_value(&self) -> Option<Box<dyn Value>> {
+            if self.0 {
+                Some(Box::new(Coin(true)))
+            } else {
+                None
+            }
+        }
+        fn is_droppable(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_nested_trackers() {
+        register_linear::<Ticket>();
+        let outer = DropTracker::start();
+        drop(wrap(Ticket(1)));
+        let inner = DropTracker::start();
+        drop(wrap(Ticket(2)));
+        let dropped: Vec<_> = inner.take_dropped().into_iter().map(|l| l.value).collect();
+        assert_eq!(dropped, vec!["|#2|"]);
+        drop(inner);
+        drop(wrap(Ticket(3)));
+        let dropped: Vec<_> = outer.take_dropped().into_iter().map(|l| l.value).collect();
+        assert_eq!(dropped, vec!["|#1|", "|#2|", "|#3|"]);
+        drop(outer);
+
+        drop(wrap(Ticket(4)));
+        assert!(DropTracker::start().take_dropped().is_empty());
+    }
+
+    #[test]
+    fn test_default_linearity() {
+        assert_eq!(Coin(true).linearity(), Linearity::Unrestricted);
+        assert_eq!(Coin(false).linearity(), Linearity::Affine);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{ValueAccessError, AnyDebugDisplay};
 
 mod context;
+mod linearity;
+mod registry;
 mod traits;
 mod wrapped;
 
+pub use linearity::{DropTracker, Leak, Linearity};
+pub use registry::{
+    register_affine, register_copyable, register_droppable, register_linear, register_unrestricted,
+};
 pub use traits::{Context, ContextExt, Value};
 
 use context::ContextImpl;
@@ -23,6 +29,7 @@ where
         .downcast::<Wrapped<T>>()
         .map_err(|_| ValueAccessError::UnwrapNotWrapped("not Wrapped type".into()))?
         .0
+        .take()
         .ok_or_else(|| ValueAccessError::UnwrapEmptyValue)
 }
 
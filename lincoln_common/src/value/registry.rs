@@ -0,0 +1,119 @@
+use super::Value;
+use crate::AnyDebugDisplay;
+use core::any::{Any, TypeId};
+use std::collections::BTreeMap;
+use std::sync::{PoisonError, RwLock};
+
+type CopyFn = fn(&dyn Any) -> Option<Box<dyn Value>>;
+
+/// What the runtime may do with the wrapped values of a host type
+#[derive(Clone, Copy, Default)]
+pub(super) struct TypeRules {
+    pub(super) copy: Option<CopyFn>,
+    pub(super) drop: bool,
+}
+
+static TYPES: RwLock<BTreeMap<TypeId, TypeRules>> = RwLock::new(BTreeMap::new());
+
+// Rules are only inserted or replaced while the lock is held,
+// so the map is still valid if a panic poisoned the lock.
+fn update<T: Any>(f: impl FnOnce(&mut TypeRules)) {
+    let mut types = TYPES.write().unwrap_or_else(PoisonError::into_inner);
+    f(types.entry(TypeId::of::<T>()).or_default())
+}
+fn copy_as<T>(value: &dyn Any) -> Option<Box<dyn Value>>
+where
+    T: AnyDebugDisplay + Clone,
+{
+    value.downcast_ref::<T>().cloned().map(super::wrap)
+}
+
+/// The rules registered for a host type
+pub(super) fn rules_of<T: Any>() -> TypeRules {
+    TYPES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&TypeId::of::<T>())
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Allow the wrapped values of a host type to be copied, by cloning them
+pub fn register_copyable<T>()
+where
+    T: AnyDebugDisplay + Clone,
+{
+    update::<T>(|rules| rules.copy = Some(copy_as::<T>))
+}
+/// Allow the wrapped values of a host type to be dropped without being used
+pub fn register_droppable<T>()
+where
+    T: AnyDebugDisplay,
+{
+    update::<T>(|rules| rules.drop = true)
+}
+/// Declare a host type linear: its wrapped values must be used exactly once
+pub fn register_linear<T>()
+where
+    T: AnyDebugDisplay,
+{
+    update::<T>(|rules| *rules = TypeRules::default())
+}
+/// Declare a host type affine: its wrapped values may be dropped, but not copied
+pub fn register_affine<T>()
+where
+    T: AnyDebugDisplay,
+{
+    update::<T>(|rules| {
+        rules.copy = None;
+        rules.drop = true;
+    })
+}
+/// Declare a host type unrestricted: its wrapped values may be copied and dropped
+pub fn register_unrestricted<T>()
+where
+    T: AnyDebugDisplay + Clone,
+{
+    register_copyable::<T>();
+    register_droppable::<T>();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{register_copyable, register_droppable};
+    use crate::value::{unwrap, wrap};
+    use core::fmt::{Display, Formatter};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Handle(u32);
+    impl Display for Handle {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "#{}", self.0)
+        }
+    }
+    #[derive(Debug)]
+    struct Token;
+    impl Display for Token {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "token")
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let v = wrap(Handle(1));
+        assert!(v.copy_value().is_none());
+        assert!(!v.is_droppable());
+
+        register_copyable::<Handle>();
+        register_droppable::<Handle>();
+        let copy = v.copy_value().expect("copyable");
+        assert_eq!(unwrap::<Handle>(copy).unwrap(), Handle(1));
+        assert_eq!(unwrap::<Handle>(v).unwrap(), Handle(1));
+        assert!(wrap(Handle(2)).is_droppable());
+
+        register_droppable::<Token>();
+        assert!(wrap(Token).copy_value().is_none());
+        assert!(wrap(Token).is_droppable());
+    }
+}
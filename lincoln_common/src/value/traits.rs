@@ -1,5 +1,6 @@
 use crate::{ValueAccessError, AnyDebugDisplay};
 use crate::permutation::Permutation;
+use super::linearity::Linearity;
 use core::fmt::Display;
 use core::iter::once;
 
@@ -11,6 +12,33 @@ pub trait Acceptor<Value> {
 
 pub trait Value: AnyDebugDisplay {
     fn take(&mut self) -> Box<dyn Value>;
+    /// The name of the type this value carries.
+    /// For wrapped values this is the name of the wrapped type.
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<Self>()
+    }
+    /// Copy the value, if it can be copied.
+    /// Values are linear unless their type says otherwise.
+    fn copy_value(&self) -> Option<Box<dyn Value>> {
+        None
+    }
+    /// Whether the value can be dropped without being used
+    fn is_droppable(&self) -> bool {
+        false
+    }
+    /// How the value may be used, derived from `is_droppable` and
+    /// `copy_value`. A value that cannot be dropped is linear, even if
+    /// it can be copied. This makes a copy to find out, so values with
+    /// costly or observable copies should override it.
+    fn linearity(&self) -> Linearity {
+        if !self.is_droppable() {
+            Linearity::Linear
+        } else if self.copy_value().is_some() {
+            Linearity::Unrestricted
+        } else {
+            Linearity::Affine
+        }
+    }
 }
 pub trait Context: Display {
     fn empty_value(&self) -> Box<dyn Value>;
@@ -19,6 +47,12 @@ pub trait Context: Display {
     fn take_after(&mut self, at: u8, values_accepter: &mut dyn FnMut(&mut dyn Value)) -> u8;
     fn extend(&'_ mut self, values: &mut dyn Iterator<Item = &mut dyn Value>);
     fn len(&self) -> u8;
+    /// Access the value at a position without taking it.
+    /// Contexts that do not support it return `None`, which hides
+    /// their values from reports and checks.
+    fn get(&self, _index: u8) -> Option<&dyn Value> {
+        None
+    }
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -71,6 +105,26 @@ pub trait ContextExt: Context {
             Ok(())
         }
     }
+    /// Copy all values of the context
+    ///
+    /// returns: the copy, or the position of the first value that cannot be copied
+    ///
+    fn copy_values(&self) -> Result<Box<dyn Context>, u8> {
+        let mut values = vec![];
+        for i in 0..self.len() {
+            match self.get(i).and_then(|v| v.copy_value()) {
+                Some(v) => values.push(v),
+                None => return Err(i),
+            }
+        }
+        let mut result = self.create_empty();
+        result.extend(&mut values.iter_mut().map(|x| &mut **x));
+        Ok(result)
+    }
+    /// The position of the first value that cannot be dropped
+    fn find_undroppable(&self) -> Option<u8> {
+        (0..self.len()).find(|i| !self.get(*i).map(|v| v.is_droppable()).unwrap_or(false))
+    }
     /// Merge two context into one. The second context put last.
     ///
     /// other: the other context to merge
@@ -1,11 +1,13 @@
+use super::linearity::{is_tracking, record_drop, Leak, Linearity};
+use super::registry::rules_of;
 use super::Value;
 use core::fmt::{Debug, Display, Formatter};
 use crate::AnyDebugDisplay;
 
-pub(super) struct Wrapped<T>(pub(super) Option<T>);
+pub(super) struct Wrapped<T: AnyDebugDisplay>(pub(super) Option<T>);
 impl<T> Debug for Wrapped<T>
 where
-    T: Debug,
+    T: AnyDebugDisplay,
 {
     fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
         write!(fmt, "|{:?}|", self.0)
@@ -13,7 +15,7 @@ where
 }
 impl<T> Display for Wrapped<T>
 where
-    T: Display,
+    T: AnyDebugDisplay,
 {
     fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
         match &self.0 {
@@ -29,9 +31,42 @@ where
     fn take(&mut self) -> Box<dyn Value> {
         Box::new(Wrapped(self.0.take()))
     }
+    fn type_name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+    fn copy_value(&self) -> Option<Box<dyn Value>> {
+        let copy = rules_of::<T>().copy?;
+        copy(self.0.as_ref()?.as_any())
+    }
+    fn is_droppable(&self) -> bool {
+        rules_of::<T>().drop
+    }
+    fn linearity(&self) -> Linearity {
+        let rules = rules_of::<T>();
+        match (rules.copy, rules.drop) {
+            (Some(_), true) => Linearity::Unrestricted,
+            (_, true) => Linearity::Affine,
+            (_, false) => Linearity::Linear,
+        }
+    }
 }
-impl<T> Default for Wrapped<T> {
+impl<T> Default for Wrapped<T>
+where
+    T: AnyDebugDisplay,
+{
     fn default() -> Self {
         Wrapped(None)
     }
 }
+/// A value still wrapped when dropped was not used,
+/// which is reported if drops are tracked and the value is linear
+impl<T> Drop for Wrapped<T>
+where
+    T: AnyDebugDisplay,
+{
+    fn drop(&mut self) {
+        if self.0.is_some() && is_tracking() && self.linearity() == Linearity::Linear {
+            record_drop(Leak::of(self));
+        }
+    }
+}
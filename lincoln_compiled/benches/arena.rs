@@ -0,0 +1,98 @@
+#[macro_use]
+extern crate criterion;
+
+use criterion::{black_box, Criterion};
+use lincoln_common::{default_context, unwrap, wrap, ContextExt, Permutation};
+use lincoln_compiled::{ArenaProgram, CodeRef, EvalFn, ExternEntry, Program, ProgramArena};
+
+/// The number of jumps between two calls of the counter
+const JUMPS: usize = 32;
+/// The number of times the counter runs
+const ROUNDS: u32 = 1000;
+
+/// Pops a counter; terminates at zero, otherwise decrements it
+/// and continues to the call entry
+fn counter() -> ExternEntry {
+    ExternEntry::Eval {
+        name: "count".into(),
+        eval: EvalFn::stateless(|ctx| {
+            let n = unwrap::<u32>(ctx.pop()?)?;
+            if n == 0 {
+                return Ok(CodeRef::Termination);
+            }
+            ctx.push(wrap(n - 1));
+            Ok(CodeRef::entry(JUMPS + 1))
+        }),
+    }
+}
+
+fn build_program() -> Program {
+    let mut prog = Program::new();
+    let count = prog.add_extern(counter());
+    let ret = prog.add_return(0);
+    let mut next = count;
+    for _ in 0..JUMPS {
+        next = prog.add_jump(next, Permutation(0));
+    }
+    let g = prog.add_empty_group();
+    prog.add_group_entry(g, next).unwrap();
+    let call = prog.add_call(ret, 1, g);
+    let main = prog.add_empty_group();
+    prog.add_group_entry(main, call).unwrap();
+    prog.add_export("main", main);
+    prog
+}
+
+fn build_arena_program<'a>(arena: &'a ProgramArena) -> ArenaProgram<'a> {
+    let mut prog = ArenaProgram::new(arena);
+    let count = prog.add_extern(counter());
+    let ret = prog.add_return(0);
+    let mut next = count;
+    for _ in 0..JUMPS {
+        next = prog.add_jump(next, Permutation(0));
+    }
+    let g = prog.add_empty_group();
+    prog.add_group_entry(g, next).unwrap();
+    let call = prog.add_call(ret, 1, g);
+    let main = prog.add_empty_group();
+    prog.add_group_entry(main, call).unwrap();
+    prog.add_export("main", main);
+    prog
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    let _ = group.bench_function("program", |b| b.iter(|| black_box(build_program())));
+    let _ = group.bench_function("arena", |b| {
+        b.iter(|| {
+            let arena = ProgramArena::new();
+            let _ = black_box(build_arena_program(&arena));
+        })
+    });
+    group.finish();
+}
+
+fn bench_run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    let prog = build_program();
+    let _ = group.bench_function("program", |b| {
+        b.iter(|| {
+            let mut ctx = default_context();
+            ctx.push(wrap(ROUNDS));
+            prog.run(&mut *ctx, "main", 0, None).unwrap();
+        })
+    });
+    let arena = ProgramArena::new();
+    let prog = build_arena_program(&arena);
+    let _ = group.bench_function("arena", |b| {
+        b.iter(|| {
+            let mut ctx = default_context();
+            ctx.push(wrap(ROUNDS));
+            prog.run(&mut *ctx, "main", 0, None).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_build, bench_run);
+criterion_main!(benches);
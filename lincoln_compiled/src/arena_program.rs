@@ -1,18 +1,300 @@
+use crate::entries::{CodeGroup, Entry, ExportEntry, ExternEntry};
+use crate::error::{BuildError, EvalError};
+use crate::labels::Labels;
+use crate::observer::{EvalObserver, EventSink};
+use crate::program::{eval_step, run};
+use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
+use crate::store::{export_entry, CodeStore};
+use failure::Error;
+use lincoln_common::{Context, Permutation, StringLike};
 use typed_arena::Arena;
 
-struct RetEntry {
-    u8: variation
+/// The memory the code of `ArenaProgram`s is allocated in.
+/// It must outlive the programs.
+///
+#[derive(Default)]
+pub struct ProgramArena {
+    entries: Arena<Entry>,
+    externs: Arena<ExternEntry>,
+    groups: Arena<CodeGroup>,
 }
-struct ExternEntry {
-    
+impl ProgramArena {
+    pub fn new() -> Self {
+        Default::default()
+    }
 }
 
-struct ProgramArena {
-    entries: Arena<ArenaEntry>,
-    groups: Arena<Group>,
-    externs: Arena<string>
+/// A compiled program whose entries, groups and externs live in a `ProgramArena`.
+///
+/// It is built the same way as a `Program`. Code references are resolved
+/// through tables of direct references into the arena, and it is a
+/// `CodeStore`, so it runs on the same evaluator as `Program` and works with
+/// `Machine`, the observers and the debugger.
+///
+pub struct ArenaProgram<'a> {
+    arena: &'a ProgramArena,
+    entries: Vec<&'a Entry>,
+    externs: Vec<&'a ExternEntry>,
+    groups: Vec<&'a mut CodeGroup>,
+    exports: Vec<ExportEntry>,
+    labels: Labels,
+}
+impl<'a> ArenaProgram<'a> {
+    /// Create a new empty program
+    ///
+    /// arena: the arena to allocate the code in
+    ///
+    pub fn new(arena: &'a ProgramArena) -> Self {
+        ArenaProgram {
+            arena,
+            entries: vec![],
+            externs: vec![],
+            groups: vec![],
+            exports: vec![],
+            labels: Labels::new(),
+        }
+    }
+    /// The debug information of the program, naming its entries and groups
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+    /// Replace the debug information of the program
+    ///
+    /// labels: the names of the entries and groups
+    ///
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = labels;
+    }
+    /// Add a new external entry
+    pub fn add_extern(&mut self, ent: ExternEntry) -> CodeRef {
+        let pos = ExternRef::new_coderef(self.externs.len());
+        self.externs.push(self.arena.externs.alloc(ent));
+        pos
+    }
+    fn add_entry(&mut self, ent: Entry) -> CodeRef {
+        let pos = EntryRef::new_coderef(self.entries.len());
+        self.entries.push(self.arena.entries.alloc(ent));
+        pos
+    }
+    /// Add a return instruction
+    ///
+    /// variant: the variant to call on return
+    ///
+    pub fn add_return(&mut self, variant: u8) -> CodeRef {
+        self.add_entry(Entry::Return { variant })
+    }
+    /// Add a jump instruction
+    ///
+    /// cont: the next instruction to jump to
+    /// per: the permutation to be performed before the jump
+    ///
+    pub fn add_jump(&mut self, cont: CodeRef, per: Permutation) -> CodeRef {
+        self.add_entry(Entry::Jump { cont, per })
+    }
+    /// Add a call instruction
+    ///
+    /// call: the instruction to call
+    /// num_args: the number of values in the context to keep
+    /// cont: the instruction group to receive the result
+    ///
+    pub fn add_call(&mut self, call: CodeRef, num_args: u8, cont: GroupRef) -> CodeRef {
+        self.add_entry(Entry::Call {
+            call,
+            cont,
+            num_args,
+        })
+    }
+    /// Set a entry group to be exported as a name
+    ///
+    /// name: the name of the exported entry
+    /// g: the entry group to be exported
+    ///
+    pub fn add_export(&mut self, name: impl StringLike, g: GroupRef) {
+        self.exports.push(ExportEntry {
+            name: name.to_string(),
+            g,
+        })
+    }
+    /// Create a new empty group and return its reference.
+    ///
+    pub fn add_empty_group(&mut self) -> GroupRef {
+        let pos = GroupRef::new(self.groups.len());
+        self.groups.push(self.arena.groups.alloc(smallvec![]));
+        pos
+    }
+    /// Add a new entry to an existing group
+    ///
+    /// grp: refers to the existing group
+    /// ent: the new entry
+    ///
+    pub fn add_group_entry(&mut self, grp: GroupRef, ent: CodeRef) -> Result<(), BuildError> {
+        match self.groups.get_mut(grp.get_index()) {
+            Some(g) => {
+                g.push(ent);
+                Ok(())
+            }
+            None => Err(BuildError::GroupNotFound(grp)),
+        }
+    }
+    /// Find an export by name, and receive an entry from its variants
+    ///
+    /// export_label: the name of the export
+    /// variant: the variant to return
+    ///
+    pub fn get_export_ent(
+        &self,
+        export_label: impl StringLike,
+        variant: u8,
+    ) -> Result<CodeRef, Error> {
+        export_entry(self, export_label.as_str(), variant)
+    }
+    /// Find an export but only returns an entry group
+    ///
+    /// export_label: the name of the export
+    ///
+    pub fn get_export(&self, export_label: impl StringLike) -> Result<GroupRef, Error> {
+        match self.export(export_label.as_str()) {
+            Some(g) => Ok(g),
+            None => bail!("Export label not found or invalid"),
+        }
+    }
+    /// Run the program
+    ///
+    /// ctx: the values given to run
+    /// export_label: the name of the exported entry to be run into
+    /// variant: the variant of the exported entry to run
+    /// rounds: None if run to the end is required, otherwise the maximum steps to tun
+    ///
+    pub fn run(
+        &self,
+        ctx: &mut dyn Context,
+        export_label: impl StringLike,
+        variant: u8,
+        rounds: Option<usize>,
+    ) -> Result<(), Error> {
+        run(self, ctx, export_label.as_str(), variant, rounds)
+    }
+    /// Evaluate the program for one step only
+    ///
+    /// ctx: the values given to evaluate
+    /// ent: the current code entry
+    ///
+    /// returns: the next code entry, or an error
+    pub fn eval(&self, ctx: &mut dyn Context, ent: &CodeRef) -> Result<CodeRef, EvalError> {
+        eval_step(self, ctx, ent, &mut (), &EventSink::none())
+    }
+    /// Evaluate the program for one step, reporting to an observer
+    ///
+    /// ctx: the values given to evaluate
+    /// ent: the current code entry
+    /// observer: receives the events of this step
+    ///
+    /// returns: the next code entry, or an error
+    pub fn eval_observed(
+        &self,
+        ctx: &mut dyn Context,
+        ent: &CodeRef,
+        observer: &mut dyn EvalObserver,
+    ) -> Result<CodeRef, EvalError> {
+        eval_step(self, ctx, ent, observer, &EventSink::new())
+    }
+}
+impl<'a> CodeStore for ArenaProgram<'a> {
+    fn entry(&self, at: EntryRef) -> Option<&Entry> {
+        self.entries.get(at.0).copied()
+    }
+    fn extern_entry(&self, at: ExternRef) -> Option<&ExternEntry> {
+        self.externs.get(at.0).copied()
+    }
+    fn group(&self, at: GroupRef) -> Option<&[CodeRef]> {
+        self.groups.get(at.get_index()).map(|g| &g[..])
+    }
+    fn export(&self, name: &str) -> Option<GroupRef> {
+        self.exports.iter().find(|e| e.name == name).map(|e| e.g)
+    }
+    fn labels(&self) -> &Labels {
+        &self.labels
+    }
 }
 
-impl ProgramArena {
-    pub fn add_extern(&mut self, ent:)
-}
\ No newline at end of file
+#[cfg(test)]
+mod test {
+    use super::{ArenaProgram, ProgramArena};
+    use crate::{CodeRef, EvalFn, ExternEntry, Machine, MachineState, Profiler, ValueFn};
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt, Permutation};
+
+    #[test]
+    fn test_arena_program() {
+        let arena = ProgramArena::new();
+        let mut prog = ArenaProgram::new(&arena);
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let swap = prog.add_jump(done, Permutation(1));
+        let ret = prog.add_return(0);
+        let g = prog.add_empty_group();
+        let call = prog.add_call(ret, 2, g);
+        prog.add_group_entry(g, swap).unwrap();
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, call).unwrap();
+        prog.add_export("main", main);
+        assert_eq!(prog.get_export_ent("main", 0).unwrap(), call);
+        assert_eq!(call, CodeRef::entry(2));
+
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        let mut ent = prog.eval(&mut *ctx, &call).unwrap();
+        assert_eq!(ent, ret);
+        ent = prog.eval(&mut *ctx, &ent).unwrap();
+        assert_eq!(ent, swap);
+        ent = prog.eval(&mut *ctx, &ent).unwrap();
+        assert_eq!(ent, done);
+        ent = prog.eval(&mut *ctx, &ent).unwrap();
+        assert_eq!(ent, CodeRef::Termination);
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        prog.run(&mut *ctx, "main", 0, None).unwrap();
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+        assert!(prog.eval(&mut *ctx, &CodeRef::entry(3)).is_err());
+        assert!(prog.add_group_entry(crate::GroupRef::new(2), ret).is_err());
+
+        // Machines and observers work the same as with `Program`
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        let mut profiler = Profiler::new();
+        let mut machine = Machine::start(&prog, ctx, "main", 0).unwrap();
+        match machine.run_observed(&mut profiler) {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        let report = profiler.report(&prog);
+        assert_eq!(report.steps, 4);
+        assert_eq!(report.closures_created, 1);
+    }
+
+    #[test]
+    fn test_value_extern() {
+        let arena = ProgramArena::new();
+        let mut prog = ArenaProgram::new(&arena);
+        let one = prog.add_extern(ExternEntry::Value {
+            name: "one".into(),
+            value: ValueFn::stateless(|| wrap(1i32)),
+        });
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, one).unwrap();
+        let call = prog.add_call(done, 0, g);
+        let mut ctx = default_context();
+        assert_eq!(prog.eval(&mut *ctx, &call).unwrap(), done);
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+    }
+}
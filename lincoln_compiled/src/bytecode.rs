@@ -0,0 +1,563 @@
+//! A compact binary encoding of compiled programs.
+//!
+//! The layout is
+//!
+//! ```text
+//! magic    b"LNBC"
+//! version  u16, little endian
+//! checksum u32, little endian, Adler-32 of the body
+//! body     externs, entries, groups, exports, debug
+//! ```
+//!
+//! Counts, indexes and permutations in the body are unsigned LEB128 numbers,
+//! strings are a length followed by UTF-8 bytes.
+//! The debug section names entries, groups, variants and returns
+//! by their IR labels.
+//!
+use crate::entries::{CodeGroup, Entry, ExportEntry};
+use crate::error::BytecodeError;
+use crate::image::{ExternDecl, ProgramImage};
+use crate::labels::Labels;
+use crate::program::Program;
+use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
+use lincoln_common::Permutation;
+
+const MAGIC: &[u8; 4] = b"LNBC";
+/// The current version of the bytecode format
+pub const BYTECODE_VERSION: u16 = 1;
+const HEADER_LEN: usize = 10;
+
+const EXTERN_EVAL: u8 = 0;
+const EXTERN_VALUE: u8 = 1;
+const ENTRY_JUMP: u8 = 0;
+const ENTRY_CALL: u8 = 1;
+const ENTRY_RETURN: u8 = 2;
+const CODE_ENTRY: u8 = 0;
+const CODE_EXTERN: u8 = 1;
+const CODE_TERMINATION: u8 = 2;
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for byte in chunk {
+            a += u32::from(*byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+struct Encoder(Vec<u8>);
+impl Encoder {
+    fn byte(&mut self, b: u8) {
+        self.0.push(b);
+    }
+    fn number(&mut self, mut n: u64) {
+        loop {
+            let b = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.0.push(b);
+                return;
+            }
+            self.0.push(b | 0x80);
+        }
+    }
+    fn string(&mut self, s: &str) {
+        self.number(s.len() as u64);
+        self.0.extend_from_slice(s.as_bytes());
+    }
+    fn code(&mut self, c: CodeRef) {
+        match c {
+            CodeRef::Entry(EntryRef(i)) => {
+                self.byte(CODE_ENTRY);
+                self.number(i as u64);
+            }
+            CodeRef::Extern(ExternRef(i)) => {
+                self.byte(CODE_EXTERN);
+                self.number(i as u64);
+            }
+            CodeRef::Termination => self.byte(CODE_TERMINATION),
+        }
+    }
+    fn program(
+        &mut self,
+        entries: &[Entry],
+        externs: impl ExactSizeIterator<Item = ExternDecl>,
+        groups: &[CodeGroup],
+        exports: &[ExportEntry],
+        labels: &Labels,
+    ) {
+        self.number(externs.len() as u64);
+        for ext in externs {
+            match ext {
+                ExternDecl::Eval { name } => {
+                    self.byte(EXTERN_EVAL);
+                    self.string(&name);
+                }
+                ExternDecl::Value { name } => {
+                    self.byte(EXTERN_VALUE);
+                    self.string(&name);
+                }
+            }
+        }
+        self.number(entries.len() as u64);
+        for entry in entries {
+            match entry {
+                Entry::Jump { cont, per } => {
+                    self.byte(ENTRY_JUMP);
+                    self.code(*cont);
+                    self.number(per.0);
+                }
+                Entry::Call {
+                    call,
+                    cont,
+                    num_args,
+                } => {
+                    self.byte(ENTRY_CALL);
+                    self.code(*call);
+                    self.number(cont.get_index() as u64);
+                    self.byte(*num_args);
+                }
+                Entry::Return { variant } => {
+                    self.byte(ENTRY_RETURN);
+                    self.byte(*variant);
+                }
+            }
+        }
+        self.number(groups.len() as u64);
+        for group in groups {
+            self.number(group.len() as u64);
+            for c in group {
+                self.code(*c);
+            }
+        }
+        self.number(exports.len() as u64);
+        for export in exports {
+            self.string(&export.name);
+            self.number(export.g.get_index() as u64);
+        }
+        self.number(labels.iterate_entries().count() as u64);
+        for (EntryRef(i), label) in labels.iterate_entries() {
+            self.number(i as u64);
+            self.string(label);
+        }
+        self.number(labels.iterate_groups().count() as u64);
+        for (g, label) in labels.iterate_groups() {
+            self.number(g.get_index() as u64);
+            self.string(label);
+        }
+        self.number(labels.iterate_variant_names().count() as u64);
+        for (g, names) in labels.iterate_variant_names() {
+            self.number(g.get_index() as u64);
+            self.number(names.len() as u64);
+            for name in names {
+                self.string(name.as_deref().unwrap_or(""));
+            }
+        }
+        self.number(labels.iterate_return_names().count() as u64);
+        for (EntryRef(i), name) in labels.iterate_return_names() {
+            self.number(i as u64);
+            self.string(name);
+        }
+    }
+    fn finish(self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(HEADER_LEN + self.0.len());
+        r.extend_from_slice(MAGIC);
+        r.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+        r.extend_from_slice(&adler32(&self.0).to_le_bytes());
+        r.extend_from_slice(&self.0);
+        r
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let b = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        self.offset += 1;
+        Ok(b)
+    }
+    fn number(&mut self) -> Result<u64, BytecodeError> {
+        let start = self.offset;
+        let mut n = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            let bits = u64::from(b & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(BytecodeError::NumberOverflow(start));
+            }
+            n |= bits << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+        }
+        Err(BytecodeError::NumberOverflow(start))
+    }
+    fn index(&mut self) -> Result<usize, BytecodeError> {
+        let start = self.offset;
+        let n = self.number()?;
+        if n > usize::MAX as u64 {
+            Err(BytecodeError::NumberOverflow(start))
+        } else {
+            Ok(n as usize)
+        }
+    }
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.index()?;
+        let start = self.offset;
+        let end = start
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        self.offset = end;
+        String::from_utf8(self.bytes[start..end].to_vec())
+            .map_err(|_| BytecodeError::InvalidString(start))
+    }
+    fn tag(&mut self, what: &'static str, max: u8) -> Result<u8, BytecodeError> {
+        let offset = self.offset;
+        let tag = self.byte()?;
+        if tag > max {
+            Err(BytecodeError::InvalidTag { what, tag, offset })
+        } else {
+            Ok(tag)
+        }
+    }
+    fn permutation(&mut self) -> Result<Permutation, BytecodeError> {
+        let offset = self.offset;
+        let value = self.number()?;
+        if Permutation(value).min_len() > Permutation::MAX_LEN {
+            Err(BytecodeError::InvalidPermutation { value, offset })
+        } else {
+            Ok(Permutation(value))
+        }
+    }
+    fn code(&mut self) -> Result<CodeRef, BytecodeError> {
+        Ok(match self.tag("code reference", CODE_TERMINATION)? {
+            CODE_ENTRY => CodeRef::Entry(EntryRef(self.index()?)),
+            CODE_EXTERN => CodeRef::Extern(ExternRef(self.index()?)),
+            _ => CodeRef::Termination,
+        })
+    }
+    fn program(&mut self) -> Result<ProgramImage, BytecodeError> {
+        let mut externs = vec![];
+        for _ in 0..self.index()? {
+            let kind = self.tag("extern", EXTERN_VALUE)?;
+            let name = self.string()?;
+            externs.push(match kind {
+                EXTERN_EVAL => ExternDecl::Eval { name },
+                _ => ExternDecl::Value { name },
+            });
+        }
+        let mut entries = vec![];
+        for _ in 0..self.index()? {
+            entries.push(match self.tag("entry", ENTRY_RETURN)? {
+                ENTRY_JUMP => Entry::Jump {
+                    cont: self.code()?,
+                    per: self.permutation()?,
+                },
+                ENTRY_CALL => Entry::Call {
+                    call: self.code()?,
+                    cont: GroupRef::new(self.index()?),
+                    num_args: self.byte()?,
+                },
+                _ => Entry::Return {
+                    variant: self.byte()?,
+                },
+            });
+        }
+        let mut groups = vec![];
+        for _ in 0..self.index()? {
+            let mut group = CodeGroup::new();
+            for _ in 0..self.index()? {
+                group.push(self.code()?);
+            }
+            groups.push(group);
+        }
+        let mut exports = vec![];
+        for _ in 0..self.index()? {
+            exports.push(ExportEntry {
+                name: self.string()?,
+                g: GroupRef::new(self.index()?),
+            });
+        }
+        let mut labels = Labels::new();
+        for _ in 0..self.index()? {
+            labels.set_entry(EntryRef(self.index()?), self.string()?);
+        }
+        for _ in 0..self.index()? {
+            labels.set_group(GroupRef::new(self.index()?), self.string()?);
+        }
+        for _ in 0..self.index()? {
+            let g = GroupRef::new(self.index()?);
+            let mut names = vec![];
+            for _ in 0..self.index()? {
+                names.push(Some(self.string()?).filter(|n| !n.is_empty()));
+            }
+            labels.set_variant_names(g, names);
+        }
+        for _ in 0..self.index()? {
+            labels.set_return_name(EntryRef(self.index()?), self.string()?);
+        }
+        if self.offset != self.bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+        Ok(ProgramImage {
+            entries,
+            externs,
+            exports,
+            groups,
+            labels,
+        })
+    }
+}
+
+fn check_index(what: &'static str, index: usize, len: usize) -> Result<(), BytecodeError> {
+    if index < len {
+        Ok(())
+    } else {
+        Err(BytecodeError::IndexOutOfRange { what, index, len })
+    }
+}
+fn check_code(image: &ProgramImage, c: CodeRef) -> Result<(), BytecodeError> {
+    match c {
+        CodeRef::Entry(EntryRef(i)) => check_index("entry", i, image.entries.len()),
+        CodeRef::Extern(ExternRef(i)) => check_index("extern", i, image.externs.len()),
+        CodeRef::Termination => Ok(()),
+    }
+}
+fn check_references(image: &ProgramImage) -> Result<(), BytecodeError> {
+    let groups = image.groups.len();
+    for entry in image.entries.iter() {
+        match entry {
+            Entry::Jump { cont, .. } => check_code(image, *cont)?,
+            Entry::Call { call, cont, .. } => {
+                check_code(image, *call)?;
+                check_index("group", cont.get_index(), groups)?;
+            }
+            Entry::Return { .. } => (),
+        }
+    }
+    for c in image.groups.iter().flat_map(|g| g.iter()) {
+        check_code(image, *c)?;
+    }
+    for export in image.exports.iter() {
+        check_index("group", export.g.get_index(), groups)?;
+    }
+    for (EntryRef(i), _) in image.labels.iterate_entries() {
+        check_index("entry label", i, image.entries.len())?;
+    }
+    for (g, _) in image.labels.iterate_groups() {
+        check_index("group label", g.get_index(), groups)?;
+    }
+    for (g, _) in image.labels.iterate_variant_names() {
+        check_index("variant names", g.get_index(), groups)?;
+    }
+    for (EntryRef(i), _) in image.labels.iterate_return_names() {
+        check_index("return name", i, image.entries.len())?;
+    }
+    Ok(())
+}
+
+impl Program {
+    /// Encode the program in the bytecode format
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut e = Encoder(vec![]);
+        e.program(
+            &self.entries,
+            self.externs.iter().map(ExternDecl::from),
+            &self.groups,
+            &self.exports,
+            &self.labels,
+        );
+        e.finish()
+    }
+}
+impl ProgramImage {
+    /// Encode the program in the bytecode format
+    pub fn to_bytecode(&self) -> Vec<u8> {
+        let mut e = Encoder(vec![]);
+        e.program(
+            &self.entries,
+            self.externs.iter().cloned(),
+            &self.groups,
+            &self.exports,
+            &self.labels,
+        );
+        e.finish()
+    }
+    /// Decode a program from the bytecode format. The header, the checksum
+    /// and all references between entries, externs and groups are checked.
+    ///
+    /// bytes: the encoded program
+    ///
+    pub fn from_bytecode(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(if bytes.starts_with(&MAGIC[..bytes.len().min(4)]) {
+                BytecodeError::UnexpectedEnd
+            } else {
+                BytecodeError::BadMagic
+            });
+        }
+        if &bytes[0..4] != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != BYTECODE_VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        let expect = u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]);
+        let actual = adler32(&bytes[HEADER_LEN..]);
+        if expect != actual {
+            return Err(BytecodeError::ChecksumMismatch { expect, actual });
+        }
+        let image = Decoder {
+            bytes,
+            offset: HEADER_LEN,
+        }
+        .program()?;
+        check_references(&image)?;
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{adler32, HEADER_LEN};
+    use crate::{
+        BytecodeError, CodeRef, EvalFn, ExternEntry, GroupRef, Labels, Program, ProgramImage,
+    };
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt, Permutation};
+
+    fn done() -> ExternEntry {
+        ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        }
+    }
+    fn program() -> Program {
+        let mut prog = Program::new();
+        let ext = prog.add_extern(done());
+        let j = prog.add_jump(ext, Permutation(1));
+        let ret = prog.add_return(0);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, j).unwrap();
+        let call = prog.add_call(ret, 2, g);
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, call).unwrap();
+        prog.add_export("main", main);
+        let mut labels = Labels::new();
+        if let (CodeRef::Entry(call), CodeRef::Entry(ret)) = (call, ret) {
+            labels.set_entry(call, "main");
+            labels.set_return_name(ret, "ok");
+        }
+        labels.set_group(g, "done_group");
+        labels.set_variant_names(g, vec![Some("ok".into())]);
+        prog.set_labels(labels);
+        prog
+    }
+    /// Replace the body of an encoded program and fix its checksum
+    fn with_body(bytes: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut r = bytes[..HEADER_LEN].to_vec();
+        r[6..10].copy_from_slice(&adler32(body).to_le_bytes());
+        r.extend_from_slice(body);
+        r
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = program().to_bytecode();
+        assert_eq!(&bytes[0..4], b"LNBC");
+        let image = ProgramImage::from_bytecode(&bytes).unwrap();
+        assert_eq!(image.to_bytecode(), bytes);
+        let prog = image.bind(vec![done()]).unwrap();
+        assert_eq!(format!("{:?}", prog), format!("{:?}", program()));
+
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        prog.run(&mut *ctx, "main", 0, None).unwrap();
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+        assert_eq!(prog.labels().variant(GroupRef::new(0), "ok"), Some(0));
+        assert!(bytes.len() < serde_json::to_vec(&program()).unwrap().len());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let bytes = program().to_bytecode();
+        match ProgramImage::from_bytecode(b"JSON{}....") {
+            Err(BytecodeError::BadMagic) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let mut v2 = bytes.clone();
+        v2[4] = 2;
+        match ProgramImage::from_bytecode(&v2) {
+            Err(BytecodeError::UnsupportedVersion(2)) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let mut corrupted = bytes.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        match ProgramImage::from_bytecode(&corrupted) {
+            Err(BytecodeError::ChecksumMismatch { .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match ProgramImage::from_bytecode(&bytes[..bytes.len() - 1]) {
+            Err(BytecodeError::ChecksumMismatch { .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        // No externs, a jump to entry 5, no groups, no exports, no labels
+        let body = [0, 1, 0, 0, 5, 1, 0, 0, 0, 0, 0, 0];
+        match ProgramImage::from_bytecode(&with_body(&bytes, &body)) {
+            Err(BytecodeError::IndexOutOfRange {
+                what: "entry",
+                index: 5,
+                len: 1,
+            }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        // No externs, a call to termination with group 0, no groups
+        let body = [0, 1, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0];
+        match ProgramImage::from_bytecode(&with_body(&bytes, &body)) {
+            Err(BytecodeError::IndexOutOfRange { what: "group", .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        // No externs, a jump to termination permutating more than 20 values,
+        // the permutation starts at offset 14 after the header
+        let mut body = vec![0, 1, 0, 2];
+        body.extend_from_slice(&[0xff; 9]);
+        body.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0]);
+        match ProgramImage::from_bytecode(&with_body(&bytes, &body)) {
+            Err(BytecodeError::InvalidPermutation {
+                value: core::u64::MAX,
+                offset: 14,
+            }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match ProgramImage::from_bytecode(&with_body(&bytes, &[0, 1, 7])) {
+            Err(BytecodeError::InvalidTag { what: "entry", .. }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match ProgramImage::from_bytecode(&with_body(&bytes, &[0, 0, 0, 0, 0, 0, 0, 0, 0])) {
+            Err(BytecodeError::TrailingBytes) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        // A label for entry 1 of a program with only one entry
+        let body = [0, 1, 2, 0, 0, 0, 1, 1, 1, b'x', 0, 0, 0];
+        match ProgramImage::from_bytecode(&with_body(&bytes, &body)) {
+            Err(BytecodeError::IndexOutOfRange {
+                what: "entry label",
+                index: 1,
+                len: 1,
+            }) => (),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
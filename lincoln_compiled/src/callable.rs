@@ -0,0 +1,132 @@
+use crate::closure::Closure;
+use crate::entries::{NativeClosure, WrappedFn};
+use crate::error::EvalError;
+use crate::references::CodeRef;
+use lincoln_common::{Context, Value};
+
+/// A value that can be resumed with a variant, by `Return` entries
+/// or `eval_closure`.
+///
+/// Closures of program groups jump to the entry of the variant,
+/// native closures (see `native_closure`) call their host function
+/// with the variant. Hosts provide callable values as native closures,
+/// so the trait is only implemented in this crate.
+///
+pub(crate) trait Callable: Value {
+    /// Resume the value, consuming it
+    ///
+    /// ctx: the values returned
+    /// variant: the variant to resume with
+    ///
+    /// returns: the code to evaluate next
+    ///
+    fn call(self: Box<Self>, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError>;
+}
+
+/// Turn a value into a callable value, or fail with `CallingWrapped`
+/// if the value is not callable
+///
+pub(crate) fn into_callable(value: Box<dyn Value>) -> Result<Box<dyn Callable>, EvalError> {
+    let value = match value.into_boxed_any().downcast::<Closure>() {
+        Ok(closure) => return Ok(closure),
+        Err(value) => value,
+    };
+    let value = match value.downcast::<NativeClosure>() {
+        Ok(native) => return Ok(native),
+        Err(value) => value,
+    };
+    match value.downcast::<WrappedFn>() {
+        Ok(native) => Ok(native),
+        Err(_) => Err(EvalError::CallingWrapped),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::into_callable;
+    use crate::{eval_closure, native_closure, CodeRef, EvalError, NativeClosure, Program};
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_native_closure() {
+        let called = Rc::new(Cell::new(None));
+        let c = called.clone();
+        let native = native_closure("host", move |ctx, variant| {
+            c.set(Some((variant, unwrap::<i32>(ctx.pop()?)?)));
+            Ok(CodeRef::Termination)
+        });
+
+        // A return to a native continuation passes the variant through
+        let mut prog = Program::new();
+        let ret = prog.add_return(2);
+        let mut ctx = default_context();
+        ctx.push(wrap(42i32));
+        ctx.push(native);
+        assert_eq!(prog.eval(&mut *ctx, &ret).unwrap(), CodeRef::Termination);
+        assert_eq!(called.get(), Some((2, 42)));
+
+        let mut ctx = default_context();
+        match eval_closure(wrap(1i32), &mut *ctx, 0) {
+            Err(EvalError::CallingWrapped) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(into_callable(wrap(1i32)).is_err());
+    }
+
+    #[test]
+    fn test_multi_shot_closure() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let l = log.clone();
+        let logger = NativeClosure::new("logger", move |ctx, variant| {
+            l.borrow_mut().push((variant, unwrap::<i32>(ctx.pop()?)?));
+            Ok(CodeRef::Termination)
+        })
+        .copyable(true)
+        .droppable(true);
+
+        // Clones share the function, and the variant is passed through
+        for (variant, value) in [(0u8, 1i32), (3, 2)].iter() {
+            let mut ctx = default_context();
+            ctx.push(wrap(*value));
+            let r = eval_closure(logger.clone().into_value(), &mut *ctx, *variant);
+            assert_eq!(r.unwrap(), CodeRef::Termination);
+        }
+        assert_eq!(*log.borrow(), vec![(0, 1), (3, 2)]);
+
+        // Variant 2 passes two copies to the continuation
+        let copies = Rc::new(RefCell::new(vec![]));
+        let c = copies.clone();
+        let mut ctx = default_context();
+        ctx.push(native_closure("collect", move |ctx, _| {
+            c.borrow_mut().push(ctx.pop()?);
+            c.borrow_mut().push(ctx.pop()?);
+            Ok(CodeRef::Termination)
+        }));
+        assert!(eval_closure(logger.clone().into_value(), &mut *ctx, 2).is_ok());
+        for copy in copies.replace(vec![]) {
+            let mut ctx = default_context();
+            ctx.push(wrap(3i32));
+            assert!(eval_closure(copy, &mut *ctx, 0).is_ok());
+        }
+        assert_eq!(log.borrow().len(), 4);
+
+        // Variant 1 resumes the continuation without calling the function
+        let mut ctx = default_context();
+        ctx.push(native_closure("cont", |_, _| Ok(CodeRef::Termination)));
+        assert!(eval_closure(logger.into_value(), &mut *ctx, 1).is_ok());
+        assert_eq!(log.borrow().len(), 4);
+
+        // Without declaring copy, variant 2 goes to the function
+        let seen = Rc::new(Cell::new(None));
+        let s = seen.clone();
+        let closure = NativeClosure::new("linear", move |_, variant| {
+            s.set(Some(variant));
+            Ok(CodeRef::Termination)
+        });
+        let mut ctx = default_context();
+        assert!(eval_closure(closure.into_value(), &mut *ctx, 2).is_ok());
+        assert_eq!(seen.get(), Some(2));
+    }
+}
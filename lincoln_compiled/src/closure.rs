@@ -1,16 +1,34 @@
-use crate::program::Program;
-use crate::references::GroupRef;
-use crate::entries::ExternEntry;
+use crate::store::CodeStore;
+use crate::references::{EntryRef, GroupRef};
+use crate::entries::{ExternEntry, ValueFn};
 use super::CodeRef;
-use lincoln_common::{Context, ContextExt, Value};
-use crate::EvalError;
+use lincoln_common::{Context, ContextExt, Linearity, Value};
+use crate::{CodeRefError, EvalError};
+use crate::callable::{into_callable, Callable};
+use crate::observer::{ClosureId, EvalEvent, EventSink};
+use core::cell::Cell;
 use core::fmt::{Debug, Display};
 use core::mem::replace;
-use lincoln_common::Access;
 
-struct Closure {
+thread_local! {
+    static NEXT_CLOSURE_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+fn new_closure_id() -> ClosureId {
+    NEXT_CLOSURE_ID.with(|id| {
+        let r = id.get();
+        id.set(r + 1);
+        ClosureId(r)
+    })
+}
+
+pub(crate) struct Closure {
+    id: ClosureId,
+    group: GroupRef,
+    call: EntryRef,
     tags: Vec<CodeRef>,
     context: Box<dyn Context>,
+    events: EventSink,
 }
 impl Debug for Closure {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -35,24 +53,92 @@ impl Value for Closure {
         let empty = self.context.create_empty();
         let context = replace(&mut self.context, empty);
         let tags = replace(&mut self.tags, vec![]);
-        Box::new(Closure { tags, context })
+        Box::new(Closure {
+            id: self.id,
+            group: self.group,
+            call: self.call,
+            tags,
+            context,
+            events: self.events.clone(),
+        })
+    }
+    fn copy_value(&self) -> Option<Box<dyn Value>> {
+        self.copy().ok().map(|c| -> Box<dyn Value> { Box::new(c) })
+    }
+    fn is_droppable(&self) -> bool {
+        self.context.find_undroppable().is_none()
+    }
+    /// The most restricted of the captured values, without copying them
+    fn linearity(&self) -> Linearity {
+        let linearity = |i| {
+            self.context
+                .get(i)
+                .map(|v| v.linearity())
+                .unwrap_or(Linearity::Linear)
+        };
+        (0..self.context.len())
+            .map(linearity)
+            .fold(Linearity::Unrestricted, |l, r| match (l, r) {
+                (Linearity::Linear, _) | (_, Linearity::Linear) => Linearity::Linear,
+                (Linearity::Affine, _) | (_, Linearity::Affine) => Linearity::Affine,
+                _ => Linearity::Unrestricted,
+            })
+    }
+}
+impl Callable for Closure {
+    fn call(self: Box<Self>, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
+        self.eval(ctx, variant)
     }
 }
 impl Closure {
-    pub fn get_from_value(value: Box<dyn Value>) -> Result<Self, EvalError> {
-        value.into_boxed_any()
-            .downcast::<Closure>().map_err(|_| EvalError::CallingWrapped)
-            .map(|c| *c)
+    fn new(
+        group: GroupRef,
+        call: EntryRef,
+        tags: Vec<CodeRef>,
+        context: Box<dyn Context>,
+        events: EventSink,
+    ) -> Self {
+        let id = new_closure_id();
+        events.record(EvalEvent::ClosureCreated {
+            id,
+            group,
+            captured: context.len(),
+        });
+        Closure {
+            id,
+            group,
+            call,
+            tags,
+            context,
+            events,
+        }
+    }
+    pub fn eval(self, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
+        let (id, group, events) = (self.id, self.group, self.events.clone());
+        //A copied closure is kept, so it is not resumed
+        let copying = variant == 2 && self.tags.len() == 1;
+        let next = self.eval_variant(ctx, variant)?;
+        if !copying {
+            events.record(EvalEvent::ClosureResumed {
+                id,
+                group,
+                variant,
+                next,
+            });
+        }
+        Ok(next)
     }
-    pub fn eval(mut self: Self, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
+    fn eval_variant(mut self, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
         let variant_cnt = self.tags.len();
-        ctx.merge(&mut *self.context);
         //A closure without variants is "Termination"
         if self.tags.is_empty() {
+            ctx.merge(&mut *self.context);
             return Ok(CodeRef::Termination);
         }
-        //Variant 1 is "drop" for single variant closures. Requires no captured variables
-        //Variant 2 is "copy" for single variant closures. Requires no captured variables
+        //Variant 1 is "drop" for single variant closures. Requires droppable captured values
+        //Variant 2 is "copy" for single variant closures. Requires copyable captured values
+        //Both resume the continuation on top of the context, with
+        //no values for "drop" and the two copies for "copy".
         if variant as usize >= variant_cnt && (variant_cnt != 1 || (variant != 1 && variant != 2)) {
             Err(EvalError::VariantOutOfBound {
                 given: variant,
@@ -60,53 +146,235 @@ impl Closure {
             })
         } else if variant == 1 && variant_cnt == 1 {
             ctx.expect_args(1)?;
+            if let Some(index) = self.context.find_undroppable() {
+                return Err(self.capture_error(index, false));
+            }
             let cont = ctx.pop()?;
-            Self::get_from_value(cont)?.eval(ctx, 0)
+            eval_closure(cont, ctx, 0)
         } else if variant == 2 && variant_cnt == 1 {
             ctx.expect_args(1)?;
+            let copy = self.copy()?;
             let cont = ctx.pop()?;
-            let mut values = [
-                Closure {
-                    tags: self.tags.clone(),
-                    context: ctx.create_empty(),
-                },
-                Closure {
-                    tags: self.tags,
-                    context: ctx.create_empty(),
-                },
-            ];
-            ctx.extend(&mut values.iter_mut().map(|c| -> &mut dyn Value { c }));            
-            Self::get_from_value(cont)?.eval(ctx, 0)
+            let mut values = [copy, self];
+            ctx.extend(&mut values.iter_mut().map(|c| -> &mut dyn Value { c }));
+            eval_closure(cont, ctx, 0)
         } else {
+            ctx.merge(&mut *self.context);
             Ok(self.tags[variant as usize])
         }
     }
+    /// A new closure with copies of the captured values
+    fn copy(&self) -> Result<Closure, EvalError> {
+        let context = self
+            .context
+            .copy_values()
+            .map_err(|index| self.capture_error(index, true))?;
+        Ok(Closure::new(
+            self.group,
+            self.call,
+            self.tags.clone(),
+            context,
+            self.events.clone(),
+        ))
+    }
+    fn capture_error(&self, index: u8, copy: bool) -> EvalError {
+        let closure = format!("{}", self);
+        let type_name = self
+            .context
+            .get(index)
+            .map(|v| v.type_name())
+            .unwrap_or("?");
+        if copy {
+            EvalError::NotCopyable {
+                closure,
+                index,
+                type_name,
+            }
+        } else {
+            EvalError::NotDroppable {
+                closure,
+                index,
+                type_name,
+            }
+        }
+    }
 }
 
+/// A closure found in a context, see `pending_closures`
+pub(crate) struct PendingClosure {
+    pub(crate) id: ClosureId,
+    pub(crate) group: GroupRef,
+    pub(crate) call: EntryRef,
+    pub(crate) captured: u8,
+    pub(crate) depth: usize,
+}
+
+/// Find the closures in a context, and the closures they captured.
+/// The last value of a context is the most recent continuation,
+/// so values are visited from the last one, and each closure is followed
+/// by the closures it captured.
+///
+/// ctx: the context to search
+///
+pub(crate) fn pending_closures(ctx: &dyn Context) -> Vec<PendingClosure> {
+    let mut result = vec![];
+    let mut stack: Vec<(&dyn Context, u8)> = vec![(ctx, ctx.len())];
+    while let Some((ctx, remaining)) = stack.last_mut() {
+        if *remaining == 0 {
+            let _ = stack.pop();
+            continue;
+        }
+        *remaining -= 1;
+        let closure = ctx
+            .get(*remaining)
+            .and_then(|v| v.as_any().downcast_ref::<Closure>());
+        if let Some(closure) = closure {
+            result.push(PendingClosure {
+                id: closure.id,
+                group: closure.group,
+                call: closure.call,
+                captured: closure.context.len(),
+                depth: stack.len() - 1,
+            });
+            stack.push((&*closure.context, closure.context.len()));
+        }
+    }
+    result
+}
+
+/// Resume a callable value, either a closure of the program
+/// or a native closure, with a variant
+///
 pub fn eval_closure(value: Box<dyn Value>, ctx: &mut dyn Context, variant: u8)
     -> Result<CodeRef, EvalError>
 {
-    Closure::get_from_value(value)?.eval(ctx, variant)
+    into_callable(value)?.call(ctx, variant)
 }
 
 /// Build a closure value from a group reference, a context and program
 ///
+/// call: the `Call` entry creating the closure
+/// events: where the closure reports its events
+///
 pub(crate) fn closure_prog(
     ent: GroupRef,
+    call: EntryRef,
     ctx: Box<dyn Context>,
-    prog: &Program,
+    prog: &dyn CodeStore,
+    events: &EventSink,
 ) -> Result<Box<dyn Value>, EvalError> {
-    if let Some(1) = ent.count(prog) {
-        if let Ok(CodeRef::Extern(ext)) = ent.get_entry(prog, 0) {
-            if let Some(ExternEntry::Value { value, .. }) = ext.access(prog) {
-                ctx.expect_args(0)?;
-                return Ok(value.get_value());
+    let tags = prog
+        .group(ent)
+        .ok_or(CodeRefError::InvalidGroupIndex { index: ent })?
+        .to_vec();
+    let value = match tags[..] {
+        [CodeRef::Extern(ext)] => match prog.extern_entry(ext) {
+            Some(ExternEntry::Value { value, .. }) => Some(value),
+            _ => None,
+        },
+        _ => None,
+    };
+    closure_of(ent, call, tags, value, ctx, events)
+}
+
+/// Build a closure value from the entries of a group and a context.
+/// A group of a single value extern produces the value directly.
+///
+/// group: the group the entries came from
+/// call: the `Call` entry creating the closure
+/// tags: the entries of the group
+/// value: the value function, if the group is a single value extern
+/// events: where the closure reports its events
+///
+pub(crate) fn closure_of(
+    group: GroupRef,
+    call: EntryRef,
+    tags: Vec<CodeRef>,
+    value: Option<&ValueFn>,
+    ctx: Box<dyn Context>,
+    events: &EventSink,
+) -> Result<Box<dyn Value>, EvalError> {
+    if let Some(value) = value {
+        ctx.expect_args(0)?;
+        return Ok(value.get_value());
+    }
+    Ok(Box::new(Closure::new(group, call, tags, ctx, events.clone())))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{eval_closure, native_closure, CodeRef, NativeClosure, Program};
+    use lincoln_common::{default_context, wrap, ContextExt, Value};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Capture a value in a single variant closure, then resume the closure
+    /// with a variant. Returns the values the continuation received,
+    /// or the error and the context left by the failed resume.
+    fn resume_captured(
+        captured: Box<dyn Value>,
+        variant: u8,
+    ) -> Result<Vec<Box<dyn Value>>, String> {
+        let mut prog = Program::new();
+        let ret = prog.add_return(0);
+        let k = prog.add_empty_group();
+        prog.add_group_entry(k, ret).unwrap();
+        let resume = prog.add_return(variant);
+        let call = prog.add_call(resume, 1, k);
+
+        let received = Rc::new(RefCell::new(vec![]));
+        let r = received.clone();
+        let mut ctx = default_context();
+        ctx.push(native_closure("cont", move |ctx, _| {
+            while !ctx.is_empty() {
+                r.borrow_mut().push(ctx.pop()?);
             }
-        }
+            Ok(CodeRef::Termination)
+        }));
+        ctx.push(captured);
+        let next = prog.eval(&mut *ctx, &call).map_err(|e| e.to_string())?;
+        let next = prog
+            .eval(&mut *ctx, &next)
+            .map_err(|e| format!("{}, leaving {}", e, ctx))?;
+        assert_eq!(next, CodeRef::Termination);
+        let received = received.replace(vec![]);
+        Ok(received)
     }
 
-    Ok(Box::new(Closure {
-        tags: ent.get_vec(prog)?.into_vec(),
-        context: ctx,
-    }))
+    #[test]
+    fn test_copy_drop_captured() {
+        let shared = || {
+            NativeClosure::new("shared", |_, _| Ok(CodeRef::Termination))
+                .copyable(true)
+                .droppable(true)
+                .into_value()
+        };
+
+        // Both copies keep a copy of the captured value
+        let copies = resume_captured(shared(), 2).unwrap();
+        assert_eq!(copies.len(), 2);
+        for copy in copies {
+            let mut ctx = default_context();
+            assert!(eval_closure(copy, &mut *ctx, 0).is_ok());
+            assert_eq!(ctx.len(), 1);
+            assert_eq!(format!("{}", ctx), "(shared(copy,drop))");
+        }
+        assert_eq!(resume_captured(shared(), 1).unwrap().len(), 0);
+
+        // Closures capturing closures are copied recursively
+        let inner = resume_captured(shared(), 2).unwrap().pop().unwrap();
+        assert_eq!(resume_captured(inner, 2).unwrap().len(), 2);
+
+        // Linear captured values are reported
+        let e = resume_captured(wrap(1i32), 2).err().unwrap();
+        assert!(e.contains("captured value 0 of type i32 cannot be copied"), "{}", e);
+        let e = resume_captured(wrap(1i32), 1).err().unwrap();
+        assert!(e.contains("captured value 0 of type i32 cannot be dropped"), "{}", e);
+
+        // The continuation is kept when the captured values cannot be copied or dropped
+        for variant in [1, 2].iter() {
+            let e = resume_captured(wrap(1i32), *variant).err().unwrap();
+            assert!(e.ends_with(", leaving (cont)"), "{}", e);
+        }
+    }
 }
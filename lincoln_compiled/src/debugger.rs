@@ -0,0 +1,499 @@
+use crate::entries::Entry;
+use crate::machine::{Machine, MachineState};
+use crate::observer::{ClosureId, EvalEvent, EvalObserver};
+use crate::references::CodeRef;
+use crate::store::CodeStore;
+use crate::EvalError;
+use core::fmt::{Display, Formatter};
+use lincoln_common::Context;
+use std::collections::BTreeMap;
+
+/// Where a breakpoint stops the execution
+///
+#[derive(Clone, Debug)]
+pub enum BreakLocation {
+    /// Before evaluating a specific program entry or extern
+    Code(CodeRef),
+    /// Before evaluating any variant of an exported group
+    Export(String),
+    /// Before invoking any extern with the given name
+    Extern(String),
+}
+impl Display for BreakLocation {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        match self {
+            BreakLocation::Code(c) => write!(fmt, "{}", c),
+            BreakLocation::Export(name) => write!(fmt, "export {}", name),
+            BreakLocation::Extern(name) => write!(fmt, "extern {}", name),
+        }
+    }
+}
+
+/// An extra condition that must hold for a breakpoint to stop
+///
+#[derive(Clone, Debug)]
+pub enum BreakCondition {
+    /// The context holds exactly this number of values
+    ContextLen(u8),
+    /// The value at the slot has the given type. The type name can be
+    /// the full path or the last segment (e.g. `usize` or `String`)
+    ValueType { slot: u8, type_name: String },
+    /// The entry is a `Return` on this variant
+    ReturnVariant(u8),
+}
+impl Display for BreakCondition {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        match self {
+            BreakCondition::ContextLen(len) => write!(fmt, "len {}", len),
+            BreakCondition::ValueType { slot, type_name } => {
+                write!(fmt, "slot {} is {}", slot, type_name)
+            }
+            BreakCondition::ReturnVariant(variant) => write!(fmt, "ret {}", variant),
+        }
+    }
+}
+impl BreakCondition {
+    fn holds(&self, prog: &dyn CodeStore, at: CodeRef, ctx: &dyn Context) -> bool {
+        match self {
+            BreakCondition::ContextLen(len) => ctx.len() == *len,
+            BreakCondition::ValueType { slot, type_name } => ctx
+                .get(*slot)
+                .map(|v| {
+                    let full = v.type_name();
+                    full == type_name || full.ends_with(&format!("::{}", type_name))
+                })
+                .unwrap_or(false),
+            BreakCondition::ReturnVariant(v) => match at {
+                CodeRef::Entry(e) => match prog.entry(e) {
+                    Some(Entry::Return { variant }) => variant == v,
+                    _ => false,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+/// A breakpoint is a location with zero or more conditions.
+/// It stops the execution before the entry at the location is evaluated,
+/// when all conditions hold.
+///
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub location: BreakLocation,
+    pub conditions: Vec<BreakCondition>,
+}
+impl Display for Breakpoint {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "{}", self.location)?;
+        let mut it = self.conditions.iter();
+        if let Some(c) = it.next() {
+            write!(fmt, " if {}", c)?;
+        }
+        for c in it {
+            write!(fmt, " and {}", c)?;
+        }
+        Ok(())
+    }
+}
+impl Breakpoint {
+    /// Create an unconditional breakpoint
+    pub fn new(location: BreakLocation) -> Self {
+        Breakpoint {
+            location,
+            conditions: vec![],
+        }
+    }
+    /// Add a condition to the breakpoint
+    pub fn when(mut self, condition: BreakCondition) -> Self {
+        self.conditions.push(condition);
+        self
+    }
+    /// Check whether the breakpoint stops before evaluating `at`
+    ///
+    /// prog: the program
+    /// at: the entry to be evaluated
+    /// ctx: the current context
+    ///
+    pub fn matches(&self, prog: &dyn CodeStore, at: CodeRef, ctx: &dyn Context) -> bool {
+        let at_location = match &self.location {
+            BreakLocation::Code(c) => *c == at,
+            BreakLocation::Export(name) => prog
+                .export(name)
+                .and_then(|g| prog.group(g))
+                .map(|v| v.contains(&at))
+                .unwrap_or(false),
+            BreakLocation::Extern(name) => match at {
+                CodeRef::Extern(e) => prog
+                    .extern_entry(e)
+                    .map(|e| e.name() == name)
+                    .unwrap_or(false),
+                _ => false,
+            },
+        };
+        at_location && self.conditions.iter().all(|c| c.holds(prog, at, ctx))
+    }
+}
+
+/// The reason the debugger stopped
+///
+#[derive(Debug)]
+pub enum DebugStop {
+    /// A breakpoint was hit. The machine stops before evaluating its entry.
+    Breakpoint(usize),
+    /// The requested step was completed
+    Stepped,
+    /// The execution reached the end
+    Terminated,
+    /// The evaluation failed
+    Failed(EvalError),
+}
+
+/// Keeps track of the continuation closures created by `Call` entries
+/// that were not yet resumed.
+///
+struct FrameTracker<'a> {
+    frames: &'a mut Vec<ClosureId>,
+    in_call: bool,
+    created: Option<ClosureId>,
+    resumed: Vec<ClosureId>,
+}
+impl<'a> EvalObserver for FrameTracker<'a> {
+    fn observe(&mut self, _: &dyn CodeStore, event: &EvalEvent, _: &dyn Context) {
+        match event {
+            EvalEvent::BeforeEntry {
+                entry: Entry::Call { .. },
+                ..
+            } => self.in_call = true,
+            EvalEvent::AfterEntry { .. } => self.in_call = false,
+            EvalEvent::ClosureCreated { id, .. } if self.in_call => {
+                self.frames.push(*id);
+                self.created = Some(*id);
+            }
+            EvalEvent::ClosureResumed { id, .. } => {
+                self.frames.retain(|f| f != id);
+                self.resumed.push(*id);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// A debugger drives a `Machine` and stops on breakpoints.
+///
+/// Besides running to the next breakpoint, it supports step-over
+/// (run until the continuation of a `Call` is resumed) and step-out
+/// (run until the continuation of the innermost pending `Call` is resumed).
+/// Pending continuations are only tracked while the machine is driven
+/// by the debugger.
+///
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    next_id: usize,
+    frames: Vec<ClosureId>,
+    /// The number of steps of the machine when the debugger last stepped it
+    stopped_at: Option<usize>,
+}
+impl Debugger {
+    /// Create a debugger without breakpoints
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Add a breakpoint, returns its id
+    pub fn add_breakpoint(&mut self, bp: Breakpoint) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        let _ = self.breakpoints.insert(id, bp);
+        id
+    }
+    /// Remove a breakpoint by its id
+    pub fn remove_breakpoint(&mut self, id: usize) -> Option<Breakpoint> {
+        self.breakpoints.remove(&id)
+    }
+    /// Iterate all breakpoints with their ids
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, bp)| (*id, bp))
+    }
+    /// Whether there are any breakpoints
+    pub fn has_breakpoints(&self) -> bool {
+        !self.breakpoints.is_empty()
+    }
+    /// The continuation closures created by `Call`s that were not yet resumed,
+    /// the innermost last
+    pub fn pending_frames(&self) -> &[ClosureId] {
+        &self.frames
+    }
+    /// Forget the tracked continuations, e.g. when starting a new execution
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.stopped_at = None;
+    }
+    /// Find the first breakpoint that stops the machine at its current entry
+    pub fn hit<P>(&self, machine: &Machine<P>) -> Option<usize>
+    where
+        P: CodeStore,
+    {
+        let (prog, at, ctx) = (machine.program(), machine.current(), machine.context());
+        self.breakpoints
+            .iter()
+            .find(|(_, bp)| bp.matches(prog, at, ctx))
+            .map(|(id, _)| *id)
+    }
+    /// Evaluate a single step
+    pub fn step<P>(&mut self, machine: &mut Machine<P>) -> DebugStop
+    where
+        P: CodeStore,
+    {
+        self.run_until(machine, |_| true)
+    }
+    /// Run until a breakpoint is hit, or the execution ends.
+    /// A breakpoint on the current entry stops before evaluating it,
+    /// unless the debugger has already stopped there.
+    pub fn continue_run<P>(&mut self, machine: &mut Machine<P>) -> DebugStop
+    where
+        P: CodeStore,
+    {
+        if let Some(id) = self.hit_before_run(machine) {
+            return DebugStop::Breakpoint(id);
+        }
+        self.run_until(machine, |_| false)
+    }
+    /// Evaluate a step. If it is a `Call`, keep running until the
+    /// continuation it created is resumed.
+    pub fn step_over<P>(&mut self, machine: &mut Machine<P>) -> DebugStop
+    where
+        P: CodeStore,
+    {
+        let created = match self.step_tracked(machine) {
+            Ok((created, _)) => created,
+            Err(stop) => return stop,
+        };
+        match created {
+            None => DebugStop::Stepped,
+            Some(target) => {
+                if let Some(id) = self.hit(machine) {
+                    return DebugStop::Breakpoint(id);
+                }
+                self.run_until(machine, |resumed| resumed.contains(&target))
+            }
+        }
+    }
+    /// Run until the innermost pending continuation is resumed.
+    /// If there is no pending continuation, this is the same as `continue_run`.
+    pub fn step_out<P>(&mut self, machine: &mut Machine<P>) -> DebugStop
+    where
+        P: CodeStore,
+    {
+        match self.frames.last().copied() {
+            Some(target) => {
+                if let Some(id) = self.hit_before_run(machine) {
+                    return DebugStop::Breakpoint(id);
+                }
+                self.run_until(machine, |resumed| resumed.contains(&target))
+            }
+            None => self.continue_run(machine),
+        }
+    }
+    /// A breakpoint at the current entry, if the machine did not get there
+    /// by a step of this debugger, e.g. at the start of the execution
+    fn hit_before_run<P>(&mut self, machine: &Machine<P>) -> Option<usize>
+    where
+        P: CodeStore,
+    {
+        if self.stopped_at == Some(machine.steps()) {
+            return None;
+        }
+        self.stopped_at = Some(machine.steps());
+        self.hit(machine)
+    }
+
+    fn run_until<P>(
+        &mut self,
+        machine: &mut Machine<P>,
+        mut done: impl FnMut(&[ClosureId]) -> bool,
+    ) -> DebugStop
+    where
+        P: CodeStore,
+    {
+        loop {
+            let resumed = match self.step_tracked(machine) {
+                Ok((_, resumed)) => resumed,
+                Err(stop) => return stop,
+            };
+            if done(&resumed) {
+                return DebugStop::Stepped;
+            }
+            if let Some(id) = self.hit(machine) {
+                return DebugStop::Breakpoint(id);
+            }
+        }
+    }
+    fn step_tracked<P>(
+        &mut self,
+        machine: &mut Machine<P>,
+    ) -> Result<(Option<ClosureId>, Vec<ClosureId>), DebugStop>
+    where
+        P: CodeStore,
+    {
+        let mut tracker = FrameTracker {
+            frames: &mut self.frames,
+            in_call: false,
+            created: None,
+            resumed: vec![],
+        };
+        let state = machine.run_for_observed(1, &mut tracker);
+        self.stopped_at = Some(machine.steps());
+        match state {
+            MachineState::Paused => Ok((tracker.created, tracker.resumed)),
+            MachineState::Terminated => Err(DebugStop::Terminated),
+            MachineState::Failed(e) => Err(DebugStop::Failed(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BreakCondition, BreakLocation, Breakpoint, DebugStop, Debugger};
+    use crate::{eval_closure, native_closure, CodeRef, EvalFn, ExternEntry, Machine, Program};
+    use lincoln_common::{default_context, wrap, ContextExt, Permutation};
+
+    // main: call sub 1 k    (k is a group of `done`)
+    // sub: jmp sub1 #!ba
+    // sub1: jmp ret #!ba
+    // ret: ret 0
+    fn call_program() -> (Program, CodeRef, CodeRef) {
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let ret = prog.add_return(0);
+        let sub1 = prog.add_jump(ret, Permutation(1));
+        let sub = prog.add_jump(sub1, Permutation(1));
+        let k = prog.add_empty_group();
+        prog.add_group_entry(k, done).unwrap();
+        let main = prog.add_call(sub, 1, k);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, main).unwrap();
+        prog.add_export("main", g);
+        (prog, sub1, done)
+    }
+    fn start(prog: &Program) -> Machine<&Program> {
+        let mut ctx = default_context();
+        ctx.push(wrap(1usize));
+        Machine::start(prog, ctx, "main", 0).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let (prog, sub1, _) = call_program();
+        let mut dbg = Debugger::new();
+        let bp = dbg.add_breakpoint(Breakpoint::new(BreakLocation::Code(sub1)));
+        let ext = dbg.add_breakpoint(
+            Breakpoint::new(BreakLocation::Extern("done".into()))
+                .when(BreakCondition::ContextLen(1))
+                .when(BreakCondition::ValueType {
+                    slot: 0,
+                    type_name: "usize".into(),
+                }),
+        );
+        let mut m = start(&prog);
+        match dbg.continue_run(&mut m) {
+            DebugStop::Breakpoint(id) => assert_eq!(id, bp),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        assert_eq!(m.current(), sub1);
+        match dbg.continue_run(&mut m) {
+            DebugStop::Breakpoint(id) => assert_eq!(id, ext),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        match dbg.continue_run(&mut m) {
+            DebugStop::Terminated => (),
+            s => panic!("unexpected stop {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_breakpoint_at_start() {
+        let (prog, _, _) = call_program();
+        let mut dbg = Debugger::new();
+        let mut m = start(&prog);
+        let bp = dbg.add_breakpoint(Breakpoint::new(BreakLocation::Code(m.current())));
+        match dbg.continue_run(&mut m) {
+            DebugStop::Breakpoint(id) => assert_eq!(id, bp),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        assert_eq!(m.steps(), 0);
+        match dbg.continue_run(&mut m) {
+            DebugStop::Terminated => (),
+            s => panic!("unexpected stop {:?}", s),
+        }
+    }
+
+    #[test]
+    fn test_step_over_and_out() {
+        let (prog, sub1, done) = call_program();
+        let mut dbg = Debugger::new();
+        let mut m = start(&prog);
+        match dbg.step_over(&mut m) {
+            DebugStop::Stepped => (),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        assert_eq!(m.current(), done);
+        assert!(dbg.pending_frames().is_empty());
+
+        let mut m = start(&prog);
+        let _ = dbg.step(&mut m);
+        let _ = dbg.step(&mut m);
+        assert_eq!(m.current(), sub1);
+        assert_eq!(dbg.pending_frames().len(), 1);
+        match dbg.step_out(&mut m) {
+            DebugStop::Stepped => (),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        assert_eq!(m.current(), done);
+    }
+
+    #[test]
+    fn test_step_over_copied_continuation() {
+        // main: call dup 0 k    (k is a group of `done`)
+        // dup copies its continuation, drops the copy and goes to
+        // resume: ret 0
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let resume = prog.add_return(0);
+        let dup = prog.add_extern(ExternEntry::Eval {
+            name: "dup".into(),
+            eval: EvalFn::stateful(Box::new(move |ctx| {
+                let k = ctx.pop()?;
+                ctx.push(native_closure("copied", move |ctx, _| {
+                    let original = ctx.pop()?;
+                    let _copy = ctx.pop()?;
+                    ctx.push(original);
+                    Ok(resume)
+                }));
+                eval_closure(k, ctx, 2)
+            })),
+        });
+        let k = prog.add_empty_group();
+        prog.add_group_entry(k, done).unwrap();
+        let main = prog.add_call(dup, 0, k);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, main).unwrap();
+        prog.add_export("main", g);
+
+        let mut dbg = Debugger::new();
+        let mut m = Machine::start(&prog, default_context(), "main", 0).unwrap();
+        match dbg.step_over(&mut m) {
+            DebugStop::Stepped => (),
+            s => panic!("unexpected stop {:?}", s),
+        }
+        assert_eq!(m.current(), done);
+        assert_eq!(m.steps(), 3);
+        assert!(dbg.pending_frames().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use super::{EvalFn, ExternEntry};
+use crate::closure::eval_closure;
+use crate::error::EvalError;
+use crate::references::CodeRef;
+use lincoln_common::{unwrap, wrap, AnyDebugDisplay, Context, ContextExt, ValueAccessError};
+
+fn copy_value(ctx: &mut dyn Context) -> Result<CodeRef, EvalError> {
+    ctx.expect_args(2)?;
+    let cont = ctx.pop()?;
+    let v = ctx.pop()?;
+    let copy = v
+        .copy_value()
+        .ok_or_else(|| ValueAccessError::NotCopyable(v.type_name()))?;
+    ctx.push(v);
+    ctx.push(copy);
+    eval_closure(cont, ctx, 0)
+}
+fn drop_value(ctx: &mut dyn Context) -> Result<CodeRef, EvalError> {
+    ctx.expect_args(2)?;
+    let cont = ctx.pop()?;
+    let v = ctx.pop()?;
+    if !v.is_droppable() {
+        return Err(ValueAccessError::NotDroppable(v.type_name()).into());
+    }
+    eval_closure(cont, ctx, 0)
+}
+fn copy_of<T>(ctx: &mut dyn Context) -> Result<CodeRef, EvalError>
+where
+    T: AnyDebugDisplay + Clone,
+{
+    ctx.expect_args(2)?;
+    let cont = ctx.pop()?;
+    let v = unwrap::<T>(ctx.pop()?)?;
+    ctx.push(wrap(v.clone()));
+    ctx.push(wrap(v));
+    eval_closure(cont, ctx, 0)
+}
+fn drop_of<T>(ctx: &mut dyn Context) -> Result<CodeRef, EvalError>
+where
+    T: AnyDebugDisplay,
+{
+    ctx.expect_args(2)?;
+    let cont = ctx.pop()?;
+    let _ = unwrap::<T>(ctx.pop()?)?;
+    eval_closure(cont, ctx, 0)
+}
+
+impl ExternEntry {
+    /// An extern copying the value before its continuation, then resuming
+    /// the continuation with the value and the copy. Wrapped values can only
+    /// be copied if their type is registered, see `register_copyable`.
+    ///
+    /// name: the name of the extern
+    ///
+    pub fn copy_value(name: impl Into<String>) -> Self {
+        ExternEntry::Eval {
+            name: name.into(),
+            eval: EvalFn::stateless(copy_value),
+        }
+    }
+    /// An extern dropping the value before its continuation, then resuming
+    /// the continuation. Wrapped values can only be dropped if their type is
+    /// registered, see `register_droppable`.
+    ///
+    /// name: the name of the extern
+    ///
+    pub fn drop_value(name: impl Into<String>) -> Self {
+        ExternEntry::Eval {
+            name: name.into(),
+            eval: EvalFn::stateless(drop_value),
+        }
+    }
+    /// Like `copy_value`, but for wrapped values of a single type,
+    /// which are cloned whether the type is registered or not
+    ///
+    /// name: the name of the extern
+    ///
+    pub fn copy_of<T>(name: impl Into<String>) -> Self
+    where
+        T: AnyDebugDisplay + Clone,
+    {
+        ExternEntry::Eval {
+            name: name.into(),
+            eval: EvalFn::stateless(copy_of::<T>),
+        }
+    }
+    /// Like `drop_value`, but for wrapped values of a single type,
+    /// which are dropped whether the type is registered or not
+    ///
+    /// name: the name of the extern
+    ///
+    pub fn drop_of<T>(name: impl Into<String>) -> Self
+    where
+        T: AnyDebugDisplay,
+    {
+        ExternEntry::Eval {
+            name: name.into(),
+            eval: EvalFn::stateless(drop_of::<T>),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{native_closure, CodeRef, ExternEntry, Program};
+    use core::fmt::{Display, Formatter};
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt, Value};
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Token(u32);
+    impl Display for Token {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "token {}", self.0)
+        }
+    }
+
+    /// Evaluate an extern on a value, returning what its continuation received
+    fn run(ext: ExternEntry, value: Box<dyn Value>) -> Result<Vec<Token>, String> {
+        let mut prog = Program::new();
+        let ext = prog.add_extern(ext);
+        let mut ctx = default_context();
+        ctx.push(value);
+        ctx.push(native_closure("cont", |_, _| Ok(CodeRef::Termination)));
+        let next = prog.eval(&mut *ctx, &ext).map_err(|e| e.to_string())?;
+        assert_eq!(next, CodeRef::Termination);
+        let mut received = vec![];
+        while !ctx.is_empty() {
+            received.push(unwrap::<Token>(ctx.pop().unwrap()).unwrap());
+        }
+        Ok(received)
+    }
+
+    #[test]
+    fn test_copy_drop() {
+        // Token is not registered
+        let copy = run(ExternEntry::copy_of::<Token>("copy"), wrap(Token(1)));
+        assert_eq!(copy, Ok(vec![Token(1), Token(1)]));
+        let drop = run(ExternEntry::drop_of::<Token>("drop"), wrap(Token(1)));
+        assert_eq!(drop, Ok(vec![]));
+        assert!(run(ExternEntry::copy_of::<u8>("copy"), wrap(Token(1))).is_err());
+
+        let e = run(ExternEntry::copy_value("copy"), wrap(Token(1))).unwrap_err();
+        assert!(e.contains("Token"), "{}", e);
+        let e = run(ExternEntry::drop_value("drop"), wrap(Token(1))).unwrap_err();
+        assert!(e.contains("Token"), "{}", e);
+
+        let shared = native_closure("shared", |_, _| Ok(CodeRef::Termination));
+        assert!(run(ExternEntry::copy_value("copy"), shared).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::GroupRef;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ExportEntry {
     pub name: String,
     pub g: GroupRef,
@@ -4,6 +4,8 @@ use crate::references::{CodeRef, GroupRef};
 use smallvec::SmallVec;
 
 mod wrapped_fn;
+mod native_fn;
+mod builtin_fn;
 mod eval_fn;
 mod export_entry;
 mod extern_entry;
@@ -11,14 +13,15 @@ mod value_fn;
 
 pub use eval_fn::EvalFn;
 pub use export_entry::ExportEntry;
+pub use native_fn::NativeClosure;
 pub use extern_entry::ExternEntry;
 pub use value_fn::ValueFn;
 
-use wrapped_fn::WrappedFn;
+pub(crate) use wrapped_fn::WrappedFn;
 
 pub(crate) type CodeGroup = SmallVec<[CodeRef; 5]>;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub enum Entry {
     Jump {
         cont: CodeRef,
@@ -51,9 +54,16 @@ impl std::fmt::Debug for Entry {
         write!(fmt, "{}", self)
     }
 }
+/// Build a closure of the host program. It can be resumed by `Return`
+/// entries and `eval_closure` like closures of the program, and receives
+/// the variant it is resumed with.
+///
+/// name: the name shown when the closure is printed
+/// f: the function to call when the closure is resumed
+///
 pub fn native_closure(
     name: impl Into<String>,
     f: impl FnOnce(&mut dyn Context, u8) -> Result<CodeRef, EvalError> + 'static,
 ) -> Box<dyn Value> {
-    Box::new(WrappedFn(name.into(), Some(f)))
+    Box::new(WrappedFn(name.into(), Some(Box::new(f))))
 }
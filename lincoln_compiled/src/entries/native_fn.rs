@@ -0,0 +1,130 @@
+use super::{CodeRef, Context, Value};
+use crate::callable::Callable;
+use crate::closure::eval_closure;
+use crate::error::EvalError;
+use core::cell::RefCell;
+use core::fmt::Debug;
+use core::fmt::Display;
+use core::fmt::Formatter;
+use lincoln_common::ContextExt;
+use std::rc::Rc;
+
+type SharedFn = Rc<RefCell<dyn FnMut(&mut dyn Context, u8) -> Result<CodeRef, EvalError>>>;
+
+/// A native closure that can be resumed any number of times.
+///
+/// Copies and clones of the closure share the same host function, so state kept
+/// by the function (a log, a counter) is seen by all of them.
+///
+/// A copyable closure resumed with variant 2 pushes two copies of itself
+/// and resumes the continuation on top of the context, a droppable closure
+/// resumed with variant 1 just resumes the continuation, like closures of
+/// single variant groups. Otherwise the variant is passed to the function.
+///
+pub struct NativeClosure {
+    name: String,
+    f: Option<SharedFn>,
+    copyable: bool,
+    droppable: bool,
+}
+impl NativeClosure {
+    /// Create a closure that is neither copyable nor droppable
+    ///
+    /// name: the name shown when the closure is printed
+    /// f: the function to call when the closure is resumed
+    ///
+    pub fn new(
+        name: impl Into<String>,
+        f: impl FnMut(&mut dyn Context, u8) -> Result<CodeRef, EvalError> + 'static,
+    ) -> Self {
+        NativeClosure {
+            name: name.into(),
+            f: Some(Rc::new(RefCell::new(f))),
+            copyable: false,
+            droppable: false,
+        }
+    }
+    /// Set whether variant 2 copies the closure
+    pub fn copyable(mut self, copyable: bool) -> Self {
+        self.copyable = copyable;
+        self
+    }
+    /// Set whether variant 1 drops the closure
+    pub fn droppable(mut self, droppable: bool) -> Self {
+        self.droppable = droppable;
+        self
+    }
+    pub fn into_value(self) -> Box<dyn Value> {
+        Box::new(self)
+    }
+}
+impl Clone for NativeClosure {
+    fn clone(&self) -> Self {
+        NativeClosure {
+            name: self.name.clone(),
+            f: self.f.clone(),
+            copyable: self.copyable,
+            droppable: self.droppable,
+        }
+    }
+}
+impl Debug for NativeClosure {
+    fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+        write!(fmt, "{}", self)
+    }
+}
+impl Display for NativeClosure {
+    fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+        write!(fmt, "{}", self.name)?;
+        match (self.copyable, self.droppable) {
+            (true, true) => write!(fmt, "(copy,drop)"),
+            (true, false) => write!(fmt, "(copy)"),
+            (false, true) => write!(fmt, "(drop)"),
+            (false, false) => Ok(()),
+        }
+    }
+}
+impl Value for NativeClosure {
+    fn take(&mut self) -> Box<dyn Value> {
+        Box::new(NativeClosure {
+            name: self.name.clone(),
+            f: self.f.take(),
+            copyable: self.copyable,
+            droppable: self.droppable,
+        })
+    }
+    fn copy_value(&self) -> Option<Box<dyn Value>> {
+        if self.copyable && self.f.is_some() {
+            Some(Box::new(self.clone()))
+        } else {
+            None
+        }
+    }
+    fn is_droppable(&self) -> bool {
+        self.droppable
+    }
+}
+impl Callable for NativeClosure {
+    fn call(self: Box<Self>, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
+        let f = match &self.f {
+            Some(f) => f.clone(),
+            None => return Err(EvalError::CallingWrapped),
+        };
+        if variant == 1 && self.droppable {
+            ctx.expect_args(1)?;
+            let cont = ctx.pop()?;
+            return eval_closure(cont, ctx, 0);
+        }
+        if variant == 2 && self.copyable {
+            ctx.expect_args(1)?;
+            let cont = ctx.pop()?;
+            ctx.push(Box::new(self.as_ref().clone()));
+            ctx.push(self);
+            return eval_closure(cont, ctx, 0);
+        }
+        let mut f = f
+            .try_borrow_mut()
+            .map_err(|_| EvalError::ReentrantCall(self.name.clone()))?;
+        (*f)(ctx, variant)
+    }
+}
@@ -1,33 +1,34 @@
 use super::{CodeRef, Context, Value};
+use crate::callable::Callable;
 use crate::error::EvalError;
 use core::fmt::Debug;
 use core::fmt::Display;
 use core::fmt::Formatter;
-use core::mem::replace;
 
-pub(super) struct WrappedFn<F>(pub(super) String, pub(super) Option<F>);
-impl<F> Debug for WrappedFn<F>
-where
-    F: FnOnce(&mut dyn Context, u8) -> Result<CodeRef, EvalError>,
-{
+/// The function of a native closure, receiving the variant it is resumed with
+pub(crate) type NativeFn = Box<dyn FnOnce(&mut dyn Context, u8) -> Result<CodeRef, EvalError>>;
+
+pub(crate) struct WrappedFn(pub(super) String, pub(super) Option<NativeFn>);
+impl Debug for WrappedFn {
     fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
         write!(fmt, "{}", self.0)
     }
 }
-impl<F> Display for WrappedFn<F>
-where
-    F: FnOnce(&mut dyn Context, u8) -> Result<CodeRef, EvalError>,
-{
+impl Display for WrappedFn {
     fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
         write!(fmt, "{}", self.0)
     }
 }
-impl<F> Value for WrappedFn<F>
-where
-    F: FnOnce(&mut dyn Context, u8) -> Result<CodeRef, EvalError> + 'static,
-{
+impl Value for WrappedFn {
     fn take(&mut self) -> Box<dyn Value> {
-        let name = replace(&mut self.0, "Terminate".into());
-        Box::new(WrappedFn(name, self.1.take()))
+        Box::new(WrappedFn(self.0.clone(), self.1.take()))
+    }
+}
+impl Callable for WrappedFn {
+    fn call(mut self: Box<Self>, ctx: &mut dyn Context, variant: u8) -> Result<CodeRef, EvalError> {
+        match self.1.take() {
+            Some(f) => f(ctx, variant),
+            None => Err(EvalError::CallingWrapped),
+        }
     }
 }
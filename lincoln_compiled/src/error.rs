@@ -1,5 +1,6 @@
-use crate::references::{EntryRef, ExternRef, GroupRef};
-use lincoln_common::ValueAccessError;
+use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
+use crate::report::EvalReport;
+use lincoln_common::{Permutation, ValueAccessError};
 
 use failure::Error;
 
@@ -11,6 +12,9 @@ pub enum BuildError {
 
     #[fail(display = "Given variant {} exceed limit {}", given, max)]
     VariangOutOfRange { max: u8, given: u8 },
+
+    #[fail(display = "{} is not a jump or call instruction", _0)]
+    NoTarget(CodeRef),
 }
 
 /// Errors may occur during evaluation
@@ -28,6 +32,29 @@ pub enum EvalError {
     #[fail(display = "Calling a wrapped value")]
     CallingWrapped,
 
+    #[fail(display = "Native closure {} is resumed while it is running", _0)]
+    ReentrantCall(String),
+
+    #[fail(
+        display = "Cannot copy {}: captured value {} of type {} cannot be copied",
+        closure, index, type_name
+    )]
+    NotCopyable {
+        closure: String,
+        index: u8,
+        type_name: &'static str,
+    },
+
+    #[fail(
+        display = "Cannot drop {}: captured value {} of type {} cannot be dropped",
+        closure, index, type_name
+    )]
+    NotDroppable {
+        closure: String,
+        index: u8,
+        type_name: &'static str,
+    },
+
     #[fail(display = "{}", _0)]
     CodeRef(CodeRefError),
 
@@ -36,6 +63,38 @@ pub enum EvalError {
 
     #[fail(display = "{}", _0)]
     External(Error),
+
+    #[fail(display = "{} in {}", error, location)]
+    At {
+        location: String,
+        error: Box<EvalError>,
+        report: Box<EvalReport>,
+    },
+}
+impl EvalError {
+    /// The cause of the error, without the location and report attached
+    /// by the evaluator. Match on this rather than on the error itself.
+    ///
+    pub fn kind(&self) -> &EvalError {
+        match self {
+            EvalError::At { error, .. } => error.kind(),
+            e => e,
+        }
+    }
+    /// The description of the failed entry, if attached
+    pub fn location(&self) -> Option<&str> {
+        match self {
+            EvalError::At { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+    /// The diagnostic report of the failed step, if attached
+    pub fn report(&self) -> Option<&EvalReport> {
+        match self {
+            EvalError::At { report, .. } => Some(report),
+            _ => None,
+        }
+    }
 }
 impl From<CodeRefError> for EvalError {
     fn from(e: CodeRefError) -> Self {
@@ -63,3 +122,126 @@ pub enum CodeRefError {
     #[fail(display = "Only extern code reference can be put in auto-wrapping closure")]
     CodeRefNotExtern,
 }
+
+/// Errors may occur when rewinding a machine
+#[derive(Fail, Debug)]
+pub enum RewindError {
+    #[fail(display = "The machine does not record history")]
+    NoHistory,
+
+    #[fail(display = "Replay failed at step {}: {}", step, error)]
+    ReplayFailed { step: usize, error: EvalError },
+
+    #[fail(
+        display = "Replay diverged at step {}: expect {:?}, actual {:?}",
+        step, expect, actual
+    )]
+    Diverged {
+        step: usize,
+        expect: CodeRef,
+        actual: CodeRef,
+    },
+}
+
+/// Errors may occur when loading a compiled program
+#[derive(Fail, Debug)]
+pub enum LoadError {
+    #[fail(display = "Extern not found: {}", name)]
+    MissingExtern { name: String },
+
+    #[fail(display = "Extern declared more than once: {}", name)]
+    DuplicateExtern { name: String },
+
+    #[fail(
+        display = "Extern {} is declared as {} but provided as {}",
+        name, expect, actual
+    )]
+    MismatchedExtern {
+        name: String,
+        expect: &'static str,
+        actual: &'static str,
+    },
+}
+
+/// Errors may occur when decoding a bytecode program
+#[derive(Fail, Debug)]
+pub enum BytecodeError {
+    #[fail(display = "Not a Lincoln bytecode file")]
+    BadMagic,
+
+    #[fail(display = "Unsupported bytecode version {}", _0)]
+    UnsupportedVersion(u16),
+
+    #[fail(display = "Checksum mismatch: expect {:08x}, actual {:08x}", expect, actual)]
+    ChecksumMismatch { expect: u32, actual: u32 },
+
+    #[fail(display = "Unexpected end of bytecode")]
+    UnexpectedEnd,
+
+    #[fail(display = "Unexpected bytes after the end of bytecode")]
+    TrailingBytes,
+
+    #[fail(display = "Number too large at offset {}", _0)]
+    NumberOverflow(usize),
+
+    #[fail(display = "Invalid {} tag {} at offset {}", what, tag, offset)]
+    InvalidTag {
+        what: &'static str,
+        tag: u8,
+        offset: usize,
+    },
+
+    #[fail(display = "Invalid permutation {} at offset {}", value, offset)]
+    InvalidPermutation { value: u64, offset: usize },
+
+    #[fail(display = "Invalid UTF-8 string at offset {}", _0)]
+    InvalidString(usize),
+
+    #[fail(display = "{} index {} out of range, {} defined", what, index, len)]
+    IndexOutOfRange {
+        what: &'static str,
+        index: usize,
+        len: usize,
+    },
+}
+
+/// Problems found when validating a compiled program
+#[derive(Fail, Debug)]
+pub enum ValidationError {
+    #[fail(display = "{} refers to {}, which is not defined", at, target)]
+    DanglingCodeRef { at: EntryRef, target: CodeRef },
+
+    #[fail(display = "{} permutates more than 20 values", at)]
+    InvalidPermutation { at: EntryRef, per: Permutation },
+
+    #[fail(display = "{} refers to {}, which is not defined", at, group)]
+    DanglingGroup { at: EntryRef, group: GroupRef },
+
+    #[fail(display = "Variant {} of {} is {}, which is not defined", variant, group, target)]
+    DanglingGroupEntry {
+        group: GroupRef,
+        variant: u8,
+        target: CodeRef,
+    },
+
+    #[fail(display = "Export {} refers to {}, which is not defined", name, group)]
+    DanglingExport { name: String, group: GroupRef },
+
+    #[fail(display = "Export {} refers to the empty group {}", name, group)]
+    EmptyExport { name: String, group: GroupRef },
+
+    #[fail(display = "{} refers to the empty group {}", at, group)]
+    EmptyGroup { at: EntryRef, group: GroupRef },
+
+    #[fail(
+        display = "{} returns variant {} to {} called from {}, which has {} variants",
+        at, variant, group, call, max
+    )]
+    VariantOutOfRange {
+        at: EntryRef,
+        call: EntryRef,
+        group: GroupRef,
+        variant: u8,
+        max: usize,
+    },
+}
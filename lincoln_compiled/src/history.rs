@@ -0,0 +1,84 @@
+use crate::references::CodeRef;
+use core::fmt::{Display, Formatter};
+use lincoln_common::Context;
+
+/// The state of a machine right before a step was evaluated.
+///
+/// Values in a context cannot be cloned in general, so the context
+/// is kept in its printed form for inspection.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The number of steps evaluated before this state
+    pub step: usize,
+    /// The entry about to be evaluated
+    pub current: CodeRef,
+    /// The printed context
+    pub context: String,
+}
+impl Display for Snapshot {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "{}: {:?} {}", self.step, self.current, self.context)
+    }
+}
+
+/// The steps a machine went through since it started.
+///
+/// Evaluating an external function consumes its values, so steps cannot be
+/// undone in general. Instead, the history keeps a way to rebuild the
+/// initial context, and a machine is rewound by replaying from the start.
+/// External functions are called again during the replay.
+///
+pub struct History {
+    initial: Box<dyn Fn() -> Box<dyn Context>>,
+    start: CodeRef,
+    snapshots: Vec<Snapshot>,
+}
+impl History {
+    /// Create an empty history
+    ///
+    /// initial: creates the initial context each time the execution is replayed
+    /// start: the entry the execution starts with
+    ///
+    pub fn new(initial: impl Fn() -> Box<dyn Context> + 'static, start: CodeRef) -> Self {
+        History {
+            initial: Box::new(initial),
+            start,
+            snapshots: vec![],
+        }
+    }
+    /// The entry the execution starts with
+    pub fn start(&self) -> CodeRef {
+        self.start
+    }
+    /// Create a fresh initial context
+    pub fn initial_context(&self) -> Box<dyn Context> {
+        (self.initial)()
+    }
+    /// The recorded states, one for each evaluated step
+    pub fn snapshots(&self) -> &[Snapshot] {
+        &self.snapshots
+    }
+    /// The recorded state before a step was evaluated
+    ///
+    /// step: the number of steps evaluated before the state
+    ///
+    pub fn snapshot(&self, step: usize) -> Option<&Snapshot> {
+        self.snapshots.get(step)
+    }
+    /// Forget the states recorded after a step
+    pub(crate) fn forget_after(&mut self, step: usize) {
+        self.snapshots.truncate(step + 1);
+    }
+    /// Record the state before a step. Any states recorded after this
+    /// step are forgotten, as the execution may have been rewound.
+    ///
+    pub(crate) fn record(&mut self, step: usize, current: CodeRef, ctx: &dyn Context) {
+        self.snapshots.truncate(step);
+        self.snapshots.push(Snapshot {
+            step,
+            current,
+            context: ctx.to_string(),
+        });
+    }
+}
@@ -0,0 +1,170 @@
+use crate::entries::{CodeGroup, Entry, ExportEntry, ExternEntry};
+use crate::error::LoadError;
+use crate::labels::Labels;
+use crate::program::Program;
+use std::collections::HashMap;
+
+/// The declaration of an external function in a serialized program.
+/// It is what remains of an `ExternEntry` after serialization.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ExternDecl {
+    Eval { name: String },
+    Value { name: String },
+}
+impl ExternDecl {
+    /// The name of the external function
+    pub fn name(&self) -> &str {
+        match self {
+            ExternDecl::Eval { name } => name,
+            ExternDecl::Value { name } => name,
+        }
+    }
+    fn kind(&self) -> &'static str {
+        match self {
+            ExternDecl::Eval { .. } => "eval",
+            ExternDecl::Value { .. } => "value",
+        }
+    }
+}
+impl<'a> From<&'a ExternEntry> for ExternDecl {
+    fn from(ext: &'a ExternEntry) -> Self {
+        match ext {
+            ExternEntry::Eval { name, .. } => ExternDecl::Eval { name: name.clone() },
+            ExternEntry::Value { name, .. } => ExternDecl::Value { name: name.clone() },
+        }
+    }
+}
+
+/// A compiled program as it is serialized, without the external functions.
+///
+/// Deserialize a program into an image, then `bind` it to a set
+/// of external functions to get a runnable `Program`.
+///
+#[derive(Serialize, Deserialize, Default)]
+pub struct ProgramImage {
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) externs: Vec<ExternDecl>,
+    pub(crate) exports: Vec<ExportEntry>,
+    pub(crate) groups: Vec<CodeGroup>,
+    #[serde(default)]
+    pub(crate) labels: Labels,
+}
+impl ProgramImage {
+    /// The external functions the program requires
+    pub fn externs(&self) -> impl Iterator<Item = &ExternDecl> {
+        self.externs.iter()
+    }
+    /// Bind the declared external functions by name.
+    /// External functions cannot be shared between entries, so a name
+    /// declared more than once is rejected.
+    ///
+    /// externs: the set of external functions. Functions not used by the program are ignored.
+    ///
+    pub fn bind(
+        self,
+        externs: impl IntoIterator<Item = ExternEntry>,
+    ) -> Result<Program, LoadError> {
+        let mut provided: HashMap<String, ExternEntry> = externs
+            .into_iter()
+            .map(|ext| (ext.name().into(), ext))
+            .collect();
+        let mut bound = vec![];
+        for decl in self.externs.iter() {
+            if bound
+                .iter()
+                .any(|ext: &ExternEntry| ext.name() == decl.name())
+            {
+                return Err(LoadError::DuplicateExtern {
+                    name: decl.name().into(),
+                });
+            }
+            let ext = provided
+                .remove(decl.name())
+                .ok_or_else(|| LoadError::MissingExtern {
+                    name: decl.name().into(),
+                })?;
+            let actual = ExternDecl::from(&ext);
+            if actual.kind() != decl.kind() {
+                return Err(LoadError::MismatchedExtern {
+                    name: decl.name().into(),
+                    expect: decl.kind(),
+                    actual: actual.kind(),
+                });
+            }
+            bound.push(ext);
+        }
+        Ok(Program {
+            entries: self.entries,
+            externs: bound,
+            exports: self.exports,
+            groups: self.groups,
+            labels: self.labels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProgramImage;
+    use crate::{CodeRef, EvalFn, ExternEntry, LoadError, Program, ValueFn};
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt};
+
+    fn swap_done() -> ExternEntry {
+        ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|c| {
+                let v = unwrap::<i32>(c.pop()?)?;
+                c.push(wrap(v * 10));
+                Ok(CodeRef::Termination)
+            }),
+        }
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut prog = Program::new();
+        let ext = prog.add_extern(swap_done());
+        let j = prog.add_jump(ext, lincoln_common::Permutation(1));
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, j).unwrap();
+        prog.add_export("main", g);
+        let json = serde_json::to_string(&prog).unwrap();
+
+        let image: ProgramImage = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            image.externs().map(|e| e.name()).collect::<Vec<_>>(),
+            vec!["done"]
+        );
+        let loaded = image.bind(vec![swap_done()]).unwrap();
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        loaded.run(&mut *ctx, "main", 0, None).unwrap();
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 10);
+
+        let image: ProgramImage = serde_json::from_str(&json).unwrap();
+        match image.bind(vec![]) {
+            Err(LoadError::MissingExtern { name }) => assert_eq!(name, "done"),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let image: ProgramImage = serde_json::from_str(&json).unwrap();
+        let value = ExternEntry::Value {
+            name: "done".into(),
+            value: ValueFn::stateless(|| wrap(0i32)),
+        };
+        match image.bind(vec![value]) {
+            Err(LoadError::MismatchedExtern { expect, actual, .. }) => {
+                assert_eq!((expect, actual), ("eval", "value"))
+            }
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        let mut image: ProgramImage = serde_json::from_str(&json).unwrap();
+        image.externs.push(image.externs[0].clone());
+        match image.bind(vec![swap_done()]) {
+            Err(LoadError::DuplicateExtern { name }) => assert_eq!(name, "done"),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+    }
+}
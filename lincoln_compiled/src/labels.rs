@@ -0,0 +1,216 @@
+use crate::entries::Entry;
+use crate::references::{CodeRef, EntryRef, GroupRef};
+use crate::store::CodeStore;
+use lincoln_common::StringLike;
+use std::collections::BTreeMap;
+
+/// Human readable names of the entries and groups of a compiled program,
+/// e.g. the labels they were given in the IR.
+///
+/// This is the debug information of a `Program`; externs are named
+/// by their external functions.
+///
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Labels {
+    entries: BTreeMap<EntryRef, String>,
+    groups: BTreeMap<GroupRef, String>,
+    #[serde(default)]
+    variants: BTreeMap<GroupRef, Vec<Option<String>>>,
+    #[serde(default)]
+    returns: BTreeMap<EntryRef, String>,
+}
+impl Labels {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Name an entry
+    ///
+    /// ent: the entry
+    /// label: the name
+    ///
+    pub fn set_entry(&mut self, ent: EntryRef, label: impl StringLike) {
+        self.entries.insert(ent, label.to_string());
+    }
+    /// Name a group
+    ///
+    /// grp: the group
+    /// label: the name
+    ///
+    pub fn set_group(&mut self, grp: GroupRef, label: impl StringLike) {
+        self.groups.insert(grp, label.to_string());
+    }
+    /// The name of an entry, if any
+    pub fn entry(&self, ent: EntryRef) -> Option<&str> {
+        self.entries.get(&ent).map(|s| s.as_str())
+    }
+    /// The name of a group, if any
+    pub fn group(&self, grp: GroupRef) -> Option<&str> {
+        self.groups.get(&grp).map(|s| s.as_str())
+    }
+    /// Name the variants of a group
+    ///
+    /// grp: the group
+    /// names: the names of the variants, None for unnamed variants
+    ///
+    pub fn set_variant_names(&mut self, grp: GroupRef, names: Vec<Option<String>>) {
+        self.variants.insert(grp, names);
+    }
+    /// The name of a variant of a group, if any
+    pub fn variant_name(&self, grp: GroupRef, variant: u8) -> Option<&str> {
+        self.variants
+            .get(&grp)
+            .and_then(|names| names.get(variant as usize))
+            .and_then(|name| name.as_deref())
+    }
+    /// Find a variant of a group by its name
+    pub fn variant(&self, grp: GroupRef, name: &str) -> Option<u8> {
+        self.variants
+            .get(&grp)
+            .and_then(|names| names.iter().position(|n| n.as_deref() == Some(name)))
+            .map(|v| v as u8)
+    }
+    /// Record the variant name a return entry was written with
+    ///
+    /// ent: the return entry
+    /// name: the name of the variant
+    ///
+    pub fn set_return_name(&mut self, ent: EntryRef, name: impl StringLike) {
+        self.returns.insert(ent, name.to_string());
+    }
+    /// The variant name a return entry was written with, if any
+    pub fn return_name(&self, ent: EntryRef) -> Option<&str> {
+        self.returns.get(&ent).map(|s| s.as_str())
+    }
+    /// Iterate all named entries
+    pub fn iterate_entries(&self) -> impl Iterator<Item = (EntryRef, &str)> {
+        self.entries.iter().map(|(e, l)| (*e, l.as_str()))
+    }
+    /// Iterate all named groups
+    pub fn iterate_groups(&self) -> impl Iterator<Item = (GroupRef, &str)> {
+        self.groups.iter().map(|(g, l)| (*g, l.as_str()))
+    }
+    /// Iterate all groups with named variants
+    pub fn iterate_variant_names(&self) -> impl Iterator<Item = (GroupRef, &[Option<String>])> {
+        self.variants.iter().map(|(g, n)| (*g, n.as_slice()))
+    }
+    /// Iterate all return entries written with variant names
+    pub fn iterate_return_names(&self) -> impl Iterator<Item = (EntryRef, &str)> {
+        self.returns.iter().map(|(e, n)| (*e, n.as_str()))
+    }
+    /// Whether nothing is named
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+            && self.groups.is_empty()
+            && self.variants.is_empty()
+            && self.returns.is_empty()
+    }
+    /// Describe a code reference. Entries use their names if known,
+    /// externs use the names of the external functions.
+    ///
+    /// prog: the program the code reference belongs to
+    /// c: the code reference
+    ///
+    pub fn describe(&self, prog: &dyn CodeStore, c: CodeRef) -> String {
+        match c {
+            CodeRef::Entry(e) => self
+                .entry(e)
+                .map(String::from)
+                .unwrap_or_else(|| format!("{}", e)),
+            CodeRef::Extern(e) => prog
+                .extern_entry(e)
+                .map(|ext| ext.name().into())
+                .unwrap_or_else(|| format!("{}", e)),
+            CodeRef::Termination => format!("{}", c),
+        }
+    }
+    /// Describe a group, using its name if known
+    pub fn describe_group(&self, grp: GroupRef) -> String {
+        self.group(grp)
+            .map(String::from)
+            .unwrap_or_else(|| format!("{}", grp))
+    }
+    /// Describe a code reference together with its instruction,
+    /// in the syntax of the IR, e.g. `fact_loop (ret 1)`
+    ///
+    /// prog: the program the code reference belongs to
+    /// c: the code reference
+    ///
+    pub fn locate(&self, prog: &dyn CodeStore, c: CodeRef) -> String {
+        let name = self.describe(prog, c);
+        match c {
+            CodeRef::Entry(e) => match prog.entry(e) {
+                Some(Entry::Jump { cont, .. }) => {
+                    format!("{} (jmp {})", name, self.describe(prog, *cont))
+                }
+                Some(Entry::Call {
+                    call,
+                    cont,
+                    num_args,
+                }) => format!(
+                    "{} (call {} {} {})",
+                    name,
+                    self.describe(prog, *call),
+                    num_args,
+                    self.describe_group(*cont)
+                ),
+                Some(Entry::Return { variant }) => match self.return_name(e) {
+                    Some(variant) => format!("{} (ret {})", name, variant),
+                    None => format!("{} (ret {})", name, variant),
+                },
+                None => name,
+            },
+            CodeRef::Extern(_) => format!("{} (extern)", name),
+            CodeRef::Termination => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Labels;
+    use crate::{CodeRef, EvalError, EvalFn, ExternEntry, Program, ProgramImage};
+    use lincoln_common::{default_context, wrap, ContextExt, Permutation};
+
+    #[test]
+    fn test_labels() {
+        let mut prog = Program::new();
+        let fail = prog.add_extern(ExternEntry::Eval {
+            name: "fail".into(),
+            eval: EvalFn::stateless(|c| {
+                let _ = c.pop()?;
+                Ok(CodeRef::Termination)
+            }),
+        });
+        let ret = prog.add_return(1);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, ret).unwrap();
+        let j = prog.add_jump(fail, Permutation(0));
+        prog.add_group_entry(g, j).unwrap();
+        prog.add_export("main", g);
+        let mut labels = Labels::new();
+        if let (CodeRef::Entry(ret), CodeRef::Entry(j)) = (ret, j) {
+            labels.set_entry(ret, "fact_loop");
+            labels.set_entry(j, "fail_now");
+        }
+        labels.set_group(g, "main");
+        prog.set_labels(labels.clone());
+        assert_eq!(prog.labels().locate(&prog, ret), "fact_loop (ret 1)");
+        assert_eq!(prog.labels().locate(&prog, j), "fail_now (jmp fail)");
+        assert_eq!(prog.labels().locate(&prog, fail), "fail (extern)");
+        assert!(format!("{:?}", prog).contains("🎯-0 fact_loop: Return"));
+
+        let json = serde_json::to_string(&prog).unwrap();
+        let image: ProgramImage = serde_json::from_str(&json).unwrap();
+        assert_eq!(image.labels, labels);
+
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        let err = prog.eval(&mut *ctx, &ret).unwrap_err();
+        assert!(format!("{}", err).ends_with(" in fact_loop (ret 1)"));
+        assert_eq!(err.location(), Some("fact_loop (ret 1)"));
+        match err.kind() {
+            EvalError::CallingWrapped => (),
+            e => panic!("unexpected error {}", e),
+        }
+    }
+}
@@ -8,18 +8,49 @@ extern crate smallvec;
 #[macro_use]
 extern crate log;
 
+mod arena_program;
+mod bytecode;
+mod callable;
 mod closure;
+mod debugger;
 mod entries;
 mod error;
+mod history;
+mod image;
+mod labels;
+mod linearity;
+mod machine;
+mod observer;
+mod profiler;
 mod program;
 mod references;
+mod report;
+mod store;
+mod trace;
+mod validate;
 
-pub use entries::{EvalFn, ExternEntry, ValueFn};
-pub use error::{BuildError, CodeRefError, EvalError};
+pub use debugger::{BreakCondition, BreakLocation, Breakpoint, DebugStop, Debugger};
+pub use entries::{Entry, EvalFn, ExternEntry, ValueFn};
+pub use arena_program::{ArenaProgram, ProgramArena};
+pub use bytecode::BYTECODE_VERSION;
+pub use error::{BuildError, BytecodeError, CodeRefError, EvalError, LoadError, RewindError,
+    ValidationError,
+};
+pub use history::{History, Snapshot};
+pub use image::{ExternDecl, ProgramImage};
+pub use labels::Labels;
+pub use linearity::{LeakReport, LinearityChecker};
+pub use machine::{Machine, MachineState};
+pub use observer::{ClosureId, EvalEvent, EvalObserver};
+pub use profiler::{EntryProfile, ExternProfile, ProfileReport, Profiler, VariantProfile};
 pub use lincoln_common::Access;
-pub use program::Program;
-pub use references::{CodeRef, GroupRef};
-pub use entries::native_closure;
+pub use program::{PendingTarget, Program};
+pub use store::CodeStore;
+pub use references::{CodeRef, EntryRef, ExternRef, GroupRef};
+pub use report::{ContinuationFrame, EvalReport};
+pub use trace::{TraceFormat, TraceRecord, TraceWriter};
+pub use validate::{follow_continuation, Follow};
+pub use entries::{native_closure, NativeClosure};
 pub use closure::eval_closure;
 
 /// The crate contains definitions for a "compiled" prgram,
@@ -0,0 +1,196 @@
+use crate::observer::{EvalEvent, EvalObserver};
+use crate::store::CodeStore;
+use core::fmt::{Display, Formatter};
+use core::mem::take;
+use lincoln_common::{Context, DropTracker, Leak, Linearity};
+
+/// An observer checking that linear values are used exactly once.
+///
+/// Until the execution terminates, wrapped host values that are linear
+/// (see `lincoln_common::register_linear`) are recorded when they are
+/// dropped without being used. When it terminates, the values that are
+/// not allowed to disappear but are still in the context are recorded too.
+///
+/// Drops are tracked per thread, so values dropped by other executions
+/// on the thread while the checker is alive are recorded as well.
+/// Checkers of nested executions each record their own drops.
+///
+pub struct LinearityChecker {
+    tracker: DropTracker,
+    terminated: bool,
+    remaining: Vec<Leak>,
+    dropped: Vec<Leak>,
+}
+impl Default for LinearityChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl LinearityChecker {
+    pub fn new() -> Self {
+        LinearityChecker {
+            tracker: DropTracker::start(),
+            terminated: false,
+            remaining: vec![],
+            dropped: vec![],
+        }
+    }
+    /// Stop checking, and report the values that disappeared.
+    /// Drops after the termination are not reported, so values remaining
+    /// at termination are only reported as remaining, even if the context
+    /// was dropped before.
+    ///
+    pub fn finish(mut self) -> LeakReport {
+        if !self.terminated {
+            self.dropped = self.tracker.take_dropped();
+        }
+        LeakReport {
+            remaining: take(&mut self.remaining),
+            dropped: take(&mut self.dropped),
+        }
+    }
+}
+impl EvalObserver for LinearityChecker {
+    fn observe(&mut self, _: &dyn CodeStore, event: &EvalEvent, ctx: &dyn Context) {
+        if let EvalEvent::Termination { .. } = event {
+            self.terminated = true;
+            self.dropped = self.tracker.take_dropped();
+            self.remaining = (0..ctx.len())
+                .filter_map(|i| ctx.get(i))
+                .filter(|v| v.linearity() == Linearity::Linear)
+                .map(Leak::of)
+                .collect();
+        }
+    }
+}
+
+/// The linear values that disappeared during an execution
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LeakReport {
+    /// Values still in the context when the execution terminated
+    pub remaining: Vec<Leak>,
+    /// Values dropped without being used
+    pub dropped: Vec<Leak>,
+}
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty() && self.dropped.is_empty()
+    }
+}
+impl Display for LeakReport {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(fmt, "no leaks");
+        }
+        for leak in self.remaining.iter() {
+            writeln!(fmt, "remaining at termination: {}", leak)?;
+        }
+        for leak in self.dropped.iter() {
+            writeln!(fmt, "dropped without being used: {}", leak)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LinearityChecker;
+    use crate::{native_closure, CodeRef, Machine, MachineState, Program};
+    use core::fmt::{Display, Formatter};
+    use lincoln_common::{default_context, register_affine, register_linear, wrap, ContextExt};
+
+    #[derive(Debug)]
+    struct Handle(&'static str);
+    impl Display for Handle {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "{}", self.0)
+        }
+    }
+    #[derive(Debug)]
+    struct Scratch;
+    impl Display for Scratch {
+        fn fmt(&self, fmt: &mut Formatter) -> core::fmt::Result {
+            write!(fmt, "scratch")
+        }
+    }
+
+    #[test]
+    fn test_leaks() {
+        register_linear::<Handle>();
+        register_affine::<Scratch>();
+
+        // The continuation drops a linear value and leaves another one
+        let mut prog = Program::new();
+        let ret = prog.add_return(0);
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, ret).unwrap();
+        prog.add_export("main", main);
+        let mut ctx = default_context();
+        ctx.push(wrap(Handle("socket")));
+        ctx.push(wrap(Scratch));
+        ctx.push(wrap(Handle("file")));
+        ctx.push(native_closure("cont", |ctx, _| {
+            let _ = ctx.pop()?;
+            let _ = ctx.pop()?;
+            Ok(CodeRef::Termination)
+        }));
+
+        let mut checker = LinearityChecker::new();
+        let mut machine = Machine::start(&prog, ctx, "main", 0).unwrap();
+        match machine.run_observed(&mut checker) {
+            MachineState::Terminated => (),
+            _ => panic!("not terminated"),
+        }
+        // The values remaining are not reported twice when the context is dropped
+        drop(machine);
+        let report = checker.finish();
+        assert_eq!(report.remaining.len(), 1);
+        assert_eq!(report.remaining[0].value, "|socket|");
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].value, "|file|");
+        assert_eq!(
+            format!("{}", report),
+            format!(
+                "remaining at termination: |socket| of type {0}\n\
+                 dropped without being used: |file| of type {0}\n",
+                core::any::type_name::<Handle>()
+            )
+        );
+
+        // Drops are not tracked after finishing
+        drop(wrap(Handle("late")));
+        assert!(LinearityChecker::new().finish().is_empty());
+    }
+
+    #[test]
+    fn test_equal_values() {
+        register_linear::<Handle>();
+
+        // The continuation drops one of two equal linear values
+        let mut prog = Program::new();
+        let ret = prog.add_return(0);
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, ret).unwrap();
+        prog.add_export("main", main);
+        let mut ctx = default_context();
+        ctx.push(wrap(Handle("socket")));
+        ctx.push(wrap(Handle("socket")));
+        ctx.push(native_closure("cont", |ctx, _| {
+            let _ = ctx.pop()?;
+            Ok(CodeRef::Termination)
+        }));
+
+        let mut checker = LinearityChecker::new();
+        let mut machine = Machine::start(&prog, ctx, "main", 0).unwrap();
+        match machine.run_observed(&mut checker) {
+            MachineState::Terminated => (),
+            _ => panic!("not terminated"),
+        }
+        // Finishing while the machine, and the remaining value, are alive
+        let report = checker.finish();
+        drop(machine);
+        assert_eq!(report.remaining.len(), 1);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0], report.remaining[0]);
+    }
+}
@@ -0,0 +1,411 @@
+use crate::history::History;
+use crate::observer::{EvalObserver, EventSink};
+use crate::program::eval_step;
+use crate::references::CodeRef;
+use crate::store::{export_entry, CodeStore};
+use crate::{EvalError, RewindError};
+use core::fmt::{Display, Formatter};
+use failure::Error;
+use lincoln_common::{Context, StringLike};
+
+/// The state of a `Machine` after it has been given some fuel to run.
+///
+#[derive(Debug)]
+pub enum MachineState {
+    /// The fuel was used up before reaching the end. The machine can be resumed.
+    Paused,
+    /// The execution reached the end.
+    Terminated,
+    /// The evaluation failed. The machine stays on the failing entry,
+    /// but the context may have been partially modified.
+    Failed(EvalError),
+}
+
+/// A resumable execution of a compiled program.
+///
+/// It holds the program (any `CodeStore`, e.g. `Program`, `&Program`,
+/// `Rc<Program>` or `ArenaProgram`), the current context,
+/// the current code entry and the number of steps executed so far.
+/// A machine created with a `History` can also be rewound to earlier steps.
+///
+/// Closures created by the machine report their events to the observer
+/// of the step in which they are created or resumed, including steps
+/// of external functions resuming them.
+///
+pub struct Machine<P> {
+    program: P,
+    context: Box<dyn Context>,
+    current: CodeRef,
+    steps: usize,
+    history: Option<History>,
+    events: EventSink,
+}
+impl<P> Display for Machine<P>
+where
+    P: CodeStore,
+{
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        let location = self.program.labels().locate(&self.program, self.current);
+        write!(fmt, "{}: {} {}", self.steps, location, self.context)
+    }
+}
+impl<P> Machine<P>
+where
+    P: CodeStore,
+{
+    /// Create a machine that will start from a given entry
+    ///
+    /// program: the program to run
+    /// context: the initial values
+    /// current: the entry to start with
+    ///
+    pub fn new(program: P, context: Box<dyn Context>, current: CodeRef) -> Self {
+        Machine {
+            program,
+            context,
+            current,
+            steps: 0,
+            history: None,
+            events: EventSink::new(),
+        }
+    }
+    /// Create a machine that records its history, starting from the beginning of the history
+    ///
+    /// program: the program to run
+    /// history: the history to record into
+    ///
+    pub fn with_history(program: P, mut history: History) -> Self {
+        let context = history.initial_context();
+        let current = history.start();
+        history.record(0, current, &*context);
+        Machine {
+            program,
+            context,
+            current,
+            steps: 0,
+            history: Some(history),
+            events: EventSink::new(),
+        }
+    }
+    /// Create a machine that records its history, starting from an exported entry
+    ///
+    /// program: the program to run
+    /// initial: creates the initial values, each time the execution is replayed
+    /// export_label: the name of the exported entry
+    /// variant: the variant of the exported entry
+    ///
+    pub fn start_with_history(
+        program: P,
+        initial: impl Fn() -> Box<dyn Context> + 'static,
+        export_label: impl StringLike,
+        variant: u8,
+    ) -> Result<Self, Error> {
+        let current = export_entry(&program, export_label.as_str(), variant)?;
+        Ok(Self::with_history(program, History::new(initial, current)))
+    }
+    /// Create a machine that will start from an exported entry
+    ///
+    /// program: the program to run
+    /// context: the initial values
+    /// export_label: the name of the exported entry
+    /// variant: the variant of the exported entry
+    ///
+    pub fn start(
+        program: P,
+        context: Box<dyn Context>,
+        export_label: impl StringLike,
+        variant: u8,
+    ) -> Result<Self, Error> {
+        let current = export_entry(&program, export_label.as_str(), variant)?;
+        Ok(Self::new(program, context, current))
+    }
+    /// The program being run
+    pub fn program(&self) -> &P {
+        &self.program
+    }
+    /// The current context
+    pub fn context(&self) -> &dyn Context {
+        &*self.context
+    }
+    /// The current context, mutable
+    pub fn context_mut(&mut self) -> &mut dyn Context {
+        &mut *self.context
+    }
+    /// The entry to be evaluated next
+    pub fn current(&self) -> CodeRef {
+        self.current
+    }
+    /// The number of steps evaluated so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+    /// The recorded history, if the machine was created with one
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+    /// Whether the execution reached the end
+    pub fn is_terminated(&self) -> bool {
+        self.current == CodeRef::Termination
+    }
+    /// Evaluate a single step
+    pub fn step(&mut self) -> MachineState {
+        self.run_for(1)
+    }
+    /// Evaluate at most `fuel` steps
+    ///
+    /// fuel: the maximum number of steps to evaluate
+    ///
+    pub fn run_for(&mut self, fuel: usize) -> MachineState {
+        self.run_for_observed(fuel, &mut ())
+    }
+    /// Evaluate until the execution terminates or fails
+    pub fn run(&mut self) -> MachineState {
+        self.run_observed(&mut ())
+    }
+    /// Evaluate at most `fuel` steps, reporting to an observer
+    ///
+    /// fuel: the maximum number of steps to evaluate
+    /// observer: receives the evaluation events
+    ///
+    pub fn run_for_observed(
+        &mut self,
+        fuel: usize,
+        observer: &mut dyn EvalObserver,
+    ) -> MachineState {
+        for _ in 0..fuel {
+            if let Some(state) = self.advance(observer) {
+                return state;
+            }
+        }
+        if self.is_terminated() {
+            MachineState::Terminated
+        } else {
+            MachineState::Paused
+        }
+    }
+    /// Evaluate until the execution terminates or fails, reporting to an observer
+    ///
+    /// observer: receives the evaluation events
+    ///
+    pub fn run_observed(&mut self, observer: &mut dyn EvalObserver) -> MachineState {
+        loop {
+            if let Some(state) = self.advance(observer) {
+                return state;
+            }
+        }
+    }
+    /// Go back a number of steps
+    ///
+    /// n: the number of steps to go back. Stops at the start if there are fewer steps.
+    ///
+    pub fn back(&mut self, n: usize) -> Result<(), RewindError> {
+        self.rewind(self.steps.saturating_sub(n))
+    }
+    /// Rewind to the state before a step was evaluated, by replaying
+    /// the execution from the start of the history
+    ///
+    /// External functions are called again during the replay, so their
+    /// side effects happen again. If they return differently than before,
+    /// the replay stops with `RewindError::Diverged`.
+    ///
+    /// step: the number of steps evaluated before the state
+    ///
+    pub fn rewind(&mut self, step: usize) -> Result<(), RewindError> {
+        let history = self.history.as_mut().ok_or(RewindError::NoHistory)?;
+        let step = step.min(self.steps);
+        // The replay records the history again, so keep the entries to compare with
+        let expected: Vec<_> = history.snapshots()[1..=step]
+            .iter()
+            .map(|s| s.current)
+            .collect();
+        history.forget_after(step);
+        self.context = history.initial_context();
+        self.current = history.start();
+        self.steps = 0;
+        self.events = EventSink::new();
+        for expect in expected {
+            if let Some(MachineState::Failed(error)) = self.advance(&mut ()) {
+                return Err(RewindError::ReplayFailed {
+                    step: self.steps,
+                    error,
+                });
+            }
+            if expect != self.current {
+                return Err(RewindError::Diverged {
+                    step: self.steps,
+                    expect,
+                    actual: self.current,
+                });
+            }
+        }
+        Ok(())
+    }
+    /// Destruct the machine into the program, context and the current entry
+    pub fn into_parts(self) -> (P, Box<dyn Context>, CodeRef) {
+        (self.program, self.context, self.current)
+    }
+
+    fn advance(&mut self, observer: &mut dyn EvalObserver) -> Option<MachineState> {
+        if self.is_terminated() {
+            return Some(MachineState::Terminated);
+        }
+        match eval_step(
+            &self.program,
+            &mut *self.context,
+            &self.current,
+            observer,
+            &self.events,
+        ) {
+            Ok(next) => {
+                self.current = next;
+                self.steps += 1;
+                if let Some(history) = &mut self.history {
+                    history.record(self.steps, self.current, &*self.context);
+                }
+                None
+            }
+            Err(e) => Some(MachineState::Failed(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Machine, MachineState};
+    use crate::history::History;
+    use crate::{CodeRef, CodeRefError, EvalError, EvalFn, ExternEntry, Program, RewindError};
+    use core::cell::Cell;
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt, Permutation};
+    use std::rc::Rc;
+
+    fn swap_program() -> Program {
+        let mut prog = Program::new();
+        let ext = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let j2 = prog.add_jump(ext, Permutation(1));
+        let j1 = prog.add_jump(j2, Permutation(1));
+        let j0 = prog.add_jump(j1, Permutation(1));
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, j0).unwrap();
+        prog.add_export("main", g);
+        prog
+    }
+
+    #[test]
+    fn test_run_for() {
+        let prog = swap_program();
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        ctx.push(wrap(2i32));
+        let mut m = Machine::start(&prog, ctx, "main", 0).unwrap();
+        match m.run_for(2) {
+            MachineState::Paused => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(m.steps(), 2);
+        match m.run_for(10) {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(m.steps(), 4);
+        assert!(m.is_terminated());
+        let (_, mut ctx, _) = m.into_parts();
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_back() {
+        let prog = swap_program();
+        let initial = || {
+            let mut ctx = default_context();
+            ctx.push(wrap(1i32));
+            ctx.push(wrap(2i32));
+            ctx
+        };
+        let mut m = Machine::start_with_history(&prog, initial, "main", 0).unwrap();
+        match m.run() {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        let snapshots = m.history().unwrap().snapshots().to_vec();
+        assert_eq!(snapshots.len(), 5);
+        m.back(2).unwrap();
+        assert_eq!(m.steps(), 2);
+        assert_eq!(m.current(), snapshots[2].current);
+        assert_eq!(m.context().to_string(), snapshots[2].context);
+        m.back(10).unwrap();
+        assert_eq!(m.steps(), 0);
+        assert_eq!(m.history().unwrap().snapshots().len(), 1);
+        m.run();
+        let (_, mut ctx, _) = m.into_parts();
+        assert_eq!(unwrap::<i32>(ctx.pop().unwrap()).unwrap(), 1);
+
+        let mut m = Machine::start(&prog, default_context(), "main", 0).unwrap();
+        m.step();
+        match m.back(1) {
+            Err(RewindError::NoHistory) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_diverged() {
+        // j1 -> j0 -> choose -> a or b -> done.
+        // `choose` goes to `a` the first time, and to `b` afterwards.
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let a = prog.add_jump(done, Permutation(0));
+        let b = prog.add_jump(done, Permutation(0));
+        let calls = Rc::new(Cell::new(0));
+        let c = calls.clone();
+        let choose = prog.add_extern(ExternEntry::Eval {
+            name: "choose".into(),
+            eval: EvalFn::stateful(Box::new(move |_| {
+                c.set(c.get() + 1);
+                Ok(if c.get() == 1 { a } else { b })
+            })),
+        });
+        let j0 = prog.add_jump(choose, Permutation(0));
+        let j1 = prog.add_jump(j0, Permutation(0));
+
+        let mut m = Machine::with_history(&prog, History::new(default_context, j1));
+        match m.run() {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(m.steps(), 5);
+        match m.rewind(4) {
+            Err(RewindError::Diverged {
+                step,
+                expect,
+                actual,
+            }) => {
+                assert_eq!(step, 3);
+                assert_eq!(expect, a);
+                assert_eq!(actual, b);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_failed() {
+        let prog = swap_program();
+        let mut m = Machine::new(&prog, default_context(), CodeRef::entry(10));
+        match m.run() {
+            MachineState::Failed(e) => match e.kind() {
+                EvalError::CodeRef(CodeRefError::EntryNotFound { .. }) => (),
+                e => panic!("unexpected error {}", e),
+            },
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(m.steps(), 0);
+        assert_eq!(m.current(), CodeRef::entry(10));
+    }
+}
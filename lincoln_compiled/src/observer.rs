@@ -0,0 +1,258 @@
+use crate::entries::{Entry, ExternEntry};
+use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
+use crate::store::CodeStore;
+use core::cell::RefCell;
+use core::fmt::{Debug, Display, Formatter};
+use lincoln_common::Context;
+use std::rc::Rc;
+
+/// Identifies a closure created during evaluation.
+///
+/// Ids are unique within a thread, so an observer can match the creation
+/// of a closure with its resumption.
+///
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ClosureId(pub usize);
+impl Debug for ClosureId {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "C🎁-{}", self.0)
+    }
+}
+impl Display for ClosureId {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "{:?}", self)
+    }
+}
+
+/// Events emitted when evaluating a program.
+///
+/// Events of a single step are emitted in the following order:
+/// `BeforeEntry`/`BeforeExtern`, then any closure events, then
+/// `AfterEntry`/`AfterExtern`, and finally `Termination` if the step
+/// ends the execution.
+///
+#[derive(Debug)]
+pub enum EvalEvent<'a> {
+    /// About to evaluate a `Jump`, `Call` or `Return` entry
+    BeforeEntry { at: EntryRef, entry: &'a Entry },
+    /// An entry was evaluated and the execution continues at `next`
+    AfterEntry {
+        at: EntryRef,
+        entry: &'a Entry,
+        next: CodeRef,
+    },
+    /// About to invoke an external function
+    BeforeExtern { at: ExternRef, ext: &'a ExternEntry },
+    /// An external function returned and the execution continues at `next`
+    AfterExtern {
+        at: ExternRef,
+        ext: &'a ExternEntry,
+        next: CodeRef,
+    },
+    /// A closure was created from a group, capturing `captured` values
+    ClosureCreated {
+        id: ClosureId,
+        group: GroupRef,
+        captured: u8,
+    },
+    /// A closure was resumed on a variant. `next` is the entry of
+    /// the variant, or `Termination`. Copying a closure keeps it,
+    /// so only the copy is reported, as created.
+    ClosureResumed {
+        id: ClosureId,
+        group: GroupRef,
+        variant: u8,
+        next: CodeRef,
+    },
+    /// The execution reached the end after evaluating `at`
+    Termination { at: CodeRef },
+}
+impl<'a> Display for EvalEvent<'a> {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        use EvalEvent::*;
+        match self {
+            BeforeEntry { at, entry } => write!(fmt, "▶ {} {}", at, entry),
+            AfterEntry { at, next, .. } => write!(fmt, "◀ {} => {}", at, next),
+            BeforeExtern { at, ext } => write!(fmt, "▶ {} {}", at, ext),
+            AfterExtern { at, next, .. } => write!(fmt, "◀ {} => {}", at, next),
+            ClosureCreated {
+                id,
+                group,
+                captured,
+            } => write!(fmt, "+ {} {} ({} captured)", id, group, captured),
+            ClosureResumed {
+                id,
+                group,
+                variant,
+                next,
+            } => write!(fmt, "- {} {}:{} => {}", id, group, variant, next),
+            Termination { at } => write!(fmt, "■ {}", at),
+        }
+    }
+}
+
+/// An observer receives structured events during evaluation.
+///
+/// It is given the code of the program being evaluated, the event
+/// and a view of the current context.
+///
+pub trait EvalObserver {
+    fn observe(&mut self, prog: &dyn CodeStore, event: &EvalEvent, ctx: &dyn Context);
+}
+/// The unit observer ignores all events.
+impl EvalObserver for () {
+    fn observe(&mut self, _: &dyn CodeStore, _: &EvalEvent, _: &dyn Context) {}
+}
+impl<F> EvalObserver for F
+where
+    F: FnMut(&dyn CodeStore, &EvalEvent, &dyn Context),
+{
+    fn observe(&mut self, prog: &dyn CodeStore, event: &EvalEvent, ctx: &dyn Context) {
+        self(prog, event, ctx)
+    }
+}
+
+/// Collects the closure events of an execution.
+///
+/// Closures can be created and resumed from within external functions
+/// (through `eval_closure`), so a closure keeps the sink of the execution
+/// that created it, and the evaluator passes the collected events to the
+/// observer after each step. Closures created by an execution without an
+/// observer get an inactive sink and record nothing.
+///
+#[derive(Clone, Default)]
+pub(crate) struct EventSink(Option<Rc<RefCell<Vec<EvalEvent<'static>>>>>);
+impl EventSink {
+    /// A sink collecting events
+    pub(crate) fn new() -> Self {
+        EventSink(Some(Default::default()))
+    }
+    /// A sink ignoring events
+    pub(crate) fn none() -> Self {
+        EventSink(None)
+    }
+    /// Whether an observer receives the events
+    pub(crate) fn is_active(&self) -> bool {
+        self.0.is_some()
+    }
+    pub(crate) fn record(&self, event: EvalEvent<'static>) {
+        if let Some(events) = &self.0 {
+            events.borrow_mut().push(event);
+        }
+    }
+    pub(crate) fn take(&self) -> Vec<EvalEvent<'static>> {
+        match &self.0 {
+            Some(events) => events.replace(vec![]),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EvalEvent;
+    use crate::{
+        eval_closure, CodeRef, CodeStore, EvalFn, ExternEntry, Machine, MachineState, Program,
+    };
+    use lincoln_common::{default_context, Context, ContextExt};
+
+    fn event_name(e: &EvalEvent) -> &'static str {
+        match e {
+            EvalEvent::BeforeEntry { .. } => "before_entry",
+            EvalEvent::AfterEntry { .. } => "after_entry",
+            EvalEvent::BeforeExtern { .. } => "before_extern",
+            EvalEvent::AfterExtern { .. } => "after_extern",
+            EvalEvent::ClosureCreated { .. } => "created",
+            EvalEvent::ClosureResumed { variant: 0, .. } => "resumed",
+            EvalEvent::ClosureResumed { .. } => "resumed_other",
+            EvalEvent::Termination { .. } => "termination",
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let ret = prog.add_return(0);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, done).unwrap();
+        let call = prog.add_call(ret, 0, g);
+
+        let mut events = vec![];
+        let mut observer =
+            |_: &dyn CodeStore, e: &EvalEvent, _: &dyn Context| events.push(event_name(e));
+        let mut m = Machine::new(&prog, default_context(), call);
+        match m.run_observed(&mut observer) {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(
+            events,
+            vec![
+                "before_entry",
+                "created",
+                "after_entry",
+                "before_entry",
+                "resumed",
+                "after_entry",
+                "before_extern",
+                "after_extern",
+                "termination"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_eval() {
+        // An external function resuming its continuation, then evaluating
+        // another program without an observer
+        let resume = ExternEntry::Eval {
+            name: "resume".into(),
+            eval: EvalFn::stateless(|ctx| {
+                let next = eval_closure(ctx.pop()?, ctx, 0)?;
+                let mut inner = Program::new();
+                let ret = inner.add_return(0);
+                let g = inner.add_empty_group();
+                inner.add_group_entry(g, ret).unwrap();
+                let call = inner.add_call(ret, 0, g);
+                let _ = inner.eval(&mut *default_context(), &call)?;
+                Ok(next)
+            }),
+        };
+        let mut prog = Program::new();
+        let resume = prog.add_extern(resume);
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, done).unwrap();
+        let call = prog.add_call(resume, 0, g);
+
+        let mut events = vec![];
+        let mut observer =
+            |_: &dyn CodeStore, e: &EvalEvent, _: &dyn Context| events.push(event_name(e));
+        let mut m = Machine::new(&prog, default_context(), call);
+        match m.run_observed(&mut observer) {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        assert_eq!(
+            events,
+            vec![
+                "before_entry",
+                "created",
+                "after_entry",
+                "before_extern",
+                "resumed",
+                "after_extern",
+                "before_extern",
+                "after_extern",
+                "termination"
+            ]
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use crate::observer::{EvalEvent, EvalObserver};
+use crate::references::{CodeRef, EntryRef, GroupRef};
+use crate::store::CodeStore;
+use core::fmt::{Display, Formatter};
+use failure::Error;
+use lincoln_common::Context;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Default, Clone, Copy)]
+struct ExternStats {
+    calls: usize,
+    time: Duration,
+}
+
+/// An observer that collects execution statistics.
+///
+/// It counts the evaluations of each entry, each external function
+/// and each group variant being resumed, measures the time spent in
+/// external functions, and tracks the peak context length and the number
+/// of closures created.
+///
+#[derive(Default)]
+pub struct Profiler {
+    steps: usize,
+    entries: HashMap<EntryRef, usize>,
+    externs: HashMap<String, ExternStats>,
+    variants: HashMap<(GroupRef, u8), usize>,
+    closures_created: usize,
+    peak_context_len: u8,
+    extern_started: Option<Instant>,
+    started: Option<Instant>,
+    last: Option<Instant>,
+}
+impl EvalObserver for Profiler {
+    fn observe(&mut self, _: &dyn CodeStore, event: &EvalEvent, ctx: &dyn Context) {
+        let now = Instant::now();
+        self.started.get_or_insert(now);
+        self.last = Some(now);
+        self.peak_context_len = self.peak_context_len.max(ctx.len());
+        match event {
+            EvalEvent::BeforeEntry { at, .. } => {
+                self.steps += 1;
+                *self.entries.entry(*at).or_default() += 1;
+            }
+            EvalEvent::BeforeExtern { ext, .. } => {
+                self.steps += 1;
+                self.externs.entry(ext.name().into()).or_default().calls += 1;
+                self.extern_started = Some(now);
+            }
+            EvalEvent::AfterExtern { ext, .. } => {
+                if let (Some(started), Some(stats)) =
+                    (self.extern_started.take(), self.externs.get_mut(ext.name()))
+                {
+                    stats.time += now - started;
+                }
+            }
+            EvalEvent::ClosureCreated { .. } => self.closures_created += 1,
+            EvalEvent::ClosureResumed { group, variant, .. } => {
+                *self.variants.entry((*group, *variant)).or_default() += 1
+            }
+            EvalEvent::AfterEntry { .. } | EvalEvent::Termination { .. } => (),
+        }
+    }
+}
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Forget all collected statistics
+    pub fn reset(&mut self) {
+        *self = Default::default();
+    }
+    /// Summarize the collected statistics, most frequent first
+    ///
+    /// prog: the program being profiled, naming its entries and groups
+    ///
+    pub fn report(&self, prog: &dyn CodeStore) -> ProfileReport {
+        let labels = prog.labels();
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(ent, count)| EntryProfile {
+                entry: format!("{}", ent),
+                label: labels.describe(prog, CodeRef::Entry(*ent)),
+                count: *count,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.count.cmp(&a.count).then(a.entry.cmp(&b.entry)));
+        let mut externs: Vec<_> = self
+            .externs
+            .iter()
+            .map(|(name, stats)| ExternProfile {
+                name: name.clone(),
+                calls: stats.calls,
+                seconds: stats.time.as_secs_f64(),
+            })
+            .collect();
+        externs.sort_by(|a, b| b.calls.cmp(&a.calls).then(a.name.cmp(&b.name)));
+        let mut variants: Vec<_> = self
+            .variants
+            .iter()
+            .map(|((grp, variant), count)| VariantProfile {
+                group: format!("{}", grp),
+                label: labels.describe_group(*grp),
+                variant: *variant,
+                name: labels.variant_name(*grp, *variant).map(String::from),
+                count: *count,
+            })
+            .collect();
+        variants.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then(a.group.cmp(&b.group))
+                .then(a.variant.cmp(&b.variant))
+        });
+        let seconds = match (self.started, self.last) {
+            (Some(started), Some(last)) => (last - started).as_secs_f64(),
+            _ => 0.0,
+        };
+        ProfileReport {
+            steps: self.steps,
+            seconds,
+            closures_created: self.closures_created,
+            peak_context_len: self.peak_context_len,
+            entries,
+            externs,
+            variants,
+        }
+    }
+}
+
+/// How many times an entry was evaluated
+#[derive(Serialize, Debug, Clone)]
+pub struct EntryProfile {
+    pub entry: String,
+    pub label: String,
+    pub count: usize,
+}
+/// How many times an external function was called, and the time spent in it
+#[derive(Serialize, Debug, Clone)]
+pub struct ExternProfile {
+    pub name: String,
+    pub calls: usize,
+    pub seconds: f64,
+}
+/// How many times a closure of a group was resumed on a variant
+#[derive(Serialize, Debug, Clone)]
+pub struct VariantProfile {
+    pub group: String,
+    pub label: String,
+    pub variant: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub count: usize,
+}
+
+/// The statistics collected by a `Profiler`.
+///
+/// It prints as a text table, and can be serialized, e.g. to JSON.
+///
+#[derive(Serialize, Debug, Clone)]
+pub struct ProfileReport {
+    pub steps: usize,
+    pub seconds: f64,
+    pub closures_created: usize,
+    pub peak_context_len: u8,
+    pub entries: Vec<EntryProfile>,
+    pub externs: Vec<ExternProfile>,
+    pub variants: Vec<VariantProfile>,
+}
+impl ProfileReport {
+    /// The report in JSON
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+impl Display for ProfileReport {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        writeln!(
+            fmt,
+            "{} steps in {:.6}s, {} closures created, peak context length {}",
+            self.steps, self.seconds, self.closures_created, self.peak_context_len
+        )?;
+        writeln!(fmt)?;
+        writeln!(fmt, "{:<12}{:<24}{:>10}", "entry", "label", "count")?;
+        for e in self.entries.iter() {
+            writeln!(fmt, "{:<12}{:<24}{:>10}", e.entry, e.label, e.count)?;
+        }
+        writeln!(fmt)?;
+        writeln!(fmt, "{:<36}{:>10}{:>14}", "extern", "calls", "seconds")?;
+        for e in self.externs.iter() {
+            writeln!(fmt, "{:<36}{:>10}{:>14.6}", e.name, e.calls, e.seconds)?;
+        }
+        writeln!(fmt)?;
+        writeln!(fmt, "{:<12}{:<24}{:>10}", "group", "label:variant", "count")?;
+        for v in self.variants.iter() {
+            let label = match &v.name {
+                Some(name) => format!("{}:{}", v.label, name),
+                None => format!("{}:{}", v.label, v.variant),
+            };
+            writeln!(fmt, "{:<12}{:<24}{:>10}", v.group, label, v.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Profiler;
+    use crate::{CodeRef, EvalFn, ExternEntry, Labels, Machine, MachineState, Program};
+    use lincoln_common::default_context;
+
+    #[test]
+    fn test_profile() {
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let ret = prog.add_return(0);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, done).unwrap();
+        let call = prog.add_call(ret, 0, g);
+        let mut labels = Labels::new();
+        if let CodeRef::Entry(e) = call {
+            labels.set_entry(e, "main");
+        }
+        labels.set_group(g, "finish");
+        prog.set_labels(labels);
+
+        let mut profiler = Profiler::new();
+        let mut m = Machine::new(&prog, default_context(), call);
+        match m.run_observed(&mut profiler) {
+            MachineState::Terminated => (),
+            s => panic!("unexpected state {:?}", s),
+        }
+        let report = profiler.report(&prog);
+        assert_eq!(report.steps, 3);
+        assert_eq!(report.closures_created, 1);
+        assert_eq!(report.peak_context_len, 1);
+        assert_eq!(report.entries.len(), 2);
+        assert!(report
+            .entries
+            .iter()
+            .any(|e| e.label == "main" && e.count == 1));
+        assert_eq!(report.externs[0].name, "done");
+        assert_eq!(report.externs[0].calls, 1);
+        assert_eq!(report.variants[0].label, "finish");
+        assert_eq!(report.variants[0].variant, 0);
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"closures_created\": 1"));
+    }
+}
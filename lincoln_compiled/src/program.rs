@@ -1,10 +1,22 @@
 use crate::entries::{CodeGroup, Entry, ExportEntry, ExternEntry};
+use crate::labels::Labels;
+use crate::report::EvalReport;
 use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
 use lincoln_common::{Context, ContextExt, Permutation};
 use crate::{BuildError, EvalError};
-use crate::closure::{closure_prog, eval_closure};
+use crate::closure::{closure_prog, eval_closure, pending_closures, PendingClosure};
+use crate::machine::{Machine, MachineState};
+use crate::observer::{EvalEvent, EvalObserver, EventSink};
+use crate::store::{export_entry, CodeStore};
 use failure::Error;
-use lincoln_common::{Access, StringLike};
+use lincoln_common::StringLike;
+
+/// A jump or call instruction whose target is not added yet.
+/// It is returned when the instruction is added and consumed by
+/// `Program::set_target`, so each such target is set exactly once.
+///
+#[must_use]
+pub struct PendingTarget(CodeRef);
 
 /// A compiled lincoln program
 ///
@@ -14,12 +26,16 @@ pub struct Program {
     pub(crate) externs: Vec<ExternEntry>,
     pub(crate) exports: Vec<ExportEntry>,
     pub(crate) groups: Vec<CodeGroup>,
+    pub(crate) labels: Labels,
 }
 impl std::fmt::Debug for Program {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
         writeln!(fmt, "\nentries:")?;
         for (idx, entry) in self.entries.iter().enumerate() {
-            writeln!(fmt, "\t🎯-{}: {}", idx, entry)?;
+            match self.labels.entry(EntryRef(idx)) {
+                Some(label) => writeln!(fmt, "\t🎯-{} {}: {}", idx, label, entry)?,
+                None => writeln!(fmt, "\t🎯-{}: {}", idx, entry)?,
+            }
         }
         writeln!(fmt, "externs:")?;
         for (idx, ext) in self.externs.iter().enumerate() {
@@ -32,13 +48,18 @@ impl std::fmt::Debug for Program {
         writeln!(fmt, "groups:")?;
         let grps = self.groups.iter();
         for (idx, grp) in grps.enumerate() {
-            write!(fmt, "\t🎎-{}: {{", idx)?;
-            let mut grp1 = grp.iter();
-            if let Some(ent) = grp1.next() {
-                write!(fmt, "{}", ent)?;
+            match self.labels.group(GroupRef::new(idx)) {
+                Some(label) => write!(fmt, "\t🎎-{} {}: {{", idx, label)?,
+                None => write!(fmt, "\t🎎-{}: {{", idx)?,
             }
-            for ent in grp1 {
-                write!(fmt, ";{}", ent)?;
+            for (variant, ent) in grp.iter().enumerate() {
+                if variant > 0 {
+                    write!(fmt, ";")?;
+                }
+                match self.labels.variant_name(GroupRef::new(idx), variant as u8) {
+                    Some(name) => write!(fmt, "{}={}", name, ent)?,
+                    None => write!(fmt, "{}", ent)?,
+                }
             }
             writeln!(fmt, "}}")?;
         }
@@ -53,8 +74,20 @@ impl Program {
             externs: vec![],
             exports: vec![],
             groups: vec![],
+            labels: Labels::new(),
         }
     }
+    /// The debug information of the program, naming its entries and groups
+    pub fn labels(&self) -> &Labels {
+        &self.labels
+    }
+    /// Replace the debug information of the program
+    ///
+    /// labels: the names of the entries and groups
+    ///
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = labels;
+    }
     /// Iterate all entries
     pub fn iterate_entries(&self) -> impl Iterator<Item = &Entry> {
         self.entries.iter()
@@ -113,6 +146,51 @@ impl Program {
             num_args,
         })
     }
+    /// Add a jump instruction whose target is set later
+    ///
+    /// per: the permutation to be performed before the jump
+    ///
+    /// returns: the instruction, and its target to be set
+    ///
+    pub fn add_pending_jump(&mut self, per: Permutation) -> (CodeRef, PendingTarget) {
+        let jump = self.add_jump(CodeRef::Termination, per);
+        (jump, PendingTarget(jump))
+    }
+    /// Add a call instruction whose callee is set later
+    ///
+    /// num_args: the number of values in the context to keep
+    /// cont: the instruction group to receive the result
+    ///
+    /// returns: the instruction, and its callee to be set
+    ///
+    pub fn add_pending_call(&mut self, num_args: u8, cont: GroupRef) -> (CodeRef, PendingTarget) {
+        let call = self.add_call(CodeRef::Termination, num_args, cont);
+        (call, PendingTarget(call))
+    }
+    /// Set the target of a jump or call instruction added with
+    /// `add_pending_jump` or `add_pending_call`.
+    /// This allows instructions to refer to instructions added after them.
+    ///
+    /// pending: the target to be set
+    /// target: the jump target or callee
+    ///
+    pub fn set_target(
+        &mut self,
+        pending: PendingTarget,
+        target: CodeRef,
+    ) -> Result<(), BuildError> {
+        let PendingTarget(at) = pending;
+        let entry = match at {
+            CodeRef::Entry(e) => self.entries.get_mut(e.0),
+            _ => None,
+        };
+        match entry {
+            Some(Entry::Jump { cont, .. }) => *cont = target,
+            Some(Entry::Call { call, .. }) => *call = target,
+            _ => return Err(BuildError::NoTarget(at)),
+        }
+        Ok(())
+    }
     /// Set a entry group to be exported as a name
     ///
     /// name: the name of the exported entry
@@ -149,15 +227,7 @@ impl Program {
         export_label: impl StringLike,
         variant: u8,
     ) -> Result<CodeRef, Error> {
-        if let Some(ent) = self
-            .exports
-            .iter()
-            .find(|e| e.name == export_label.as_str())
-        {
-            ent.g.get_entry(self, variant).map_err(Error::from)
-        } else {
-            bail!("Export label not found or invalid");
-        }
+        export_entry(self, export_label.as_str(), variant)
     }
     /// Find an export but only returns an entry group
     ///
@@ -188,25 +258,7 @@ impl Program {
         variant: u8,
         rounds: Option<usize>,
     ) -> Result<(), Error> {
-        let ent = self.get_export_ent(export_label, variant)?;
-        let mut evalresult = self.eval(ctx, &ent)?;
-        let (check_rounds, mut rounds) = (rounds.is_some(), rounds.unwrap_or(0));
-        loop {
-            if check_rounds && rounds == 0 {
-                break;
-            };
-            if check_rounds {
-                print!("{}: ", rounds);
-            }
-            evalresult = self.eval(ctx, &evalresult)?;
-            if let CodeRef::Termination = evalresult {
-                break;
-            }
-            if check_rounds {
-                rounds -= 1;
-            }
-        }
-        Ok(())
+        run(self, ctx, export_label.as_str(), variant, rounds)
     }
     /// Evaluate the program for one step only
     ///
@@ -215,45 +267,200 @@ impl Program {
     ///
     /// returns: the next code entry, or an error
     pub fn eval(&self, ctx: &mut dyn Context, ent: &CodeRef) -> Result<CodeRef, EvalError> {
-        debug!("eval {:?} {}", ent, ctx);
-        match ent {
-            CodeRef::Entry(ent) => match ent.access(self) {
-                Some(Entry::Jump { cont, per }) => {
-                    ctx.permutate(*per);
-                    Ok(*cont)
-                }
-                Some(Entry::Call {
-                    call,
-                    cont,
-                    num_args,
-                }) => {
-                    let c2 = ctx.split(*num_args)?;
-                    let v = closure_prog(*cont, c2, self)?;
-                    ctx.push(v);
-                    Ok(*call)
-                }
-                Some(Entry::Return { variant }) => {
-                    let v = ctx.pop()?;
-                    eval_closure(v, ctx, *variant)
-                }
-                _ => Err(ent.not_found().into()),
-            },
-            CodeRef::Extern(ext) => {
-                if let Some(ext) = ext.access(self) {
-                    match ext {
-                        ExternEntry::Eval { ref eval, .. } => eval.eval(ctx),
-                        ExternEntry::Value { ref value, .. } => {
-                            ctx.expect_args(1)?;
-                            let c = ctx.pop()?;
-                            ctx.push(value.get_value());
-                            eval_closure(c, ctx, 0)
-                        }
-                    }
-                } else {
-                    Err(ext.not_found().into())
-                }
+        eval_step(self, ctx, ent, &mut (), &EventSink::none())
+    }
+    /// Evaluate the program for one step, reporting to an observer
+    ///
+    /// Closures created in this step report their events only within
+    /// this step. Use a `Machine` to observe closures across steps.
+    ///
+    /// ctx: the values given to evaluate
+    /// ent: the current code entry
+    /// observer: receives the events of this step
+    ///
+    /// returns: the next code entry, or an error
+    pub fn eval_observed(
+        &self,
+        ctx: &mut dyn Context,
+        ent: &CodeRef,
+        observer: &mut dyn EvalObserver,
+    ) -> Result<CodeRef, EvalError> {
+        eval_step(self, ctx, ent, observer, &EventSink::new())
+    }
+}
+impl CodeStore for Program {
+    fn entry(&self, at: EntryRef) -> Option<&Entry> {
+        self.entries.get(at.0)
+    }
+    fn extern_entry(&self, at: ExternRef) -> Option<&ExternEntry> {
+        self.externs.get(at.0)
+    }
+    fn group(&self, at: GroupRef) -> Option<&[CodeRef]> {
+        self.groups.get(at.get_index()).map(|g| &g[..])
+    }
+    fn export(&self, name: &str) -> Option<GroupRef> {
+        self.exports.iter().find(|e| e.name == name).map(|e| e.g)
+    }
+    fn labels(&self) -> &Labels {
+        &self.labels
+    }
+}
+
+/// Run a program from an exported entry, see `Program::run`
+pub(crate) fn run(
+    prog: &dyn CodeStore,
+    ctx: &mut dyn Context,
+    export_label: &str,
+    variant: u8,
+    rounds: Option<usize>,
+) -> Result<(), Error> {
+    let mut context = ctx.create_empty();
+    context.merge(ctx);
+    let mut machine = Machine::start(prog, context, export_label, variant)?;
+    let state = match rounds {
+        Some(rounds) => machine.run_for(rounds),
+        None => machine.run(),
+    };
+    ctx.merge(machine.context_mut());
+    match state {
+        MachineState::Failed(e) => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Evaluate a program for one step, reporting to an observer.
+/// This is the evaluator of all kinds of programs.
+///
+/// prog: the code of the program
+/// ctx: the values given to evaluate
+/// ent: the current code entry
+/// observer: receives the events of this step
+/// events: collects the closure events of the execution
+///
+pub(crate) fn eval_step(
+    prog: &dyn CodeStore,
+    ctx: &mut dyn Context,
+    ent: &CodeRef,
+    observer: &mut dyn EvalObserver,
+    events: &EventSink,
+) -> Result<CodeRef, EvalError> {
+    debug!("eval {:?} {}", ent, ctx);
+    // The step may consume continuations, so observed executions report them
+    // as they were before it. Others only find them when the step fails.
+    let pending = if events.is_active() {
+        Some(pending_closures(ctx))
+    } else {
+        None
+    };
+    let next = match ent {
+        CodeRef::Entry(at) => match prog.entry(*at) {
+            Some(entry) => {
+                observer.observe(prog, &EvalEvent::BeforeEntry { at: *at, entry }, ctx);
+                let next = eval_entry(prog, ctx, *at, entry, events)
+                    .map_err(|e| error_at(prog, *ent, ctx, e, pending, events))?;
+                report_closure_events(prog, ctx, observer, events);
+                let event = EvalEvent::AfterEntry {
+                    at: *at,
+                    entry,
+                    next,
+                };
+                observer.observe(prog, &event, ctx);
+                next
+            }
+            None => return Err(at.not_found().into()),
+        },
+        CodeRef::Extern(at) => match prog.extern_entry(*at) {
+            Some(ext) => {
+                observer.observe(prog, &EvalEvent::BeforeExtern { at: *at, ext }, ctx);
+                let next = eval_extern(ctx, ext)
+                    .map_err(|e| error_at(prog, *ent, ctx, e, pending, events))?;
+                report_closure_events(prog, ctx, observer, events);
+                let event = EvalEvent::AfterExtern { at: *at, ext, next };
+                observer.observe(prog, &event, ctx);
+                next
             }
-            CodeRef::Termination => Err(EvalError::EvalOnTermination),
+            None => return Err(at.not_found().into()),
+        },
+        CodeRef::Termination => return Err(EvalError::EvalOnTermination),
+    };
+    if let CodeRef::Termination = next {
+        observer.observe(prog, &EvalEvent::Termination { at: *ent }, ctx);
+    }
+    Ok(next)
+}
+/// Attach the location, described by the debug information,
+/// and a report of the pending continuations to an error.
+/// Without the continuations found before the step, the ones
+/// left in the context are reported.
+/// The closure events of the failed step are discarded.
+fn error_at(
+    prog: &dyn CodeStore,
+    at: CodeRef,
+    ctx: &dyn Context,
+    error: EvalError,
+    pending: Option<Vec<PendingClosure>>,
+    events: &EventSink,
+) -> EvalError {
+    let _ = events.take();
+    let pending = pending.unwrap_or_else(|| pending_closures(ctx));
+    EvalError::At {
+        location: prog.labels().locate(prog, at),
+        error: Box::new(error),
+        report: Box::new(EvalReport::new(prog, at, ctx, pending)),
+    }
+}
+fn eval_entry(
+    prog: &dyn CodeStore,
+    ctx: &mut dyn Context,
+    at: EntryRef,
+    entry: &Entry,
+    events: &EventSink,
+) -> Result<CodeRef, EvalError> {
+    match entry {
+        Entry::Jump { cont, per } => {
+            ctx.permutate(*per);
+            Ok(*cont)
+        }
+        Entry::Call {
+            call,
+            cont,
+            num_args,
+        } => {
+            let c2 = ctx.split(*num_args)?;
+            let v = closure_prog(*cont, at, c2, prog, events)?;
+            ctx.push(v);
+            Ok(*call)
+        }
+        Entry::Return { variant } => {
+            let v = ctx.pop()?;
+            eval_closure(v, ctx, *variant)
+        }
+    }
+}
+fn report_closure_events(
+    prog: &dyn CodeStore,
+    ctx: &dyn Context,
+    observer: &mut dyn EvalObserver,
+    events: &EventSink,
+) {
+    for event in events.take() {
+        observer.observe(prog, &event, ctx);
+    }
+}
+
+/// Evaluate an external function
+///
+/// ctx: the values given to the function
+/// ext: the external function
+///
+pub(crate) fn eval_extern(ctx: &mut dyn Context, ext: &ExternEntry) -> Result<CodeRef, EvalError> {
+    match ext {
+        ExternEntry::Eval { ref eval, .. } => eval.eval(ctx),
+        ExternEntry::Value { ref value, .. } => {
+            ctx.expect_args(1)?;
+            let c = ctx.pop()?;
+            ctx.push(value.get_value());
+            eval_closure(c, ctx, 0)
         }
     }
 }
@@ -0,0 +1,191 @@
+use crate::closure::PendingClosure;
+use crate::observer::ClosureId;
+use crate::references::{CodeRef, EntryRef, GroupRef};
+use crate::store::CodeStore;
+use core::fmt::{Display, Formatter};
+use lincoln_common::Context;
+
+/// A continuation waiting in the context of a failed evaluation
+#[derive(Debug, Clone)]
+pub struct ContinuationFrame {
+    /// The closure holding the continuation
+    pub closure: ClosureId,
+    /// The group of the continuation
+    pub group: GroupRef,
+    /// The name of the group
+    pub label: String,
+    /// The `Call` entry that created the closure
+    pub call: EntryRef,
+    /// The description of the `Call` entry
+    pub caller: String,
+    /// The number of values the closure captured
+    pub captured: u8,
+    /// How many closures this closure is captured in
+    pub depth: usize,
+}
+impl Display for ContinuationFrame {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(
+            fmt,
+            "{:indent$}{} {} capturing {} values",
+            "",
+            self.closure,
+            self.label,
+            self.captured,
+            indent = self.depth * 2
+        )?;
+        write!(fmt, ", from {}", self.caller)
+    }
+}
+
+/// The Lincoln equivalent of a backtrace, attached to evaluation errors.
+///
+/// The continuations are the closures found in the context, the most
+/// recent first, each followed by the continuations it captured.
+///
+#[derive(Debug, Clone)]
+pub struct EvalReport {
+    /// The code being evaluated when the error occurred
+    pub at: CodeRef,
+    /// The context as the failed step left it
+    pub context: String,
+    /// The continuations pending when the failed step started, or
+    /// the ones it left if the execution is not observed
+    pub continuations: Vec<ContinuationFrame>,
+}
+impl EvalReport {
+    /// Build the report of a failed step
+    ///
+    /// prog: the program being evaluated
+    /// at: the code being evaluated
+    /// ctx: the context as the failed step left it
+    /// pending: the closures pending in the context
+    ///
+    pub(crate) fn new(
+        prog: &dyn CodeStore,
+        at: CodeRef,
+        ctx: &dyn Context,
+        pending: Vec<PendingClosure>,
+    ) -> Self {
+        let labels = prog.labels();
+        let continuations = pending
+            .into_iter()
+            .map(|c| ContinuationFrame {
+                closure: c.id,
+                group: c.group,
+                label: labels.describe_group(c.group),
+                call: c.call,
+                caller: labels.locate(prog, CodeRef::Entry(c.call)),
+                captured: c.captured,
+                depth: c.depth,
+            })
+            .collect();
+        EvalReport {
+            at,
+            context: ctx.to_string(),
+            continuations,
+        }
+    }
+}
+impl Display for EvalReport {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        writeln!(fmt, "at {}", self.at)?;
+        writeln!(fmt, "context: {}", self.context)?;
+        if self.continuations.is_empty() {
+            write!(fmt, "no pending continuations")
+        } else {
+            write!(fmt, "pending continuations:")?;
+            for frame in self.continuations.iter() {
+                write!(fmt, "\n  {}", frame)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CodeRef, EvalError, EvalFn, ExternEntry, Labels, Program};
+    use lincoln_common::{default_context, wrap, ContextExt};
+
+    #[test]
+    fn test_report() {
+        let mut prog = Program::new();
+        let fail = prog.add_extern(ExternEntry::Eval {
+            name: "fail".into(),
+            eval: EvalFn::stateless(|_| Err(EvalError::CallingWrapped)),
+        });
+        let ret = prog.add_return(0);
+        let k1 = prog.add_empty_group();
+        prog.add_group_entry(k1, ret).unwrap();
+        let k2 = prog.add_empty_group();
+        prog.add_group_entry(k2, ret).unwrap();
+        let inner = prog.add_call(fail, 0, k2);
+        let main = prog.add_call(inner, 0, k1);
+        let mut labels = Labels::new();
+        if let (CodeRef::Entry(inner), CodeRef::Entry(main)) = (inner, main) {
+            labels.set_entry(inner, "inner");
+            labels.set_entry(main, "main");
+        }
+        labels.set_group(k1, "k1");
+        labels.set_group(k2, "k2");
+        prog.set_labels(labels);
+
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        let mut next = main;
+        let err = loop {
+            match prog.eval(&mut *ctx, &next) {
+                Ok(n) => next = n,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(
+            format!("{}", err),
+            "Calling a wrapped value in fail (extern)"
+        );
+        match err.kind() {
+            EvalError::CallingWrapped => (),
+            e => panic!("unexpected error {}", e),
+        }
+        let report = err.report().unwrap();
+        assert_eq!(report.at, fail);
+        let frames: Vec<_> = report
+            .continuations
+            .iter()
+            .map(|f| (f.label.as_str(), f.caller.as_str(), f.captured, f.depth))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                ("k2", "inner (call fail 0 k2)", 1, 0),
+                ("k1", "main (call inner 0 k1)", 1, 1),
+            ]
+        );
+        assert!(format!("{}", report).contains("pending continuations:"));
+
+        // The continuation a failed return consumed is only
+        // reported when the execution is observed
+        let bad = prog.add_return(3);
+        let call = prog.add_call(bad, 0, k1);
+        let mut ctx = default_context();
+        let next = prog.eval(&mut *ctx, &call).unwrap();
+        let err = prog.eval(&mut *ctx, &next).unwrap_err();
+        assert!(err.report().unwrap().continuations.is_empty());
+        let mut ctx = default_context();
+        let next = prog.eval(&mut *ctx, &call).unwrap();
+        let err = prog.eval_observed(&mut *ctx, &next, &mut ()).unwrap_err();
+        match err.kind() {
+            EvalError::VariantOutOfBound { given: 3, max: 1 } => (),
+            e => panic!("unexpected error {}", e),
+        }
+        let report = err.report().unwrap();
+        assert_eq!(report.context, "()");
+        let frames: Vec<_> = report
+            .continuations
+            .iter()
+            .map(|f| (f.label.as_str(), CodeRef::Entry(f.call)))
+            .collect();
+        assert_eq!(frames, vec![("k1", call)]);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::entries::{Entry, ExternEntry};
+use crate::error::BuildError;
+use crate::labels::Labels;
+use crate::references::{CodeRef, EntryRef, ExternRef, GroupRef};
+use failure::Error;
+use std::rc::Rc;
+
+/// The storage of the code of a compiled program.
+///
+/// `Program` keeps its code in vectors, `ArenaProgram` in an arena.
+/// The evaluator, `Machine`, the observers and the debugger only access
+/// code through this trait, so programs of both kinds run the same way.
+///
+pub trait CodeStore {
+    /// The entry at an index, if any
+    fn entry(&self, at: EntryRef) -> Option<&Entry>;
+    /// The external function at an index, if any
+    fn extern_entry(&self, at: ExternRef) -> Option<&ExternEntry>;
+    /// The entries of a group, if any
+    fn group(&self, at: GroupRef) -> Option<&[CodeRef]>;
+    /// The group exported as a name, if any
+    fn export(&self, name: &str) -> Option<GroupRef>;
+    /// The debug information of the program
+    fn labels(&self) -> &Labels;
+}
+
+macro_rules! delegate_code_store {
+    ($($t:ty),*) => {$(
+        impl<T> CodeStore for $t
+        where
+            T: CodeStore + ?Sized,
+        {
+            fn entry(&self, at: EntryRef) -> Option<&Entry> {
+                (**self).entry(at)
+            }
+            fn extern_entry(&self, at: ExternRef) -> Option<&ExternEntry> {
+                (**self).extern_entry(at)
+            }
+            fn group(&self, at: GroupRef) -> Option<&[CodeRef]> {
+                (**self).group(at)
+            }
+            fn export(&self, name: &str) -> Option<GroupRef> {
+                (**self).export(name)
+            }
+            fn labels(&self) -> &Labels {
+                (**self).labels()
+            }
+        }
+    )*};
+}
+delegate_code_store!(&T, Rc<T>, Box<T>);
+
+/// Find an export by name, and receive an entry from its variants
+///
+/// store: the code of the program
+/// export_label: the name of the export
+/// variant: the variant to return
+///
+pub(crate) fn export_entry(
+    store: &dyn CodeStore,
+    export_label: &str,
+    variant: u8,
+) -> Result<CodeRef, Error> {
+    let g = match store.export(export_label) {
+        Some(g) => g,
+        None => bail!("Export label not found or invalid"),
+    };
+    let entries = store.group(g).ok_or(BuildError::GroupNotFound(g))?;
+    match entries.get(variant as usize) {
+        Some(ent) => Ok(*ent),
+        None => Err(BuildError::VariangOutOfRange {
+            given: variant,
+            max: entries.len() as u8,
+        }
+        .into()),
+    }
+}
@@ -0,0 +1,323 @@
+use crate::entries::Entry;
+use crate::labels::Labels;
+use crate::observer::{ClosureId, EvalEvent, EvalObserver};
+use crate::references::CodeRef;
+use crate::store::CodeStore;
+use failure::Error;
+use lincoln_common::Context;
+use serde_json::json;
+use std::io::Write;
+use std::time::Instant;
+
+/// The file formats a `TraceWriter` can write.
+///
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One JSON object per line, one line per step
+    JsonLines,
+    /// The Chrome trace-event format (a JSON array), viewable with
+    /// `chrome://tracing` or Perfetto. Calls are shown as nested spans
+    /// that end when their continuations are resumed.
+    Chrome,
+}
+
+/// A single evaluation step in a trace
+///
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceRecord {
+    /// The number of steps evaluated before this step
+    pub step: usize,
+    /// The entry being evaluated
+    pub at: String,
+    /// The IR label of the entry, or the name of the external function
+    pub label: String,
+    /// The instruction of the entry, e.g. `k (ret err)`
+    pub instruction: String,
+    /// `jmp`, `call`, `ret` or `extern`
+    pub kind: &'static str,
+    /// The number of values in the context before the step
+    pub context_len: u8,
+    /// The values in the context before the step
+    pub values: Vec<String>,
+    /// Microseconds from the start of the trace. Not recorded in deterministic mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_us: Option<u64>,
+}
+
+struct Span {
+    closure: Option<ClosureId>,
+    name: String,
+}
+
+/// An observer that writes every evaluation step to a file.
+///
+/// In deterministic mode no wall clock time is recorded, and the Chrome
+/// timestamps are the step indexes, so the traces of two runs of the
+/// same program can be compared.
+///
+/// Write errors are kept and reported by `finish`.
+///
+pub struct TraceWriter<'a, W> {
+    out: W,
+    format: TraceFormat,
+    labels: &'a Labels,
+    deterministic: bool,
+    started: Instant,
+    step: usize,
+    pending: Option<TraceRecord>,
+    spans: Vec<Span>,
+    events: usize,
+    error: Option<std::io::Error>,
+}
+impl<'a, W> TraceWriter<'a, W>
+where
+    W: Write,
+{
+    /// Create a trace writer
+    ///
+    /// out: where the trace is written to
+    /// format: the format of the trace
+    /// labels: names of the entries and groups of the program being traced
+    ///
+    pub fn new(out: W, format: TraceFormat, labels: &'a Labels) -> Self {
+        TraceWriter {
+            out,
+            format,
+            labels,
+            deterministic: false,
+            started: Instant::now(),
+            step: 0,
+            pending: None,
+            spans: vec![],
+            events: 0,
+            error: None,
+        }
+    }
+    /// Set the deterministic mode
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+    /// Close any open spans, finish the file and return the output
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.flush_pending();
+        self.close_spans(0);
+        if self.format == TraceFormat::Chrome {
+            let r = if self.events == 0 {
+                writeln!(self.out, "[]")
+            } else {
+                writeln!(self.out, "\n]")
+            };
+            self.keep_error(r);
+        }
+        let r = self.out.flush();
+        self.keep_error(r);
+        match self.error {
+            Some(e) => Err(e.into()),
+            None => Ok(self.out),
+        }
+    }
+
+    fn keep_error(&mut self, r: std::io::Result<()>) {
+        if let (Err(e), None) = (r, &self.error) {
+            self.error = Some(e);
+        }
+    }
+    fn time_us(&self) -> u64 {
+        if self.deterministic {
+            self.step as u64
+        } else {
+            self.started.elapsed().as_micros() as u64
+        }
+    }
+    fn write_chrome(&mut self, event: serde_json::Value) {
+        let sep = if self.events == 0 { "[\n" } else { ",\n" };
+        self.events += 1;
+        let r = write!(self.out, "{}{}", sep, event);
+        self.keep_error(r);
+    }
+    fn begin_step(
+        &mut self,
+        prog: &dyn CodeStore,
+        at: CodeRef,
+        kind: &'static str,
+        ctx: &dyn Context,
+    ) {
+        self.flush_pending();
+        let values = (0..ctx.len())
+            .filter_map(|i| ctx.get(i))
+            .map(|v| format!("{:?}", v))
+            .collect();
+        let time_us = if self.deterministic {
+            None
+        } else {
+            Some(self.time_us())
+        };
+        let record = TraceRecord {
+            step: self.step,
+            at: format!("{}", at),
+            label: self.labels.describe(prog, at),
+            instruction: self.labels.locate(prog, at),
+            kind,
+            context_len: ctx.len(),
+            values,
+            time_us,
+        };
+        match self.format {
+            TraceFormat::JsonLines => {
+                let r = serde_json::to_string(&record)
+                    .map_err(std::io::Error::from)
+                    .and_then(|line| writeln!(self.out, "{}", line));
+                self.keep_error(r);
+            }
+            TraceFormat::Chrome => self.pending = Some(record),
+        }
+    }
+    fn flush_pending(&mut self) {
+        if let Some(record) = self.pending.take() {
+            let ts = if self.deterministic {
+                record.step as u64
+            } else {
+                record.time_us.unwrap_or(0)
+            };
+            let dur = (self.time_us() - ts).max(1);
+            self.write_chrome(json!({
+                "name": record.label,
+                "cat": record.kind,
+                "ph": "X",
+                "ts": ts,
+                "dur": dur,
+                "pid": 1,
+                "tid": 1,
+                "args": record,
+            }));
+        }
+    }
+    fn close_spans(&mut self, depth: usize) {
+        while self.spans.len() > depth {
+            let span = self.spans.pop().expect("spans is empty");
+            if self.format == TraceFormat::Chrome {
+                let ts = self.time_us();
+                self.write_chrome(json!({
+                    "name": span.name,
+                    "cat": "call",
+                    "ph": "E",
+                    "ts": ts,
+                    "pid": 1,
+                    "tid": 1,
+                }));
+            }
+        }
+    }
+}
+impl<'a, W> EvalObserver for TraceWriter<'a, W>
+where
+    W: Write,
+{
+    fn observe(&mut self, prog: &dyn CodeStore, event: &EvalEvent, ctx: &dyn Context) {
+        match event {
+            EvalEvent::BeforeEntry { at, entry } => {
+                let kind = match entry {
+                    Entry::Jump { .. } => "jmp",
+                    Entry::Call { .. } => "call",
+                    Entry::Return { .. } => "ret",
+                };
+                self.begin_step(prog, CodeRef::Entry(*at), kind, ctx);
+                if let (Entry::Call { call, .. }, TraceFormat::Chrome) = (entry, self.format) {
+                    let name = self.labels.describe(prog, *call);
+                    let ts = self.time_us();
+                    self.write_chrome(json!({
+                        "name": name,
+                        "cat": "call",
+                        "ph": "B",
+                        "ts": ts,
+                        "pid": 1,
+                        "tid": 1,
+                    }));
+                    self.spans.push(Span {
+                        closure: None,
+                        name,
+                    });
+                }
+            }
+            EvalEvent::BeforeExtern { at, .. } => {
+                self.begin_step(prog, CodeRef::Extern(*at), "extern", ctx)
+            }
+            EvalEvent::ClosureCreated { id, .. } => {
+                // The first closure created by a call is its continuation
+                if let Some(span) = self.spans.last_mut() {
+                    if span.closure.is_none() {
+                        span.closure = Some(*id);
+                    }
+                }
+            }
+            EvalEvent::ClosureResumed { id, .. } => {
+                if let Some(depth) = self.spans.iter().rposition(|s| s.closure == Some(*id)) {
+                    self.close_spans(depth);
+                }
+            }
+            EvalEvent::AfterEntry { .. } | EvalEvent::AfterExtern { .. } => {
+                self.step += 1;
+                self.flush_pending();
+            }
+            EvalEvent::Termination { .. } => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TraceFormat, TraceWriter};
+    use crate::{CodeRef, EvalFn, ExternEntry, Labels, Machine, Program};
+    use lincoln_common::{default_context, wrap, ContextExt};
+
+    fn trace(prog: &Program, labels: &Labels, format: TraceFormat) -> String {
+        let mut ctx = default_context();
+        ctx.push(wrap(1i32));
+        let mut writer = TraceWriter::new(vec![], format, labels).deterministic(true);
+        let mut m = Machine::start(prog, ctx, "main", 0).unwrap();
+        m.run_observed(&mut writer);
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut prog = Program::new();
+        let done = prog.add_extern(ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        });
+        let ret = prog.add_return(0);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, done).unwrap();
+        let call = prog.add_call(ret, 1, g);
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, call).unwrap();
+        prog.add_export("main", main);
+        let mut labels = Labels::new();
+        if let CodeRef::Entry(e) = call {
+            labels.set_entry(e, "main");
+        }
+
+        let lines = trace(&prog, &labels, TraceFormat::JsonLines);
+        assert_eq!(lines, trace(&prog, &labels, TraceFormat::JsonLines));
+        let records: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["label"], "main");
+        assert_eq!(records[0]["kind"], "call");
+        assert_eq!(records[0]["context_len"], 1);
+        assert_eq!(records[1]["kind"], "ret");
+        assert_eq!(records[2]["label"], "done");
+        assert_eq!(records[2]["step"], 2);
+        assert!(records[0].get("time_us").is_none());
+
+        let chrome = trace(&prog, &labels, TraceFormat::Chrome);
+        assert_eq!(chrome, trace(&prog, &labels, TraceFormat::Chrome));
+        let events: Vec<serde_json::Value> = serde_json::from_str(&chrome).unwrap();
+        let phases: Vec<_> = events.iter().map(|e| e["ph"].as_str().unwrap()).collect();
+        assert_eq!(phases, vec!["B", "X", "E", "X", "X"]);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::entries::Entry;
+use crate::error::ValidationError;
+use crate::program::Program;
+use crate::references::{CodeRef, EntryRef, GroupRef};
+use core::hash::Hash;
+use lincoln_common::Permutation;
+use std::collections::HashSet;
+
+/// An instruction met by `follow_continuation`, described by
+/// the program representation being walked
+pub enum Follow<K> {
+    /// Permutate the values, then continue at an instruction
+    Jump(Permutation, K),
+    /// Resume the last value on a variant
+    Return(u8),
+    /// Anything else ends the walk
+    Stop,
+}
+
+/// Find the return resuming a continuation.
+///
+/// A call passes its continuation as the last of `len` values.
+/// The continuation is followed through the jumps from the callee, and
+/// the return reached is the one resuming it, if the continuation is
+/// still the last value there. Jumps with permutations needing more than
+/// `len` values, and loops, end the walk.
+///
+/// This is part of the public API, so any representation of a program
+/// can apply the calling convention of compiled programs: locations are
+/// only compared and hashed, and `instruction` describes what is found
+/// at each of them. `Program::validate` walks `CodeRef`s with it, the IR
+/// walks its own entries to infer the variants a call returns.
+///
+/// start: the callee
+/// len: the number of values the callee receives
+/// instruction: describes the instruction at a location
+///
+/// returns: the location of the return and its variant
+///
+pub fn follow_continuation<K>(
+    start: K,
+    len: usize,
+    mut instruction: impl FnMut(K) -> Follow<K>,
+) -> Option<(K, u8)>
+where
+    K: Copy + Eq + Hash,
+{
+    let mut pos = len.checked_sub(1)?;
+    let mut next = start;
+    let mut visited = HashSet::new();
+    while visited.insert((next, pos)) {
+        match instruction(next) {
+            Follow::Jump(per, cont) if per.min_len() as usize <= len => {
+                let mut positions: Vec<usize> = (0..len).collect();
+                per.permutate(&mut positions);
+                pos = positions.iter().position(|p| *p == pos).unwrap_or(pos);
+                next = cont;
+            }
+            Follow::Return(variant) if pos == len - 1 => return Some((next, variant)),
+            _ => return None,
+        }
+    }
+    None
+}
+
+impl Program {
+    /// Check the structure of the program before running it.
+    ///
+    /// All code references, groups and externs must be defined,
+    /// permutations must not need more than 20 values, exports and call continuations must not be empty groups,
+    /// and a `Return` reached from a call must not return a variant
+    /// its continuation group does not have.
+    ///
+    /// returns: all problems found, if any
+    ///
+    pub fn validate(&self) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let at = EntryRef(idx);
+            match entry {
+                Entry::Jump { cont, per } => {
+                    if !self.is_defined(*cont) {
+                        errors.push(ValidationError::DanglingCodeRef { at, target: *cont });
+                    }
+                    if per.min_len() > Permutation::MAX_LEN {
+                        errors.push(ValidationError::InvalidPermutation { at, per: *per });
+                    }
+                }
+                Entry::Call { call, cont, .. } => {
+                    if !self.is_defined(*call) {
+                        errors.push(ValidationError::DanglingCodeRef { at, target: *call });
+                    }
+                    match self.groups.get(cont.get_index()) {
+                        None => errors.push(ValidationError::DanglingGroup { at, group: *cont }),
+                        Some(g) if g.is_empty() => {
+                            errors.push(ValidationError::EmptyGroup { at, group: *cont })
+                        }
+                        Some(_) => (),
+                    }
+                }
+                Entry::Return { .. } => (),
+            }
+        }
+        for (idx, group) in self.groups.iter().enumerate() {
+            for (variant, target) in group.iter().enumerate() {
+                if !self.is_defined(*target) {
+                    errors.push(ValidationError::DanglingGroupEntry {
+                        group: GroupRef::new(idx),
+                        variant: variant as u8,
+                        target: *target,
+                    });
+                }
+            }
+        }
+        for export in self.exports.iter() {
+            match self.groups.get(export.g.get_index()) {
+                None => errors.push(ValidationError::DanglingExport {
+                    name: export.name.clone(),
+                    group: export.g,
+                }),
+                Some(g) if g.is_empty() => errors.push(ValidationError::EmptyExport {
+                    name: export.name.clone(),
+                    group: export.g,
+                }),
+                Some(_) => (),
+            }
+        }
+        if errors.is_empty() {
+            self.validate_returns(&mut errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+    fn is_defined(&self, c: CodeRef) -> bool {
+        match c {
+            CodeRef::Entry(e) => e.0 < self.entries.len(),
+            CodeRef::Extern(e) => e.0 < self.externs.len(),
+            CodeRef::Termination => true,
+        }
+    }
+    /// Check the variant of the `Return` resuming the continuation
+    /// of each call against the size of the continuation group.
+    /// Only called when all references are defined.
+    ///
+    fn validate_returns(&self, errors: &mut Vec<ValidationError>) {
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if let Entry::Call {
+                call,
+                cont,
+                num_args,
+            } = entry
+            {
+                let max = self.groups[cont.get_index()].len();
+                let resume = follow_continuation(*call, *num_args as usize + 1, |c| match c {
+                    CodeRef::Entry(at) => match self.entries[at.0] {
+                        Entry::Jump { cont, per } => Follow::Jump(per, cont),
+                        Entry::Return { variant } => Follow::Return(variant),
+                        Entry::Call { .. } => Follow::Stop,
+                    },
+                    _ => Follow::Stop,
+                });
+                if let Some((CodeRef::Entry(at), variant)) = resume {
+                    let v = variant as usize;
+                    // Single variant closures also accept "drop" (1) and "copy" (2)
+                    if v >= max && !(max == 1 && (v == 1 || v == 2)) {
+                        errors.push(ValidationError::VariantOutOfRange {
+                            at,
+                            call: EntryRef(idx),
+                            group: *cont,
+                            variant,
+                            max,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{CodeRef, EvalFn, ExternEntry, GroupRef, Program, ValidationError};
+    use lincoln_common::Permutation;
+
+    fn done() -> ExternEntry {
+        ExternEntry::Eval {
+            name: "done".into(),
+            eval: EvalFn::stateless(|_| Ok(CodeRef::Termination)),
+        }
+    }
+
+    #[test]
+    fn test_valid() {
+        let mut prog = Program::new();
+        let ext = prog.add_extern(done());
+        let ret = prog.add_return(1);
+        let swap = prog.add_jump(ret, Permutation(1));
+        let g = prog.add_empty_group();
+        let call = prog.add_call(swap, 1, g);
+        prog.add_group_entry(g, ext).unwrap();
+        prog.add_group_entry(g, ext).unwrap();
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, call).unwrap();
+        prog.add_export("main", main);
+        prog.validate().unwrap();
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut prog = Program::new();
+        let ret = prog.add_return(1);
+        let g = prog.add_empty_group();
+        let _ = prog.add_jump(CodeRef::entry(10), Permutation(0));
+        let _ = prog.add_jump(ret, Permutation(u64::MAX));
+        let _ = prog.add_call(ret, 0, GroupRef::new(5));
+        let _ = prog.add_call(ret, 0, g);
+        prog.add_export("main", g);
+        let errors = prog.validate().unwrap_err();
+        assert_eq!(errors.len(), 5);
+        assert!(matches!(errors[0], ValidationError::DanglingCodeRef { .. }));
+        assert!(matches!(
+            errors[1],
+            ValidationError::InvalidPermutation { .. }
+        ));
+        assert!(matches!(errors[2], ValidationError::DanglingGroup { .. }));
+        assert!(matches!(errors[3], ValidationError::EmptyGroup { .. }));
+        assert!(matches!(errors[4], ValidationError::EmptyExport { .. }));
+
+        let mut prog = Program::new();
+        let ext = prog.add_extern(done());
+        let ret = prog.add_return(1);
+        let ret3 = prog.add_return(3);
+        let g = prog.add_empty_group();
+        prog.add_group_entry(g, ext).unwrap();
+        prog.add_group_entry(g, ext).unwrap();
+        let call = prog.add_call(ret, 1, g);
+        // The swapped return does not resume the continuation
+        let swap = prog.add_jump(ret3, Permutation(1));
+        let _ = prog.add_call(swap, 1, g);
+        let main = prog.add_empty_group();
+        prog.add_group_entry(main, call).unwrap();
+        prog.add_export("main", main);
+        prog.validate().unwrap();
+
+        let _ = prog.add_call(ret3, 0, g);
+        match &prog.validate().unwrap_err()[..] {
+            [ValidationError::VariantOutOfRange {
+                variant: 3, max: 2, ..
+            }] => (),
+            e => panic!("unexpected errors {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_group_bounds() {
+        let mut prog = Program::new();
+        let g = prog.add_empty_group();
+        assert!(prog.add_group_entry(g, CodeRef::Termination).is_ok());
+        assert!(prog
+            .add_group_entry(GroupRef::new(1), CodeRef::Termination)
+            .is_err());
+    }
+}
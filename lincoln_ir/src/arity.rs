@@ -0,0 +1,366 @@
+use crate::entry::{Entry, EntryRef};
+use crate::program::PreCompileProgram;
+use core::fmt::{Display, Formatter};
+use lincoln_common::Access;
+use lincoln_compiled::{follow_continuation, Follow};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+/// Problems found by the arity analysis
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum ArityIssue {
+    #[fail(
+        display = "{} receives {} values on one path and {} on another",
+        label, expect, actual
+    )]
+    Conflict {
+        label: String,
+        expect: usize,
+        actual: usize,
+    },
+
+    #[fail(
+        display = "{} receives {} values but requires at least {}",
+        label, arity, required
+    )]
+    TooFewValues {
+        label: String,
+        arity: usize,
+        required: usize,
+    },
+
+    #[fail(
+        display = "{} returns variant {} to the continuation {} of {}, which has {} variants",
+        label, variant, cont, call, variants
+    )]
+    InvalidVariant {
+        label: String,
+        call: String,
+        cont: String,
+        variant: u8,
+        variants: usize,
+    },
+}
+
+/// The result of the arity analysis of a program
+///
+#[derive(Debug, Default)]
+pub struct ArityReport {
+    arities: BTreeMap<String, usize>,
+    issues: Vec<ArityIssue>,
+}
+impl ArityReport {
+    /// The inferred number of values an entry expects, if known
+    ///
+    /// label: the name of the entry
+    ///
+    pub fn arity(&self, label: &str) -> Option<usize> {
+        self.arities.get(label).cloned()
+    }
+    /// Iterate all entries with known arity
+    pub fn iterate_arities(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.arities.iter().map(|(l, a)| (l.as_str(), *a))
+    }
+    /// All problems found
+    pub fn issues(&self) -> &[ArityIssue] {
+        &self.issues
+    }
+}
+impl Display for ArityReport {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        for (label, arity) in self.arities.iter() {
+            writeln!(fmt, "{}: {}", label, arity)?;
+        }
+        for issue in self.issues.iter() {
+            writeln!(fmt, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// A call whose continuation is resumed by a return
+pub(crate) struct Resume {
+    pub(crate) call: EntryRef,
+    pub(crate) ret: EntryRef,
+    pub(crate) variant: u8,
+}
+
+/// Find the `ret` resuming the continuation of each `call`, by the
+/// same walk the compiled program is validated with. Labels that cannot
+/// be resolved end the walk; `compile` reports them.
+///
+pub(crate) fn find_resumes(program: &PreCompileProgram) -> Vec<Resume> {
+    let mut resumes = vec![];
+    for (idx, entry) in program.entries.iter().enumerate() {
+        if let Entry::Call {
+            callee, callcnt, ..
+        } = entry
+        {
+            let resume = follow_continuation(*callee, *callcnt as usize + 1, |at| {
+                match at.access(program) {
+                    Ok(Entry::Jmp { cont, per }) => Follow::Jump(*per, *cont),
+                    Ok(Entry::Ret { variant }) => Follow::Return(*variant),
+                    _ => Follow::Stop,
+                }
+            });
+            if let Some((ret, variant)) = resume {
+                resumes.push(Resume {
+                    call: EntryRef::new(idx),
+                    ret,
+                    variant,
+                });
+            }
+        }
+    }
+    resumes
+}
+
+struct Inference<'a> {
+    program: &'a PreCompileProgram,
+    arities: BTreeMap<EntryRef, usize>,
+    polymorphic: BTreeSet<EntryRef>,
+    uses: BTreeSet<(EntryRef, usize)>,
+    conflicts: BTreeSet<EntryRef>,
+    issues: Vec<ArityIssue>,
+    changed: bool,
+}
+impl<'a> Inference<'a> {
+    fn new(program: &'a PreCompileProgram) -> Self {
+        Inference {
+            program,
+            arities: BTreeMap::new(),
+            polymorphic: Self::find_polymorphic(program),
+            uses: BTreeSet::new(),
+            conflicts: BTreeSet::new(),
+            issues: vec![],
+            changed: false,
+        }
+    }
+    /// A `ret`, or a `jmp` that can only lead to a `ret`, accepts any
+    /// number of values large enough for its permutations. Such entries are
+    /// checked for each use, rather than given a single arity.
+    ///
+    fn find_polymorphic(program: &PreCompileProgram) -> BTreeSet<EntryRef> {
+        let mut polymorphic = BTreeSet::new();
+        loop {
+            let found: Vec<EntryRef> = program
+                .entries
+                .iter()
+                .enumerate()
+                .map(|(idx, entry)| (EntryRef::new(idx), entry))
+                .filter(|(ent, entry)| {
+                    !polymorphic.contains(ent)
+                        && match entry {
+                            Entry::Ret { .. } => true,
+                            Entry::Jmp { cont, .. } => polymorphic.contains(cont),
+                            _ => false,
+                        }
+                })
+                .map(|(ent, _)| ent)
+                .collect();
+            if found.is_empty() {
+                return polymorphic;
+            }
+            polymorphic.extend(found);
+        }
+    }
+    fn label(&self, ent: EntryRef) -> String {
+        self.program
+            .find_name(ent)
+            .map(String::from)
+            .unwrap_or_else(|_| format!("{}", ent))
+    }
+    fn get(&self, ent: EntryRef) -> Option<usize> {
+        self.arities.get(&ent).cloned()
+    }
+    fn assign(&mut self, ent: EntryRef, arity: usize) {
+        if ent.is_group_in(self.program) {
+            return;
+        }
+        if self.polymorphic.contains(&ent) {
+            let _ = self.uses.insert((ent, arity));
+            return;
+        }
+        match self.get(ent) {
+            None => {
+                let _ = self.arities.insert(ent, arity);
+                self.changed = true;
+            }
+            Some(expect) if expect != arity => {
+                if self.conflicts.insert(ent) {
+                    self.issues.push(ArityIssue::Conflict {
+                        label: self.label(ent),
+                        expect,
+                        actual: arity,
+                    });
+                }
+            }
+            Some(_) => (),
+        }
+    }
+    fn report(&mut self, issue: ArityIssue) {
+        if !self.issues.contains(&issue) {
+            self.issues.push(issue);
+        }
+    }
+    /// The entry a variant of a continuation resumes to
+    fn variant_of(&self, cont: EntryRef, variant: u8) -> Option<EntryRef> {
+        match cont.access(self.program) {
+            Ok(Entry::Group { elements }) => elements.get(variant as usize).cloned(),
+            Ok(_) if variant == 0 => Some(cont),
+            _ => None,
+        }
+    }
+    fn variant_count(&self, cont: EntryRef) -> usize {
+        match cont.access(self.program) {
+            Ok(Entry::Group { elements }) => elements.len(),
+            _ => 1,
+        }
+    }
+    fn propagate(&mut self, resumes: &[Resume]) {
+        let program = self.program;
+        for entry in program.entries.iter() {
+            if let Entry::Call {
+                callee, callcnt, ..
+            } = entry
+            {
+                self.assign(*callee, *callcnt as usize + 1);
+            }
+        }
+        self.changed = true;
+        while self.changed {
+            self.changed = false;
+            for (idx, entry) in program.entries.iter().enumerate() {
+                let ent = EntryRef::new(idx);
+                if let Entry::Jmp { cont, .. } = entry {
+                    match (self.get(ent), self.get(*cont)) {
+                        (Some(a), _) => self.assign(*cont, a),
+                        (None, Some(a)) => self.assign(ent, a),
+                        _ => (),
+                    }
+                }
+            }
+            // The callee returns all the values it was called with, except
+            // the closure itself; the captured values are then added back,
+            // so the continuation receives as many values as the call.
+            for resume in resumes {
+                let cont = match resume.call.access(program) {
+                    Ok(Entry::Call { callcont, .. }) => *callcont,
+                    _ => continue,
+                };
+                let target = match self.variant_of(cont, resume.variant) {
+                    Some(target) => target,
+                    None => continue,
+                };
+                match (self.get(resume.call), self.get(target)) {
+                    (Some(a), _) => self.assign(target, a),
+                    (None, Some(a)) => self.assign(resume.call, a),
+                    _ => (),
+                }
+            }
+        }
+    }
+    fn check(&mut self, resumes: &[Resume]) {
+        let program = self.program;
+        for (idx, entry) in program.entries.iter().enumerate() {
+            let ent = EntryRef::new(idx);
+            let arity = match self.get(ent) {
+                Some(arity) => arity,
+                None => continue,
+            };
+            let required = match entry {
+                Entry::Jmp { per, .. } => per.min_len() as usize,
+                Entry::Call { callcnt, .. } => *callcnt as usize,
+                _ => 0,
+            };
+            if arity < required {
+                self.report(ArityIssue::TooFewValues {
+                    label: self.label(ent),
+                    arity,
+                    required,
+                });
+            }
+        }
+        for (ent, arity) in std::mem::take(&mut self.uses) {
+            let mut next = ent;
+            let mut visited = HashSet::new();
+            while visited.insert(next) {
+                let required = match next.access(program) {
+                    Ok(Entry::Jmp { cont, per }) => {
+                        let required = per.min_len() as usize;
+                        if required <= arity {
+                            next = *cont;
+                            continue;
+                        }
+                        required
+                    }
+                    Ok(Entry::Ret { .. }) if arity < 1 => 1,
+                    _ => break,
+                };
+                self.report(ArityIssue::TooFewValues {
+                    label: self.label(next),
+                    arity,
+                    required,
+                });
+                break;
+            }
+        }
+        for resume in resumes {
+            let cont = match resume.call.access(program) {
+                Ok(Entry::Call { callcont, .. }) => *callcont,
+                _ => continue,
+            };
+            let variants = self.variant_count(cont);
+            let v = resume.variant as usize;
+            // Variants 1 and 2 of a one-variant group are the closure's drop and copy
+            if v >= variants && !(variants == 1 && (v == 1 || v == 2)) {
+                self.report(ArityIssue::InvalidVariant {
+                    label: self.label(resume.ret),
+                    call: self.label(resume.call),
+                    cont: self.label(cont),
+                    variant: resume.variant,
+                    variants,
+                });
+            }
+        }
+    }
+}
+
+impl PreCompileProgram {
+    /// Infer how many values each entry expects in its context.
+    ///
+    /// The callee of a call receives the kept values and the continuation,
+    /// a jmp passes all its values on, and a continuation resumed by a ret
+    /// receives the returned values followed by the captured values.
+    /// Rets, and jmps leading only to rets, accept any large enough number
+    /// of values, so they have no arity of their own.
+    /// Rets naming their variants are checked with the resolved variants.
+    /// Entries that receive different numbers of values on different paths,
+    /// or fewer values than their instruction requires, and rets returning
+    /// variants their continuations don't have, are reported.
+    ///
+    pub fn infer_arity(&self) -> ArityReport {
+        let mut inference = Inference::new(self);
+        // Rets naming variants that cannot be resolved are reported by `compile`
+        let resumes = find_resumes(self);
+        let (variants, _) = self.resolve_variants(&resumes);
+        let resumes: Vec<Resume> = resumes
+            .into_iter()
+            .filter_map(|mut resume| {
+                if self.is_named_ret(resume.ret) {
+                    resume.variant = *variants.get(&resume.ret)?;
+                }
+                Some(resume)
+            })
+            .collect();
+        inference.propagate(&resumes);
+        inference.check(&resumes);
+        ArityReport {
+            arities: inference
+                .arities
+                .iter()
+                .map(|(e, a)| (inference.label(*e), *a))
+                .collect(),
+            issues: inference.issues,
+        }
+    }
+}
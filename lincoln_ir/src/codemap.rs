@@ -1,6 +1,6 @@
 use crate::entry::EntryRef;
 use lincoln_common::{StringLike, Permutation};
-use lincoln_compiled::{BuildError, CodeRef, ExternEntry, GroupRef, Program};
+use lincoln_compiled::{BuildError, CodeRef, ExternEntry, GroupRef, PendingTarget, Program};
 use std::collections::BTreeMap;
 
 #[derive(Fail, Debug)]
@@ -13,9 +13,16 @@ pub enum CodeMapError {
     Build(BuildError),
 }
 
+/// Compiles entries in two phases: every entry is first allocated in
+/// the program, then `link` patches in the targets of jmps and calls
+/// and the elements of groups. Entries can thus refer to entries
+/// compiled after them.
+///
 pub struct CodeMap {
     coderef_map: BTreeMap<EntryRef, CodeRef>,
     group_map: BTreeMap<EntryRef, GroupRef>,
+    targets: Vec<(PendingTarget, EntryRef)>,
+    group_entries: Vec<(GroupRef, EntryRef)>,
     prog: Program,
 }
 impl CodeMap {
@@ -23,6 +30,8 @@ impl CodeMap {
         Self {
             coderef_map: BTreeMap::new(),
             group_map: BTreeMap::new(),
+            targets: vec![],
+            group_entries: vec![],
             prog: Default::default(),
         }
     }
@@ -32,18 +41,10 @@ impl CodeMap {
     pub(crate) fn add_return(&mut self, ent: EntryRef, variant: u8) {
         self.coderef_map.insert(ent, self.prog.add_return(variant));
     }
-    pub(crate) fn add_jmp(
-        &mut self,
-        ent: EntryRef,
-        cont: EntryRef,
-        per: Permutation,
-    ) -> Result<(), CodeMapError> {
-        let cont = *self
-            .coderef_map
-            .get(&cont)
-            .ok_or(CodeMapError::EntryNotFound(cont))?;
-        self.coderef_map.insert(ent, self.prog.add_jump(cont, per));
-        Ok(())
+    pub(crate) fn add_jmp(&mut self, ent: EntryRef, cont: EntryRef, per: Permutation) {
+        let (jmp, pending) = self.prog.add_pending_jump(per);
+        self.coderef_map.insert(ent, jmp);
+        self.targets.push((pending, cont));
     }
     pub(crate) fn add_call(
         &mut self,
@@ -51,58 +52,62 @@ impl CodeMap {
         callee: EntryRef,
         callcnt: u8,
         callcont: EntryRef,
-    ) -> Result<(), CodeMapError> {
-        let call = *self
-            .coderef_map
-            .get(&callee)
-            .ok_or(CodeMapError::EntryNotFound(callee))?;
-        let cont = self.group_map.get(&callcont);
+        callcont_is_group: bool,
+    ) {
         // The continuation part of a `call` instruction is a group.
-        // If the group has been defined, add a new entry to it.
-        // Otherwise create a new group and add an entry.
-        match cont {
-            Some(cont) => {
-                self.coderef_map
-                    .insert(ent, self.prog.add_call(call, callcnt, *cont));
-            }
+        // If the continuation is not a group, a group of the single entry
+        // is created and shared by all calls to the same continuation.
+        let cont = match self.group_map.get(&callcont) {
+            Some(cont) => *cont,
             None => {
-                let grp = self.prog.add_empty_group();
-                let _ = self.group_map.insert(callcont, grp);
-                if let Some(cont) = self.coderef_map.get(&callcont) {
-                    self.prog
-                        .add_group_entry(grp, *cont)
-                        .map_err(CodeMapError::Build)?;
+                let cont = self.group(callcont);
+                if !callcont_is_group {
+                    self.group_entries.push((cont, callcont));
                 }
-                self.coderef_map
-                    .insert(ent, self.prog.add_call(call, callcnt, grp));
+                cont
             }
-        }
-        Ok(())
-    }
-    pub(crate) fn add_group(
-        &mut self,
-        ent: EntryRef,
-        elements: &[EntryRef],
-    ) -> Result<(), CodeMapError> {
-        let grp = if let Some(grp) = self.group_map.get(&ent) {
-            debug!("group inserted {:?}", grp);
-            *grp
-        } else {
-            let grp = self.prog.add_empty_group();
-            self.group_map.insert(ent, grp);
-            debug!("new group {:?}", grp);
-            grp
         };
+        let (call, pending) = self.prog.add_pending_call(callcnt, cont);
+        self.coderef_map.insert(ent, call);
+        self.targets.push((pending, callee));
+    }
+    pub(crate) fn add_group(&mut self, ent: EntryRef, elements: &[EntryRef]) {
+        let grp = self.group(ent);
+        debug!("group {:?} for {}", grp, ent);
         for element in elements {
-            let element = self
-                .coderef_map
-                .get(element)
-                .ok_or(CodeMapError::EntryNotFound(*element))?;
+            self.group_entries.push((grp, *element));
+        }
+    }
+    /// Find the group allocated for an entry, or allocate a new one
+    fn group(&mut self, ent: EntryRef) -> GroupRef {
+        if let Some(grp) = self.group_map.get(&ent) {
+            return *grp;
+        }
+        let grp = self.prog.add_empty_group();
+        self.group_map.insert(ent, grp);
+        grp
+    }
+    fn code_ref(&self, ent: EntryRef) -> Result<CodeRef, CodeMapError> {
+        self.coderef_map
+            .get(&ent)
+            .cloned()
+            .ok_or(CodeMapError::EntryNotFound(ent))
+    }
+    /// Patch in all targets and group elements, once all entries are allocated
+    ///
+    pub(crate) fn link(&mut self) -> Result<(), CodeMapError> {
+        for (pending, target) in std::mem::take(&mut self.targets) {
+            let target = self.code_ref(target)?;
             self.prog
-                .add_group_entry(grp, element.clone())
+                .set_target(pending, target)
+                .map_err(CodeMapError::Build)?;
+        }
+        for (grp, element) in std::mem::take(&mut self.group_entries) {
+            let element = self.code_ref(element)?;
+            self.prog
+                .add_group_entry(grp, element)
                 .map_err(CodeMapError::Build)?;
         }
-        self.group_map.insert(ent, grp);
         Ok(())
     }
     pub(crate) fn add_export_group(
@@ -21,7 +21,7 @@ use std::fmt::{Debug, Display, Formatter};
 ///
 /// Extern: Denotes a function that to be executed in the outside world.
 ///
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum Entry {
     Jmp {
         cont: EntryRef,
@@ -93,6 +93,9 @@ impl EntryRef {
     pub fn new(index: usize) -> Self {
         EntryRef { index }
     }
+    pub(crate) fn index(self) -> usize {
+        self.index
+    }
 }
 impl Debug for EntryRef {
     fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
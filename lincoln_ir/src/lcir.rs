@@ -0,0 +1,556 @@
+//! The text assembly format of Lincoln IR (`.lcir` files).
+//!
+//! Every line holds one statement:
+//!
+//! ```text
+//! <label>: jmp <cont> #!<permutation>
+//! <label>: call <callee> <callcnt> <callcont>
+//! <label>: ret <variant>
+//! <label>: group <element>*
+//! extern <name>
+//! export <label>
+//! ```
+//!
+//! Variants of a group can be named by writing elements as
+//! `<name>=<label>`, and a `ret` can then give its variant by name,
+//! e.g. `k: group ok=done err=fail` and `r: ret err`.
+//!
+//! Comments start with `//` and run to the end of the line.
+//! Labels can be referred to before they are defined; a name that is
+//! referred to but never defined is an external function.
+//!
+//! The `Display` output of a `PreCompileProgram` is in this format.
+//!
+use crate::program::PreCompileProgram;
+use core::str::FromStr;
+use lincoln_common::Permutation;
+use std::collections::BTreeMap;
+
+/// Errors may occur when parsing the text format,
+/// with the line and column (both starting from 1) they occur
+///
+#[derive(Fail, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    #[fail(display = "{}:{}: unexpected character {:?}", line, column, found)]
+    UnexpectedChar {
+        line: usize,
+        column: usize,
+        found: char,
+    },
+
+    #[fail(display = "{}:{}: expect {}, found {}", line, column, expect, found)]
+    UnexpectedToken {
+        line: usize,
+        column: usize,
+        expect: &'static str,
+        found: String,
+    },
+
+    #[fail(display = "{}:{}: invalid {} {}", line, column, what, text)]
+    Invalid {
+        line: usize,
+        column: usize,
+        what: &'static str,
+        text: String,
+    },
+
+    #[fail(
+        display = "{}:{}: {} is already defined at line {}",
+        line, column, label, previous
+    )]
+    Redefined {
+        line: usize,
+        column: usize,
+        label: String,
+        previous: usize,
+    },
+
+    #[fail(display = "{}:{}: {} is not defined", line, column, label)]
+    Undefined {
+        line: usize,
+        column: usize,
+        label: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Permutation(String),
+    Colon,
+    Equals,
+    Newline,
+    End,
+}
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(s) => format!("\"{}\"", s),
+            Token::Number(s) => s.clone(),
+            Token::Permutation(s) => format!("#!{}", s),
+            Token::Colon => "\":\"".into(),
+            Token::Equals => "\"=\"".into(),
+            Token::Newline => "end of line".into(),
+            Token::End => "end of file".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pos {
+    line: usize,
+    column: usize,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    pos: Pos,
+}
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Self {
+        Lexer {
+            chars: text.chars().peekable(),
+            pos: Pos { line: 1, column: 1 },
+        }
+    }
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut s = String::new();
+        while let Some(c) = self.chars.peek().cloned().filter(|c| f(*c)) {
+            s.push(c);
+            let _ = self.bump();
+        }
+        s
+    }
+    fn next_token(&mut self) -> Result<(Pos, Token), ParseError> {
+        loop {
+            let _ = self.take_while(|c| c != '\n' && c.is_whitespace());
+            let start = self.pos;
+            let c = match self.chars.peek() {
+                Some(c) => *c,
+                None => return Ok((start, Token::End)),
+            };
+            let token = match c {
+                '\n' => {
+                    let _ = self.bump();
+                    Token::Newline
+                }
+                ':' => {
+                    let _ = self.bump();
+                    Token::Colon
+                }
+                '=' => {
+                    let _ = self.bump();
+                    Token::Equals
+                }
+                '/' => {
+                    let _ = self.bump();
+                    if self.chars.peek() != Some(&'/') {
+                        return Err(ParseError::UnexpectedChar {
+                            line: start.line,
+                            column: start.column,
+                            found: '/',
+                        });
+                    }
+                    let _ = self.take_while(|c| c != '\n');
+                    continue;
+                }
+                '#' => {
+                    let _ = self.bump();
+                    if self.bump() != Some('!') {
+                        return Err(ParseError::Invalid {
+                            line: start.line,
+                            column: start.column,
+                            what: "permutation",
+                            text: "#".into(),
+                        });
+                    }
+                    Token::Permutation(self.take_while(is_ident_continue))
+                }
+                c if c.is_ascii_digit() => Token::Number(self.take_while(is_ident_continue)),
+                c if is_ident_start(c) => Token::Ident(self.take_while(is_ident_continue)),
+                found => {
+                    return Err(ParseError::UnexpectedChar {
+                        line: start.line,
+                        column: start.column,
+                        found,
+                    })
+                }
+            };
+            return Ok((start, token));
+        }
+    }
+}
+
+/// A name with the position it appears
+#[derive(Debug, Clone)]
+struct Name {
+    pos: Pos,
+    name: String,
+}
+
+#[derive(Debug)]
+enum Instruction {
+    Jmp {
+        cont: Name,
+        per: Permutation,
+    },
+    Call {
+        callee: Name,
+        callcnt: u8,
+        callcont: Name,
+    },
+    Ret {
+        variant: u8,
+    },
+    NamedRet {
+        variant: String,
+    },
+    Group {
+        elements: Vec<(Option<String>, Name)>,
+    },
+}
+
+#[derive(Debug)]
+enum Statement {
+    Define { label: Name, inst: Instruction },
+    Extern(Name),
+    Export(Name),
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<(Pos, Token)>,
+}
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Result<&(Pos, Token), ParseError> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next_token()?);
+        }
+        Ok(self.peeked.as_ref().expect("token is peeked"))
+    }
+    fn next(&mut self) -> Result<(Pos, Token), ParseError> {
+        let _ = self.peek()?;
+        Ok(self.peeked.take().expect("token is peeked"))
+    }
+    fn unexpected(pos: Pos, expect: &'static str, found: &Token) -> ParseError {
+        ParseError::UnexpectedToken {
+            line: pos.line,
+            column: pos.column,
+            expect,
+            found: found.describe(),
+        }
+    }
+    fn name(&mut self, expect: &'static str) -> Result<Name, ParseError> {
+        match self.next()? {
+            (pos, Token::Ident(name)) => Ok(Name { pos, name }),
+            (pos, t) => Err(Self::unexpected(pos, expect, &t)),
+        }
+    }
+    fn number(&mut self, what: &'static str) -> Result<u8, ParseError> {
+        match self.next()? {
+            (pos, Token::Number(text)) => text.parse().map_err(|_| ParseError::Invalid {
+                line: pos.line,
+                column: pos.column,
+                what,
+                text,
+            }),
+            (pos, t) => Err(Self::unexpected(pos, what, &t)),
+        }
+    }
+    fn permutation(&mut self) -> Result<Permutation, ParseError> {
+        match self.next()? {
+            (pos, Token::Permutation(text)) => {
+                Permutation::from_str(&text).map_err(|_| ParseError::Invalid {
+                    line: pos.line,
+                    column: pos.column,
+                    what: "permutation",
+                    text: format!("#!{}", text),
+                })
+            }
+            (pos, t) => Err(Self::unexpected(pos, "permutation", &t)),
+        }
+    }
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.next()? {
+            (_, Token::Newline) | (_, Token::End) => Ok(()),
+            (pos, t) => Err(Self::unexpected(pos, "end of line", &t)),
+        }
+    }
+    fn instruction(&mut self) -> Result<Instruction, ParseError> {
+        let (pos, token) = self.next()?;
+        let inst = match token {
+            Token::Ident(ref kw) if kw == "jmp" => Instruction::Jmp {
+                cont: self.name("label")?,
+                per: self.permutation()?,
+            },
+            Token::Ident(ref kw) if kw == "call" => Instruction::Call {
+                callee: self.name("label")?,
+                callcnt: self.number("count")?,
+                callcont: self.name("label")?,
+            },
+            Token::Ident(ref kw) if kw == "ret" => match self.peek()? {
+                (_, Token::Ident(_)) => Instruction::NamedRet {
+                    variant: self.name("variant")?.name,
+                },
+                _ => Instruction::Ret {
+                    variant: self.number("variant")?,
+                },
+            },
+            Token::Ident(ref kw) if kw == "group" => {
+                let mut elements = vec![];
+                while let (_, Token::Ident(_)) = self.peek()? {
+                    let element = self.name("label")?;
+                    if let (_, Token::Equals) = self.peek()? {
+                        let _ = self.next()?;
+                        elements.push((Some(element.name), self.name("label")?));
+                    } else {
+                        elements.push((None, element));
+                    }
+                }
+                Instruction::Group { elements }
+            }
+            t => return Err(Self::unexpected(pos, "jmp, call, ret or group", &t)),
+        };
+        Ok(inst)
+    }
+    /// The next statement, or None at the end of the text
+    fn statement(&mut self) -> Result<Option<Statement>, ParseError> {
+        loop {
+            let (pos, token) = self.next()?;
+            let name = match token {
+                Token::Newline => continue,
+                Token::End => return Ok(None),
+                Token::Ident(name) => Name { pos, name },
+                t => return Err(Self::unexpected(pos, "statement", &t)),
+            };
+            let statement = match self.peek()? {
+                (_, Token::Colon) => {
+                    let _ = self.next()?;
+                    Statement::Define {
+                        label: name,
+                        inst: self.instruction()?,
+                    }
+                }
+                _ if name.name == "extern" => Statement::Extern(self.name("label")?),
+                _ if name.name == "export" => Statement::Export(self.name("label")?),
+                (pos, t) => return Err(Self::unexpected(*pos, "\":\"", t)),
+            };
+            self.end_of_statement()?;
+            return Ok(Some(statement));
+        }
+    }
+}
+
+impl PreCompileProgram {
+    /// Parse a program in the text format
+    ///
+    /// The entries are numbered in the order they are defined or declared,
+    /// so printing a parsed program gives the same text, without comments.
+    ///
+    /// text: the content of a `.lcir` file
+    ///
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            lexer: Lexer::new(text),
+            peeked: None,
+        };
+        let mut statements = vec![];
+        while let Some(statement) = parser.statement()? {
+            statements.push(statement);
+        }
+        let mut prog = PreCompileProgram::default();
+        let mut defined: BTreeMap<&str, usize> = BTreeMap::new();
+        for statement in statements.iter() {
+            let label = match statement {
+                Statement::Define { label, .. } | Statement::Extern(label) => label,
+                Statement::Export(_) => continue,
+            };
+            if let Some(previous) = defined.insert(&label.name, label.pos.line) {
+                return Err(ParseError::Redefined {
+                    line: label.pos.line,
+                    column: label.pos.column,
+                    label: label.name.clone(),
+                    previous,
+                });
+            }
+            let _ = match statement {
+                Statement::Extern(_) => prog.declare_extern(&label.name),
+                _ => prog.define_extern(&label.name),
+            };
+        }
+        for statement in statements.iter() {
+            let (label, defined) = match statement {
+                Statement::Define { label, inst } => (
+                    label,
+                    match inst {
+                        Instruction::Jmp { cont, per } => {
+                            prog.define_jmp(label.name.as_str(), &cont.name, per)
+                        }
+                        Instruction::Call {
+                            callee,
+                            callcnt,
+                            callcont,
+                        } => prog.define_call(
+                            label.name.as_str(),
+                            &callee.name,
+                            *callcnt,
+                            &callcont.name,
+                        ),
+                        Instruction::Ret { variant } => {
+                            prog.define_ret(label.name.as_str(), *variant)
+                        }
+                        Instruction::NamedRet { variant } => {
+                            prog.define_named_ret(label.name.as_str(), variant.as_str())
+                        }
+                        Instruction::Group { elements } => {
+                            let elements: Vec<(Option<&str>, &str)> = elements
+                                .iter()
+                                .map(|(v, e)| (v.as_ref().map(String::as_str), e.name.as_str()))
+                                .collect();
+                            prog.define_named_group(label.name.as_str(), &elements)
+                        }
+                    },
+                ),
+                _ => continue,
+            };
+            if let Err(e) = defined {
+                return Err(ParseError::Invalid {
+                    line: label.pos.line,
+                    column: label.pos.column,
+                    what: "definition",
+                    text: e.to_string(),
+                });
+            }
+        }
+        for statement in statements.iter() {
+            if let Statement::Export(label) = statement {
+                if prog.set_export(&label.name).is_err() {
+                    return Err(ParseError::Undefined {
+                        line: label.pos.line,
+                        column: label.pos.column,
+                        label: label.name.clone(),
+                    });
+                }
+            }
+        }
+        Ok(prog)
+    }
+}
+impl FromStr for PreCompileProgram {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        PreCompileProgram::parse(text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParseError;
+    use crate::PreCompileProgram;
+
+    #[test]
+    fn test_round_trip() {
+        let prog = PreCompileProgram::parse(include_str!("../../bint.lcir")).unwrap();
+        let text = format!("{}", prog);
+        let parsed = PreCompileProgram::parse(&text).unwrap();
+        assert_eq!(format!("{}", parsed), text);
+        assert!(text.contains("test_add4: call add1 2 count\n"));
+        assert!(text.contains("extern from\nextern count\n"));
+
+        let json: PreCompileProgram =
+            serde_json::from_str(include_str!("../../fact.json")).unwrap();
+        let text = format!("{}", json);
+        let parsed: PreCompileProgram = text.parse().unwrap();
+        assert_eq!(format!("{}", parsed), text);
+    }
+
+    #[test]
+    fn test_forward_and_extern() {
+        let text = "// a comment\nmain: call f 1 k // call\nk: jmp ret0 #!\nret0: ret 0\n\
+                    extern f\nexport main\n";
+        let prog = PreCompileProgram::parse(text).unwrap();
+        assert_eq!(
+            format!("{}", prog),
+            "main: call f 1 k\nk: jmp ret0 #!\nret0: ret 0\n\nextern f\n\nexport main\n"
+        );
+    }
+
+    #[test]
+    fn test_named_variants() {
+        let text = "main: call f 0 k\nk: group ok=\n";
+        assert_eq!(
+            format!("{}", PreCompileProgram::parse(text).err().unwrap()),
+            "2:13: expect label, found end of line"
+        );
+        let text = "main: call f 0 k\nk: group ok=done fail\nfail: ret err\n\nextern f\n\n";
+        let prog = PreCompileProgram::parse(text).unwrap();
+        assert_eq!(prog.variant_names("k").unwrap(), &[Some("ok".into()), None]);
+        assert_eq!(prog.return_name("fail"), Some("err"));
+        assert_eq!(
+            format!("{}", prog),
+            "main: call f 0 k\nk: group ok=done fail \nfail: ret err\n\nextern f\nextern done\n\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text| PreCompileProgram::parse(text).err().unwrap();
+        assert_eq!(
+            error("a: ret 0\nb: jmp a ba\n"),
+            ParseError::UnexpectedToken {
+                line: 2,
+                column: 10,
+                expect: "permutation",
+                found: "\"ba\"".into(),
+            }
+        );
+        assert_eq!(
+            error("a: ret 300"),
+            ParseError::Invalid {
+                line: 1,
+                column: 8,
+                what: "variant",
+                text: "300".into(),
+            }
+        );
+        assert_eq!(
+            error("a: ret 0\n  a: ret 1"),
+            ParseError::Redefined {
+                line: 2,
+                column: 3,
+                label: "a".into(),
+                previous: 1,
+            }
+        );
+        assert_eq!(
+            format!("{}", error("a: ret 0\nexport b")),
+            "2:8: b is not defined"
+        );
+        assert_eq!(
+            format!("{}", error("a: jmp a #!ab$")),
+            "1:14: unexpected character '$'"
+        );
+        assert_eq!(
+            format!("{}", error("a: ret 1 2")),
+            "1:10: expect end of line, found 2"
+        );
+    }
+}
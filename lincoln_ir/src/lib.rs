@@ -6,12 +6,23 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+mod arity;
 mod codemap;
 mod entry;
+mod lcir;
+mod merge;
 mod program;
+mod refactor;
+mod scc;
+mod variants;
 
 #[cfg(test)]
 mod tests;
 
+pub use arity::{ArityIssue, ArityReport};
 pub use entry::{Entry, EntryRef};
-pub use program::PreCompileProgram;
+pub use lcir::ParseError;
+pub use merge::{MergeConflict, MergeMode, MergeReport};
+pub use program::{PreCompileProgram, UndeclaredExtern};
+pub use refactor::RefactorError;
+pub use variants::VariantError;
@@ -0,0 +1,193 @@
+use crate::entry::{Entry, EntryRef};
+use crate::program::PreCompileProgram;
+use core::fmt::{Display, Formatter};
+use failure::{Error, Fail};
+use std::collections::BTreeMap;
+
+/// How to merge labels defined differently in both programs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeMode {
+    /// Fail without changing anything
+    Fail,
+    /// Keep our definitions; their entries refer to ours instead
+    PreferOurs,
+    /// Overwrite our definitions with theirs
+    PreferTheirs,
+    /// Rename their labels by adding a prefix, until they are unique
+    Prefix(String),
+}
+
+/// Labels defined differently in both programs, when merging in `Fail` mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub labels: Vec<String>,
+}
+impl Display for MergeConflict {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "conflicting definitions of {}", self.labels.join(", "))
+    }
+}
+impl Fail for MergeConflict {}
+
+/// What a merge did with the labels of the other program
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Labels that were not defined in this program
+    pub added: Vec<String>,
+    /// Labels whose definitions in this program were replaced
+    pub overwritten: Vec<String>,
+    /// Labels whose definitions in this program were kept
+    pub kept: Vec<String>,
+    /// Labels that were renamed, with their new names
+    pub renamed: Vec<(String, String)>,
+}
+impl MergeReport {
+    /// Whether any definition was replaced, ignored or renamed
+    pub fn has_conflicts(&self) -> bool {
+        !(self.overwritten.is_empty() && self.kept.is_empty() && self.renamed.is_empty())
+    }
+}
+impl Display for MergeReport {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "{} added", self.added.len())?;
+        if !self.overwritten.is_empty() {
+            write!(fmt, ", overwritten: {}", self.overwritten.join(", "))?;
+        }
+        if !self.kept.is_empty() {
+            write!(fmt, ", kept ours: {}", self.kept.join(", "))?;
+        }
+        for (from, to) in self.renamed.iter() {
+            write!(fmt, ", renamed {} to {}", from, to)?;
+        }
+        Ok(())
+    }
+}
+
+impl PreCompileProgram {
+    /// The labels defined in both programs with different instructions.
+    /// Externs are not conflicts: they are linked to the definitions
+    /// in the other program.
+    ///
+    pub fn merge_conflicts(&self, other: &PreCompileProgram) -> Vec<String> {
+        other
+            .defined_ent
+            .iter()
+            .filter(|(label, theirs)| {
+                let ours = match self.defined_ent.get(*label) {
+                    Some(ours) => *ours,
+                    None => return false,
+                };
+                !self.is_extern(ours)
+                    && !other.is_extern(**theirs)
+                    && self.definition(ours).ok() != other.definition(**theirs).ok()
+            })
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+    /// Merge the instructions, externs and exports from another program
+    ///
+    /// Nothing is changed if the merge fails.
+    ///
+    /// other: the program to merge from
+    /// mode: what to do with the labels defined differently in both programs
+    ///
+    pub fn merge_with(
+        &mut self,
+        other: &PreCompileProgram,
+        mode: MergeMode,
+    ) -> Result<MergeReport, Error> {
+        let conflicts = self.merge_conflicts(other);
+        let mut report = MergeReport::default();
+        let mut renamed: BTreeMap<&str, String> = BTreeMap::new();
+        match &mode {
+            MergeMode::Fail if !conflicts.is_empty() => {
+                return Err(MergeConflict { labels: conflicts }.into())
+            }
+            MergeMode::Prefix(prefix) => {
+                for label in conflicts.iter() {
+                    let mut name = format!("{}{}", prefix, label);
+                    while self.defined_ent.contains_key(&name)
+                        || other.defined_ent.contains_key(&name)
+                    {
+                        name = format!("{}{}", prefix, name);
+                    }
+                    report.renamed.push((label.clone(), name.clone()));
+                    let _ = renamed.insert(label.as_str(), name);
+                }
+            }
+            _ => (),
+        }
+        // Merge into a copy, so nothing is changed if a definition fails
+        let mut merged = self.clone();
+        let map = |name: &str| {
+            renamed
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| String::from(name))
+        };
+        let name_of = |ent: EntryRef| other.find_name(ent).map(map);
+        for name in other.declared_externs.iter() {
+            let _ = merged.declare_extern(map(name))?;
+        }
+        for item in other.iterate() {
+            let (_, theirs, entry) = item?;
+            if let Entry::Extern { .. } = entry {
+                let _ = merged.define_extern(theirs)?;
+                continue;
+            }
+            if conflicts.iter().any(|c| c == theirs) {
+                match mode {
+                    MergeMode::PreferOurs => {
+                        report.kept.push(theirs.into());
+                        continue;
+                    }
+                    MergeMode::PreferTheirs => report.overwritten.push(theirs.into()),
+                    _ => (),
+                }
+            } else if merged.is_new(theirs) {
+                report.added.push(theirs.into());
+            }
+            let label = map(theirs);
+            let _ = match entry {
+                Entry::Jmp { cont, per } => merged.define_jmp(label, name_of(*cont)?, per)?,
+                Entry::Call {
+                    callee,
+                    callcnt,
+                    callcont,
+                } => merged.define_call(label, name_of(*callee)?, *callcnt, name_of(*callcont)?)?,
+                Entry::Ret { variant } => match other.named_returns.get(theirs) {
+                    Some(name) => merged.define_named_ret(label, name.as_str())?,
+                    None => merged.define_ret(label, *variant)?,
+                },
+                Entry::Group { elements } => {
+                    let names = other.variant_names.get(theirs);
+                    let mut v = vec![];
+                    for (idx, element) in elements.iter().enumerate() {
+                        let variant = names.and_then(|n| n.get(idx)).and_then(|n| n.as_ref());
+                        v.push((variant.map(String::as_str), name_of(*element)?));
+                    }
+                    let v: Vec<(Option<&str>, &str)> =
+                        v.iter().map(|(n, e)| (*n, e.as_str())).collect();
+                    merged.define_named_group(label, &v)?
+                }
+                Entry::Extern { .. } => continue,
+            };
+        }
+        for export in other.exports.iter() {
+            merged.set_export(map(export))?;
+        }
+        *self = merged;
+        Ok(report)
+    }
+
+    fn is_extern(&self, ent: EntryRef) -> bool {
+        matches!(self.entry(ent.index()), Ok(Entry::Extern { .. }))
+    }
+    /// Whether a label is not defined, or only as an extern
+    fn is_new(&self, label: &str) -> bool {
+        self.defined_ent
+            .get(label)
+            .map(|ent| self.is_extern(*ent))
+            .unwrap_or(true)
+    }
+}
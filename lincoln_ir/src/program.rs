@@ -1,47 +1,38 @@
+use crate::arity::find_resumes;
 use crate::codemap::CodeMap;
 use crate::entry::{Entry, EntryRef};
+use crate::merge::MergeMode;
+use crate::scc::{find_cycle, strongly_connected};
 use core::fmt::{Debug, Display, Formatter};
-use failure::Error;
+use failure::{Error, Fail};
 use lincoln_common::{Access, AccessMut, StringLike, AsPermutation, Permutation};
-use lincoln_compiled::{ExternEntry, Program};
+use lincoln_compiled::{CodeRef, ExternEntry, Labels, Program};
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct PreCompileProgram {
-    defined_ent: BTreeMap<String, EntryRef>,
-    entries: Vec<Entry>,
-    exports: BTreeSet<String>,
+    pub(crate) defined_ent: BTreeMap<String, EntryRef>,
+    pub(crate) entries: Vec<Entry>,
+    pub(crate) exports: BTreeSet<String>,
+    #[serde(default)]
+    pub(crate) declared_externs: BTreeSet<String>,
+    #[serde(default)]
+    pub(crate) strict: bool,
+    /// The names of the variants of groups, by the labels of the groups
+    #[serde(default)]
+    pub(crate) variant_names: BTreeMap<String, Vec<Option<String>>>,
+    /// The variant names rets refer to, by the labels of the rets
+    #[serde(default)]
+    pub(crate) named_returns: BTreeMap<String, String>,
 }
 impl Display for PreCompileProgram {
     fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
         for (idx, ent) in self.entries.iter().enumerate() {
-            let label = self.find_name(EntryRef::new(idx))?;
-            match ent {
-                Entry::Jmp { cont, per } => {
-                    writeln!(fmt, "{}: jmp {} #!{}", label, self.find_name(*cont)?, per)?
-                }
-                Entry::Call {
-                    callee,
-                    callcnt,
-                    callcont,
-                } => writeln!(
-                    fmt,
-                    "{}: call {} {} {}",
-                    label,
-                    self.find_name(*callee)?,
-                    callcnt,
-                    self.find_name(*callcont)?
-                )?,
-                Entry::Ret { variant } => writeln!(fmt, "{}: ret {}", label, variant)?,
-                Entry::Group { elements } => {
-                    write!(fmt, "{}: group ", label)?;
-                    for element in elements.iter() {
-                        write!(fmt, "{} ", self.find_name(*element)?)?;
-                    }
-                    writeln!(fmt)?;
-                }
-                Entry::Extern { .. } => (),
+            if let Entry::Extern { .. } = ent {
+                continue;
             }
+            let ent = EntryRef::new(idx);
+            writeln!(fmt, "{}: {}", self.find_name(ent)?, self.definition(ent)?)?;
         }
         writeln!(fmt)?;
         for ent in self.entries.iter() {
@@ -73,55 +64,11 @@ impl Debug for PreCompileProgram {
     }
 }
 impl PreCompileProgram {
-    /// merge the instructions from another program
+    /// merge the instructions from another program,
+    /// overwriting the labels defined in both
     ///
     pub fn merge(&mut self, other: &PreCompileProgram) -> Result<(), Error> {
-        for inst in other.iterate() {
-            match inst {
-                (_, _, Entry::Extern { .. }) => {}
-                (
-                    is_export,
-                    name,
-                    Entry::Call {
-                        callee,
-                        callcnt,
-                        callcont,
-                    },
-                ) => {
-                    let _ = self.define_call(
-                        name,
-                        other.find_name(*callee)?,
-                        *callcnt,
-                        other.find_name(*callcont)?,
-                    )?;
-                    if is_export {
-                        self.set_export(name)?
-                    }
-                }
-                (is_export, name, Entry::Group { elements }) => {
-                    let mut v = vec![];
-                    for element in elements {
-                        v.push(other.find_name(*element)?)
-                    }
-                    let _ = self.define_group(name, &v)?;
-                    if is_export {
-                        self.set_export(name)?
-                    }
-                }
-                (is_export, name, Entry::Jmp { cont, per }) => {
-                    let _ = self.define_jmp(name, other.find_name(*cont)?, per)?;
-                    if is_export {
-                        self.set_export(name)?
-                    }
-                }
-                (is_export, name, Entry::Ret { variant }) => {
-                    let _ = self.define_ret(name, *variant)?;
-                    if is_export {
-                        self.set_export(name)?
-                    }
-                }
-            }
-        }
+        let _ = self.merge_with(other, MergeMode::PreferTheirs)?;
         Ok(())
     }
     /// Set an entry to be exported
@@ -138,6 +85,8 @@ impl PreCompileProgram {
         let labelent = self.defined_ent.get(label.as_str());
         if let Some(ent) = labelent {
             let ent = *ent;
+            let _ = self.variant_names.remove(label.as_str());
+            let _ = self.named_returns.remove(label.as_str());
             *ent.access_mut(self)? = Entry::Extern {
                 name: label.to_string(),
             };
@@ -220,6 +169,139 @@ impl PreCompileProgram {
         )?;
         self.define_group_internal(label, elements)
     }
+    /// Define a return instruction referring to its variant by name
+    ///
+    /// The name is resolved to the number of the variant of the
+    /// continuation groups when compiling.
+    ///
+    /// name: the name of the variant
+    ///
+    pub fn define_named_ret(
+        &mut self,
+        label: impl StringLike,
+        name: impl StringLike,
+    ) -> Result<EntryRef, Error> {
+        let r = self.define_ret(label.clone_string(), 0)?;
+        let _ = self
+            .named_returns
+            .insert(label.clone_string(), name.clone_string());
+        Ok(r)
+    }
+    /// Define a group of instructions with named variants
+    ///
+    /// label: the name of this group entry
+    /// elements: the variant entries, each with an optional variant name
+    ///
+    pub fn define_named_group(
+        &mut self,
+        label: impl StringLike,
+        elements: &[(Option<&str>, &str)],
+    ) -> Result<EntryRef, Error> {
+        let mut names: Vec<Option<String>> = vec![];
+        for (name, _) in elements.iter() {
+            if let Some(name) = name {
+                if names
+                    .iter()
+                    .any(|n| n.as_ref().map(String::as_str) == Some(*name))
+                {
+                    bail!("variant {} is named twice", name);
+                }
+            }
+            names.push(name.map(String::from));
+        }
+        let entries: Vec<&str> = elements.iter().map(|(_, e)| *e).collect();
+        let r = self.define_group(label.clone_string(), &entries)?;
+        if names.iter().any(Option::is_some) {
+            let _ = self.variant_names.insert(label.clone_string(), names);
+        }
+        Ok(r)
+    }
+    /// The names of the variants of a group, None for unnamed variants
+    ///
+    /// label: the name of the group entry
+    ///
+    pub fn variant_names(&self, label: &str) -> Option<&[Option<String>]> {
+        self.variant_names.get(label).map(Vec::as_slice)
+    }
+    /// The name of the variant a return instruction refers to, if any
+    ///
+    /// label: the name of the return entry
+    ///
+    pub fn return_name(&self, label: &str) -> Option<&str> {
+        self.named_returns.get(label).map(String::as_str)
+    }
+    /// Declare an external function
+    ///
+    /// In strict mode, only declared names can be referred to without
+    /// being defined.
+    ///
+    /// name: the name of the external function
+    ///
+    pub fn declare_extern(&mut self, name: impl StringLike) -> Result<EntryRef, Error> {
+        let _ = self.declared_externs.insert(name.clone_string());
+        self.define_extern_or_entry(name)
+    }
+    /// Turn on or off strict mode. In strict mode, compiling fails if
+    /// an undefined name is referred to without being declared as extern.
+    ///
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    /// Whether the program is in strict mode
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+    /// Find the names referred to without being either defined or declared
+    /// as extern, with suggestions of similar names
+    ///
+    pub fn undeclared_externs(&self) -> Vec<UndeclaredExtern> {
+        let known: Vec<&str> = self
+            .defined_ent
+            .iter()
+            .filter(|(name, ent)| {
+                self.declared_externs.contains(name.as_str())
+                    || !matches!(ent.access(self), Ok(Entry::Extern { .. }))
+            })
+            .map(|(name, _)| name.as_str())
+            .collect();
+        let mut undeclared = vec![];
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let name = match entry {
+                Entry::Extern { name } if !self.declared_externs.contains(name) => name,
+                _ => continue,
+            };
+            let ent = EntryRef::new(idx);
+            if !self.is_referenced(ent) {
+                continue;
+            }
+            let used_by = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| self.dependencies(e).contains(&ent))
+                .filter_map(|(i, _)| self.find_name(EntryRef::new(i)).ok())
+                .map(String::from)
+                .collect();
+            undeclared.push(UndeclaredExtern {
+                name: name.clone(),
+                used_by,
+                suggestions: suggest(name, &known),
+            });
+        }
+        undeclared
+    }
+    /// The provided external functions that are not used by this program
+    ///
+    /// provided: the names of the external functions
+    ///
+    pub fn unused_externs<'a>(&self, provided: impl Iterator<Item = &'a str>) -> Vec<String> {
+        let mut unused: Vec<String> = provided
+            .filter(|name| !self.externs().iter().any(|(n, _)| n == name))
+            .map(String::from)
+            .collect();
+        unused.sort();
+        unused
+    }
     /// Returns all defined external entries, including the name
     ///
     pub fn externs(&self) -> Vec<(String, EntryRef)> {
@@ -232,73 +314,85 @@ impl PreCompileProgram {
             })
             .collect()
     }
-    /// Compile this program with a set of external functions
+    /// Compile this program with a set of external functions.
+    /// The compiled entries and groups are named with their labels
+    /// in the debug information of the compiled program.
+    /// Provided functions the program does not use are ignored,
+    /// see `unused_externs` to report them.
     ///
     pub fn compile(&self, externs: impl Iterator<Item = ExternEntry>) -> Result<Program, Error> {
         let mut cm = CodeMap::new();
-        let ds = self.dependency_sort();
-        let sorted = ds
-            .iter()
-            .flat_map(|(_, e)| e)
-            .collect::<BTreeSet<&EntryRef>>();
-        // dependency_sort will put different entries into categories where
-        // level 0 are externs, level 1 are returns or only refering to externs,
-        // level 2 are only refering level 1 or less, etc.
+        // compile_order puts every entry after the entries it refers to,
+        // except for entries referring to each other in a circle.
         //
-        // If there is an entry have not been sourt out, this entry must be in a circular reference.
-        // We then find sucn entries and report error on those.
+        // A circle of jmps only can never be left. We then report the circles
+        // so the user can find the missing exit.
         //
-        if sorted.len() != self.entries.len() {
-            error!("{} entries was found involved in a circular reference without groups (call conts).",
-                self.entries.len()-sorted.len());
-            for v in self
-                .entries
-                .iter()
-                .enumerate()
-                .filter(|(i, _)| !sorted.contains(&EntryRef::new(*i)))
-            {
-                error!("{}", self.find_name(EntryRef::new(v.0))?);
+        let order = match self.compile_order() {
+            Ok(order) => order,
+            Err(cycles) => {
+                error!("{} circular jmps were found without exits.", cycles.len());
+                for cycle in cycles.iter() {
+                    error!("{}", cycle);
+                }
+                bail!("circular jmp detected: {}", cycles[0]);
+            }
+        };
+        if self.strict {
+            let undeclared = self.undeclared_externs();
+            if let Some(first) = undeclared.first() {
+                for u in undeclared.iter() {
+                    error!("{}", u);
+                }
+                bail!("{}", first);
             }
-            bail!("circular reference detected");
+        }
+        let (variants, errors) = self.resolve_variants(&find_resumes(self));
+        if let Some(first) = errors.first() {
+            for e in errors.iter() {
+                error!("{}", e);
+            }
+            bail!("{}", first);
         }
         let mut externs_map: HashMap<String, ExternEntry> = HashMap::new();
         for ext in externs {
             let name = ext.name().into();
             externs_map.insert(name, ext);
         }
-        // Starting from the lowest level, we add compiled instructions to the compiled program.
+        // Starting from the entries without dependencies, we add compiled instructions to the compiled program.
+        // Targets are patched in by `link` afterwards, so circular references are allowed.
         //
-        for (_level, entries) in ds {
-            for entry in entries {
-                let entryref = entry;
-                let entry = entry.access(&self)?;
-                match entry {
-                    Entry::Extern { name } => {
-                        if let Some(e) = externs_map.remove(name) {
-                            cm.add_extern(entryref, e);
-                        } else {
-                            bail!("Extern entry not found {}", name);
-                        }
-                    }
-                    Entry::Ret { variant } => {
-                        cm.add_return(entryref, *variant);
-                    }
-                    Entry::Jmp { cont, per } => {
-                        cm.add_jmp(entryref, *cont, *per)?;
-                    }
-                    Entry::Call {
-                        callee,
-                        callcnt,
-                        callcont,
-                    } => {
-                        cm.add_call(entryref, *callee, *callcnt, *callcont)?;
-                    }
-                    Entry::Group { elements } => {
-                        cm.add_group(entryref, elements)?;
+        for entryref in order {
+            let entry = entryref.access(&self)?;
+            match entry {
+                Entry::Extern { name } => {
+                    if let Some(e) = externs_map.remove(name) {
+                        cm.add_extern(entryref, e);
+                    } else if self.is_referenced(entryref) || self.declared_externs.contains(name) {
+                        bail!("Extern entry not found {}", name);
                     }
                 }
+                Entry::Ret { variant } => {
+                    let variant = variants.get(&entryref).unwrap_or(variant);
+                    cm.add_return(entryref, *variant);
+                }
+                Entry::Jmp { cont, per } => {
+                    cm.add_jmp(entryref, *cont, *per);
+                }
+                Entry::Call {
+                    callee,
+                    callcnt,
+                    callcont,
+                } => {
+                    let is_group = callcont.is_group_in(self);
+                    cm.add_call(entryref, *callee, *callcnt, *callcont, is_group);
+                }
+                Entry::Group { elements } => {
+                    cm.add_group(entryref, elements);
+                }
             }
         }
+        cm.link()?;
         // Mark exports
         for export in self.exports.iter() {
             let name = export.clone();
@@ -315,7 +409,36 @@ impl PreCompileProgram {
                 }
             }
         }
-        Ok(cm.destruct().0)
+        let (mut prog, coderef_map, group_map) = cm.destruct();
+        let names: HashMap<EntryRef, &str> = self
+            .defined_ent
+            .iter()
+            .map(|(name, ent)| (*ent, name.as_str()))
+            .collect();
+        let mut labels = Labels::new();
+        for (ent, coderef) in coderef_map {
+            if let (CodeRef::Entry(e), Some(name)) = (coderef, names.get(&ent)) {
+                labels.set_entry(e, *name);
+                if let Some(variant) = self.named_returns.get(*name) {
+                    labels.set_return_name(e, variant.as_str());
+                }
+            }
+        }
+        for (ent, grp) in group_map {
+            if let Some(name) = names.get(&ent) {
+                labels.set_group(grp, *name);
+                if let Some(variants) = self.variant_names.get(*name) {
+                    labels.set_variant_names(grp, variants.clone());
+                }
+            }
+        }
+        for export in prog.iterate_exports() {
+            if labels.group(export.g).is_none() {
+                labels.set_group(export.g, export.name.as_str());
+            }
+        }
+        prog.set_labels(labels);
+        Ok(prog)
     }
 
     pub(crate) fn entry(&self, idx: usize) -> Result<&Entry, Error> {
@@ -333,7 +456,45 @@ impl PreCompileProgram {
         }
     }
 
-    fn find_name(&self, entry: EntryRef) -> Result<&str, std::fmt::Error> {
+    /// The instruction of an entry in the text format,
+    /// referring to other entries by their labels
+    ///
+    pub(crate) fn definition(&self, ent: EntryRef) -> Result<String, std::fmt::Error> {
+        let label = self.find_name(ent)?;
+        let entry = ent.access(self).map_err(|_| std::fmt::Error)?;
+        Ok(match entry {
+            Entry::Jmp { cont, per } => format!("jmp {} #!{}", self.find_name(*cont)?, per),
+            Entry::Call {
+                callee,
+                callcnt,
+                callcont,
+            } => format!(
+                "call {} {} {}",
+                self.find_name(*callee)?,
+                callcnt,
+                self.find_name(*callcont)?
+            ),
+            Entry::Ret { variant } => match self.named_returns.get(label) {
+                Some(name) => format!("ret {}", name),
+                None => format!("ret {}", variant),
+            },
+            Entry::Group { elements } => {
+                let mut r = String::from("group ");
+                let names = self.variant_names.get(label);
+                for (idx, element) in elements.iter().enumerate() {
+                    if let Some(Some(name)) = names.and_then(|n| n.get(idx)) {
+                        r.push_str(name);
+                        r.push('=');
+                    }
+                    r.push_str(self.find_name(*element)?);
+                    r.push(' ');
+                }
+                r
+            }
+            Entry::Extern { name } => format!("extern {}", name),
+        })
+    }
+    pub(crate) fn find_name(&self, entry: EntryRef) -> Result<&str, std::fmt::Error> {
         for e in self.defined_ent.iter() {
             if entry == *e.1 {
                 return Ok(&e.0);
@@ -348,6 +509,8 @@ impl PreCompileProgram {
     ) -> Result<EntryRef, Error> {
         let idx = self.entries.len();
         let ret = EntryRef::new(idx);
+        let _ = self.variant_names.remove(label.as_str());
+        let _ = self.named_returns.remove(label.as_str());
         let labelent = self.defined_ent.get(label.as_str());
         if let Some(ext) = labelent {
             let ent_orig = format!("{}", ext.access(self)?);
@@ -360,6 +523,13 @@ impl PreCompileProgram {
         }
         Ok(ret)
     }
+    /// Declare an external function, unless the name is already defined
+    ///
+    /// name: the name of the external function
+    ///
+    pub(crate) fn define_extern(&mut self, name: impl StringLike) -> Result<EntryRef, Error> {
+        self.define_extern_or_entry(name)
+    }
     fn define_extern_or_entry(&mut self, name: impl StringLike) -> Result<EntryRef, Error> {
         if let Some(ent) = self.defined_ent.get(name.as_str()) {
             return Ok(*ent);
@@ -402,56 +572,29 @@ impl PreCompileProgram {
         self.define_ent_internal(name, ent)
     }
 
-    fn find_refs(&self, seed: &BTreeSet<EntryRef>) -> BTreeSet<EntryRef> {
-        self.entries
-            .iter()
-            .enumerate()
-            .filter_map(|(index, e)| match e {
-                Entry::Call {
-                    callee, callcont, ..
-                } => {
-                    if seed.iter().any(|e| *e == *callee)
-                        && (callcont.is_group_in(self) || seed.iter().any(|e| *e == *callcont))
-                    {
-                        Some(EntryRef::new(index))
-                    } else {
-                        None
-                    }
-                }
-                Entry::Jmp { cont, .. } => {
-                    if seed.iter().any(|e| *e == *cont) {
-                        Some(EntryRef::new(index))
-                    } else {
-                        None
-                    }
-                }
-                Entry::Group { elements } => {
-                    for element in elements {
-                        seed.iter().find(|e| *e == element)?;
-                    }
-                    Some(EntryRef::new(index))
-                }
-                Entry::Ret { .. } => Some(EntryRef::new(index)),
-                _ => None,
-            })
-            .collect()
-    }
-    fn iterate(&self) -> impl Iterator<Item = (bool, &str, &Entry)> {
+    /// Iterate the entries with their labels, and whether they are exported.
+    /// An entry without a label is an error, ending the iteration.
+    ///
+    pub(crate) fn iterate(
+        &self,
+    ) -> impl Iterator<Item = Result<(bool, &str, &Entry), std::fmt::Error>> {
         struct PIterator<'name>(usize, &'name PreCompileProgram, Vec<EntryRef>);
         impl<'name> Iterator for PIterator<'name> {
-            type Item = (bool, &'name str, &'name Entry);
+            type Item = Result<(bool, &'name str, &'name Entry), std::fmt::Error>;
             fn next(&mut self) -> Option<Self::Item> {
                 let entref = EntryRef::new(self.0);
                 let ent = entref.access(self.1);
                 if let Ok(ent) = ent {
                     let is_export = self.2.contains(&entref);
-                    let name = if let Ok(name) = self.1.find_name(EntryRef::new(self.0)) {
-                        name
-                    } else {
-                        return None;
+                    let name = match self.1.find_name(EntryRef::new(self.0)) {
+                        Ok(name) => name,
+                        Err(e) => {
+                            self.0 = self.1.entries.len();
+                            return Some(Err(e));
+                        }
                     };
                     self.0 += 1;
-                    Some((is_export, name, ent))
+                    Some(Ok((is_export, name, ent)))
                 } else {
                     None
                 }
@@ -464,24 +607,137 @@ impl PreCompileProgram {
             .collect();
         PIterator(0, self, exps)
     }
-    fn dependency_sort(&self) -> BTreeMap<usize, BTreeSet<EntryRef>> {
-        let mut r = BTreeMap::new();
-        let mut s1: BTreeSet<EntryRef> = self.externs().iter().map(|(_, e)| *e).collect();
-
-        let _ = r.insert(0, s1.clone());
-        let mut i = 1;
-        loop {
-            let s2 = self.find_refs(&s1);
-            let s2: BTreeSet<EntryRef> = s2.difference(&s1).copied().collect();
-            if s2.is_empty() {
-                break;
+    /// Whether an entry is exported or referred to by any entry
+    fn is_referenced(&self, ent: EntryRef) -> bool {
+        self.exports
+            .iter()
+            .any(|e| self.defined_ent.get(e) == Some(&ent))
+            || self
+                .entries
+                .iter()
+                .any(|e| self.dependencies(e).contains(&ent))
+    }
+    /// The entries an entry refers to.
+    ///
+    /// A call does not depend on a group continuation, as a closure
+    /// of the group is only created when the call runs.
+    ///
+    fn dependencies(&self, entry: &Entry) -> Vec<EntryRef> {
+        match entry {
+            Entry::Jmp { cont, .. } => vec![*cont],
+            Entry::Call {
+                callee, callcont, ..
+            } => {
+                if callcont.is_group_in(self) {
+                    vec![*callee]
+                } else {
+                    vec![*callee, *callcont]
+                }
             }
-            s1 = s1.union(&s2).copied().collect();
-            let _ = r.insert(i, s2);
-            i += 1;
+            Entry::Group { elements } => elements.clone(),
+            Entry::Ret { .. } | Entry::Extern { .. } => vec![],
         }
-        r
     }
+    /// Sort the entries so that every entry comes after its dependencies,
+    /// keeping entries that refer to each other in a circle together.
+    ///
+    /// returns: the sorted entries, or the circles made of jmps only,
+    ///          written as `a -> b -> a`
+    ///
+    fn compile_order(&self) -> Result<Vec<EntryRef>, Vec<String>> {
+        let graph: Vec<Vec<usize>> = self
+            .entries
+            .iter()
+            .map(|e| {
+                self.dependencies(e)
+                    .into_iter()
+                    .map(|d| d.index())
+                    .collect()
+            })
+            .collect();
+        let mut order = vec![];
+        let mut cycles = vec![];
+        for component in strongly_connected(&graph) {
+            let jmps_only = component
+                .iter()
+                .all(|i| matches!(self.entries[*i], Entry::Jmp { .. }));
+            match find_cycle(&graph, &component) {
+                Some(cycle) if jmps_only => cycles.push(
+                    cycle
+                        .into_iter()
+                        .map(|i| self.find_name(EntryRef::new(i)).unwrap_or("?"))
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                ),
+                _ => order.extend(component.into_iter().rev().map(EntryRef::new)),
+            }
+        }
+        if cycles.is_empty() {
+            Ok(order)
+        } else {
+            Err(cycles)
+        }
+    }
+}
+
+/// A name referred to without being defined or declared as extern
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndeclaredExtern {
+    /// The name referred to
+    pub name: String,
+    /// The entries referring to the name
+    pub used_by: Vec<String>,
+    /// Defined or declared names similar to the name, the most similar first
+    pub suggestions: Vec<String>,
+}
+impl Display for UndeclaredExtern {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        write!(fmt, "{} is neither defined nor declared extern", self.name)?;
+        if !self.used_by.is_empty() {
+            write!(fmt, " (used by {})", self.used_by.join(", "))?;
+        }
+        match self.suggestions.split_last() {
+            None => Ok(()),
+            Some((last, [])) => write!(fmt, ", did you mean {}?", last),
+            Some((last, rest)) => write!(fmt, ", did you mean {} or {}?", rest.join(", "), last),
+        }
+    }
+}
+impl Fail for UndeclaredExtern {}
+
+/// The edit distance between two strings, counting insertions,
+/// deletions and substitutions of characters
+///
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Up to 3 known names close enough to a name to be a typo of it
+fn suggest(name: &str, known: &[&str]) -> Vec<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    let mut candidates: Vec<(usize, &str)> = known
+        .iter()
+        .map(|k| (edit_distance(name, k), *k))
+        .filter(|(d, _)| *d <= limit)
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(3)
+        .map(|(_, k)| String::from(k))
+        .collect()
 }
 
 fn collect_successful<T, E>(i: impl Iterator<Item = Result<T, E>>) -> Result<Vec<T>, E> {
@@ -0,0 +1,317 @@
+use crate::entry::{Entry, EntryRef};
+use crate::program::PreCompileProgram;
+use core::fmt::{Display, Formatter};
+use failure::{Error, Fail};
+use lincoln_common::{Access, AccessMut, Permutation, StringLike};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Reasons a refactoring cannot be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefactorError {
+    /// The label is not defined
+    NotFound { label: String },
+    /// The new label is already defined
+    AlreadyDefined { label: String },
+    /// The entry is still referred to by other entries, or exported
+    StillUsed { label: String, used_by: Vec<String> },
+    /// The entry is not a jmp
+    NotJmp { label: String },
+    /// The target of the jmp cannot be merged into it
+    CannotInline { label: String, target: String },
+    /// A group cannot be a variant of another group
+    GroupElement { label: String },
+    /// The permutation of the jmp needs more than 20 values
+    InvalidPermutation { label: String },
+}
+impl Display for RefactorError {
+    fn fmt(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        match self {
+            RefactorError::NotFound { label } => write!(fmt, "{} is not defined", label),
+            RefactorError::AlreadyDefined { label } => {
+                write!(fmt, "{} is already defined", label)
+            }
+            RefactorError::StillUsed { label, used_by } => {
+                write!(fmt, "{} is still used by {}", label, used_by.join(", "))
+            }
+            RefactorError::NotJmp { label } => write!(fmt, "{} is not a jmp", label),
+            RefactorError::CannotInline { label, target } => {
+                write!(fmt, "{} cannot be inlined into {}", target, label)
+            }
+            RefactorError::GroupElement { label } => {
+                write!(fmt, "{} is a group and cannot be a variant", label)
+            }
+            RefactorError::InvalidPermutation { label } => {
+                write!(fmt, "{} permutates more than 20 values", label)
+            }
+        }
+    }
+}
+impl Fail for RefactorError {}
+
+/// The permutation performing `first` and then `second`,
+/// if neither needs more than 20 values
+fn compose(first: Permutation, second: Permutation) -> Option<Permutation> {
+    let len = first.min_len().max(second.min_len());
+    if len > Permutation::MAX_LEN {
+        return None;
+    }
+    let mut letters = b"abcdefghijklmnopqrst"[..len as usize].to_vec();
+    first.permutate(&mut letters);
+    second.permutate(&mut letters);
+    let composed = String::from_utf8(letters)
+        .expect("letters are ascii")
+        .parse()
+        .expect("letters are a permutation");
+    Some(composed)
+}
+
+impl PreCompileProgram {
+    /// Rename a label. References are kept, as entries refer to
+    /// each other by position; an extern is renamed together with
+    /// the external function it refers to.
+    ///
+    /// from: the current name of the entry
+    /// to: the new name of the entry
+    ///
+    pub fn rename(&mut self, from: impl StringLike, to: impl StringLike) -> Result<(), Error> {
+        let (from, to) = (from.clone_string(), to.clone_string());
+        let ent = self.lookup(&from)?;
+        if self.defined_ent.contains_key(&to) {
+            return Err(RefactorError::AlreadyDefined { label: to }.into());
+        }
+        let _ = self.defined_ent.remove(&from);
+        let _ = self.defined_ent.insert(to.clone(), ent);
+        if self.exports.remove(&from) {
+            let _ = self.exports.insert(to.clone());
+        }
+        if self.declared_externs.remove(&from) {
+            let _ = self.declared_externs.insert(to.clone());
+        }
+        if let Some(names) = self.variant_names.remove(&from) {
+            let _ = self.variant_names.insert(to.clone(), names);
+        }
+        if let Some(name) = self.named_returns.remove(&from) {
+            let _ = self.named_returns.insert(to.clone(), name);
+        }
+        if let Entry::Extern { name } = ent.access_mut(self)? {
+            *name = to;
+        }
+        Ok(())
+    }
+    /// The labels of the entries referring to an entry, and `export`
+    /// if the entry is exported
+    ///
+    /// label: the name of the entry
+    ///
+    pub fn used_by(&self, label: impl StringLike) -> Result<Vec<String>, Error> {
+        let ent = self.lookup(label.as_str())?;
+        let mut used_by: Vec<String> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| references(e).contains(&ent))
+            .filter_map(|(i, _)| self.find_name(EntryRef::new(i)).ok())
+            .map(String::from)
+            .collect();
+        if self.exports.contains(label.as_str()) {
+            used_by.push("export".into());
+        }
+        Ok(used_by)
+    }
+    /// Delete an entry, if no entry refers to it and it is not exported.
+    /// Otherwise the entries still using it are reported.
+    ///
+    /// label: the name of the entry
+    ///
+    pub fn safe_delete(&mut self, label: impl StringLike) -> Result<(), Error> {
+        let used_by = self.used_by(label.as_str())?;
+        if !used_by.is_empty() {
+            return Err(RefactorError::StillUsed {
+                label: label.clone_string(),
+                used_by,
+            }
+            .into());
+        }
+        let ent = self.lookup(label.as_str())?;
+        let _ = self.defined_ent.remove(label.as_str());
+        let _ = self.declared_externs.remove(label.as_str());
+        let _ = self.variant_names.remove(label.as_str());
+        let _ = self.named_returns.remove(label.as_str());
+        let _ = self.entries.remove(ent.index());
+        let shift = |e: &mut EntryRef| {
+            if e.index() > ent.index() {
+                *e = EntryRef::new(e.index() - 1)
+            }
+        };
+        for e in self.defined_ent.values_mut() {
+            shift(e);
+        }
+        for entry in self.entries.iter_mut() {
+            for_each_reference(entry, shift);
+        }
+        Ok(())
+    }
+    /// Merge the target of a jmp into the jmp.
+    ///
+    /// A jmp to another jmp becomes a jmp to the target of that jmp,
+    /// performing both permutations. A jmp without permutation takes
+    /// the instruction of its target if that is a call or a ret.
+    /// The target is kept, as it may be used elsewhere.
+    ///
+    /// label: the name of the jmp
+    ///
+    pub fn inline_jmp(&mut self, label: impl StringLike) -> Result<(), Error> {
+        let ent = self.lookup(label.as_str())?;
+        let (cont, per) = match ent.access(self)? {
+            Entry::Jmp { cont, per } => (*cont, *per),
+            _ => {
+                return Err(RefactorError::NotJmp {
+                    label: label.clone_string(),
+                }
+                .into())
+            }
+        };
+        let identical = per.min_len() == 0;
+        let inlined = match cont.access(self)? {
+            Entry::Jmp { cont, per: next } => Entry::Jmp {
+                cont: *cont,
+                per: compose(per, *next).ok_or_else(|| RefactorError::InvalidPermutation {
+                    label: label.clone_string(),
+                })?,
+            },
+            Entry::Call {
+                callee,
+                callcnt,
+                callcont,
+            } if identical => Entry::Call {
+                callee: *callee,
+                callcnt: *callcnt,
+                callcont: *callcont,
+            },
+            Entry::Ret { variant } if identical => Entry::Ret { variant: *variant },
+            _ => {
+                return Err(RefactorError::CannotInline {
+                    label: label.clone_string(),
+                    target: self.find_name(cont).unwrap_or("?").into(),
+                }
+                .into())
+            }
+        };
+        let target = String::from(self.find_name(cont).unwrap_or("?"));
+        if let Some(name) = self.named_returns.get(&target).cloned() {
+            let _ = self.named_returns.insert(label.clone_string(), name);
+        }
+        *ent.access_mut(self)? = inlined;
+        Ok(())
+    }
+    /// Extract the part of the program reachable from some entries as
+    /// a new program, so it can be run on its own. The entries become the
+    /// variants of a new group, the only export of the new program.
+    /// This program is not changed.
+    ///
+    /// group: the name of the new group
+    /// elements: the variant entries, each with an optional variant name
+    ///
+    /// returns: the new program
+    ///
+    pub fn extract_group(
+        &self,
+        group: impl StringLike,
+        elements: &[(Option<&str>, &str)],
+    ) -> Result<PreCompileProgram, Error> {
+        if self.defined_ent.contains_key(group.as_str()) {
+            return Err(RefactorError::AlreadyDefined {
+                label: group.clone_string(),
+            }
+            .into());
+        }
+        for (_, element) in elements.iter() {
+            if self.lookup(element)?.is_group_in(self) {
+                return Err(RefactorError::GroupElement {
+                    label: String::from(*element),
+                }
+                .into());
+            }
+        }
+        let mut reachable = BTreeSet::new();
+        let mut pending = elements
+            .iter()
+            .map(|(_, element)| self.lookup(element))
+            .collect::<Result<Vec<_>, _>>()?;
+        while let Some(ent) = pending.pop() {
+            if reachable.insert(ent) {
+                pending.extend(references(ent.access(self)?));
+            }
+        }
+        // Kept entries are renumbered in their original order
+        let relocated: BTreeMap<EntryRef, EntryRef> = reachable
+            .iter()
+            .enumerate()
+            .map(|(idx, ent)| (*ent, EntryRef::new(idx)))
+            .collect();
+        let mut program = PreCompileProgram {
+            strict: self.strict,
+            ..Default::default()
+        };
+        for ent in reachable.iter() {
+            let mut entry = ent.access(self)?.clone();
+            for_each_reference(&mut entry, |e| *e = relocated[e]);
+            program.entries.push(entry);
+        }
+        for (label, ent) in self.defined_ent.iter() {
+            let ent = match relocated.get(ent) {
+                Some(ent) => *ent,
+                None => continue,
+            };
+            let _ = program.defined_ent.insert(label.clone(), ent);
+            if self.declared_externs.contains(label) {
+                let _ = program.declared_externs.insert(label.clone());
+            }
+            if let Some(names) = self.variant_names.get(label) {
+                let _ = program.variant_names.insert(label.clone(), names.clone());
+            }
+            if let Some(name) = self.named_returns.get(label) {
+                let _ = program.named_returns.insert(label.clone(), name.clone());
+            }
+        }
+        let _ = program.define_named_group(group.clone_string(), elements)?;
+        program.set_export(group)?;
+        Ok(program)
+    }
+
+    fn lookup(&self, label: &str) -> Result<EntryRef, RefactorError> {
+        self.defined_ent
+            .get(label)
+            .cloned()
+            .ok_or_else(|| RefactorError::NotFound {
+                label: label.into(),
+            })
+    }
+}
+
+/// Update all entries an entry refers to
+fn for_each_reference(entry: &mut Entry, mut f: impl FnMut(&mut EntryRef)) {
+    match entry {
+        Entry::Jmp { cont, .. } => f(cont),
+        Entry::Call {
+            callee, callcont, ..
+        } => {
+            f(callee);
+            f(callcont);
+        }
+        Entry::Group { elements } => elements.iter_mut().for_each(f),
+        Entry::Ret { .. } | Entry::Extern { .. } => (),
+    }
+}
+
+/// All entries an entry refers to
+fn references(entry: &Entry) -> Vec<EntryRef> {
+    match entry {
+        Entry::Jmp { cont, .. } => vec![*cont],
+        Entry::Call {
+            callee, callcont, ..
+        } => vec![*callee, *callcont],
+        Entry::Group { elements } => elements.clone(),
+        Entry::Ret { .. } | Entry::Extern { .. } => vec![],
+    }
+}
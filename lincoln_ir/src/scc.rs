@@ -0,0 +1,151 @@
+use std::collections::{HashSet, VecDeque};
+
+const UNVISITED: usize = usize::MAX;
+
+/// Tarjan's strongly connected components algorithm, without recursion
+/// so that long chains of entries don't overflow the stack.
+///
+struct Tarjan<'a> {
+    graph: &'a [Vec<usize>],
+    index: Vec<usize>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    work: Vec<(usize, usize)>,
+    next_index: usize,
+    components: Vec<Vec<usize>>,
+}
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, v: usize) {
+        self.index[v] = self.next_index;
+        self.lowlink[v] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        self.work.push((v, 0));
+    }
+    fn run(&mut self, root: usize) {
+        self.visit(root);
+        while let Some((v, i)) = self.work.last().cloned() {
+            if i < self.graph[v].len() {
+                self.work.last_mut().expect("work is empty").1 += 1;
+                let w = self.graph[v][i];
+                if self.index[w] == UNVISITED {
+                    self.visit(w);
+                } else if self.on_stack[w] {
+                    self.lowlink[v] = self.lowlink[v].min(self.index[w]);
+                }
+                continue;
+            }
+            let _ = self.work.pop();
+            if let Some((u, _)) = self.work.last() {
+                self.lowlink[*u] = self.lowlink[*u].min(self.lowlink[v]);
+            }
+            if self.lowlink[v] == self.index[v] {
+                let mut component = vec![];
+                while let Some(w) = self.stack.pop() {
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
+}
+
+/// Find the strongly connected components of a graph
+///
+/// graph: the successors of each node
+///
+/// returns: the components, each one after all components it has edges to
+///
+pub(crate) fn strongly_connected(graph: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = graph.len();
+    let mut tarjan = Tarjan {
+        graph,
+        index: vec![UNVISITED; n],
+        lowlink: vec![0; n],
+        on_stack: vec![false; n],
+        stack: vec![],
+        work: vec![],
+        next_index: 0,
+        components: vec![],
+    };
+    for root in 0..n {
+        if tarjan.index[root] == UNVISITED {
+            tarjan.run(root);
+        }
+    }
+    tarjan.components
+}
+
+/// Find a cycle within a strongly connected component
+///
+/// graph: the successors of each node
+/// component: the strongly connected component
+///
+/// returns: the nodes of the cycle, starting and ending with the same node,
+///          or None if the component is a single node without a loop
+///
+pub(crate) fn find_cycle(graph: &[Vec<usize>], component: &[usize]) -> Option<Vec<usize>> {
+    let start = *component.first()?;
+    let members: HashSet<usize> = component.iter().cloned().collect();
+    let mut parent = vec![UNVISITED; graph.len()];
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(v) = queue.pop_front() {
+        for &w in graph[v].iter() {
+            if w == start {
+                let mut path = vec![start, v];
+                let mut u = v;
+                while u != start {
+                    u = parent[u];
+                    path.push(u);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if members.contains(&w) && parent[w] == UNVISITED {
+                parent[w] = v;
+                queue.push_back(w);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_cycle, strongly_connected};
+
+    #[test]
+    fn test_scc() {
+        // 0 -> 1 -> 2 -> 0, 3 -> 2, 4 -> 4
+        let graph = vec![vec![1], vec![2], vec![0], vec![2], vec![4], vec![]];
+        let components = strongly_connected(&graph);
+        assert_eq!(components.len(), 4);
+        let pos = |n| components.iter().position(|c| c.contains(&n)).unwrap();
+        assert_eq!(pos(0), pos(2));
+        assert!(pos(2) < pos(3));
+        assert_eq!(
+            find_cycle(&graph, &components[pos(1)]).map(|c| c.len()),
+            Some(4)
+        );
+        assert_eq!(find_cycle(&graph, &[4]), Some(vec![4, 4]));
+        assert_eq!(find_cycle(&graph, &[5]), None);
+    }
+
+    #[test]
+    fn test_long_chain() {
+        let graph: Vec<Vec<usize>> = (0..100_000)
+            .map(|i| vec![i + 1])
+            .chain(Some(vec![]))
+            .collect();
+        let components = strongly_connected(&graph);
+        assert_eq!(components.len(), 100_001);
+        assert_eq!(components[0], vec![100_000]);
+    }
+}
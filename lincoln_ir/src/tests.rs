@@ -1,10 +1,13 @@
 #[cfg(test)]
 mod test {
-    use crate::PreCompileProgram;
+    use crate::{
+        ArityIssue, MergeConflict, MergeMode, PreCompileProgram, RefactorError, UndeclaredExtern,
+        VariantError,
+    };
     use failure::Error;
+    use lincoln_common::{default_context, unwrap, wrap, ContextExt, Permutation};
     use lincoln_compiled::CodeRef::Termination;
     use lincoln_compiled::{EvalFn, ExternEntry};
-    use lincoln_common::{default_context, unwrap, wrap, ContextExt};
     #[test]
     fn test_call_ret() -> Result<(), Error> {
         let mut prog: PreCompileProgram = Default::default();
@@ -85,4 +88,331 @@ mod test {
 
         Ok(())
     }
+    #[test]
+    fn test_compile_labels() -> Result<(), Error> {
+        let mut prog: PreCompileProgram = Default::default();
+        prog.define_call("test", "rec1", 2, "rec2").unwrap();
+        prog.define_ret("rec1", 0)?;
+        prog.set_export("test")?;
+
+        let cprog = prog.compile(
+            vec![ExternEntry::Eval {
+                name: "rec2".into(),
+                eval: EvalFn::stateless(|_| Ok(Termination)),
+            }]
+            .into_iter(),
+        )?;
+        let labels = cprog.labels();
+        let next = cprog.get_export_ent("test", 0)?;
+        assert_eq!(labels.locate(&cprog, next), "test (call rec1 2 rec2)");
+        assert_eq!(labels.describe(&cprog, next), "test");
+        let group = cprog.get_export("test")?;
+        assert_eq!(labels.describe_group(group), "test");
+        Ok(())
+    }
+    #[test]
+    fn test_infer_arity() -> Result<(), Error> {
+        let mut prog: PreCompileProgram = Default::default();
+        prog.define_call("main", "f", 2, "k")?;
+        prog.define_jmp("f", "ext", "cab")?;
+        prog.define_call("k", "g", 1, "k2")?;
+        prog.define_ret("g", 0)?;
+        prog.define_jmp("k2", "ext2", "ba")?;
+        let report = prog.infer_arity();
+        assert_eq!(report.arity("f"), Some(3));
+        assert_eq!(report.arity("ext"), Some(3));
+        assert_eq!(report.arity("g"), None);
+        assert_eq!(report.arity("k2"), report.arity("k"));
+        assert!(report.issues().is_empty());
+
+        prog.define_call("main2", "f", 1, "k")?;
+        prog.define_call("r", "ret2", 0, "both")?;
+        prog.define_ret("ret2", 2)?;
+        prog.define_group("both", &["k", "k2"])?;
+        let report = prog.infer_arity();
+        assert_eq!(
+            report.issues(),
+            &[
+                ArityIssue::Conflict {
+                    label: "f".into(),
+                    expect: 3,
+                    actual: 2,
+                },
+                ArityIssue::InvalidVariant {
+                    label: "ret2".into(),
+                    call: "r".into(),
+                    cont: "both".into(),
+                    variant: 2,
+                    variants: 2,
+                }
+            ]
+        );
+
+        let mut prog: PreCompileProgram = Default::default();
+        prog.define_call("main", "f", 1, "k")?;
+        prog.define_jmp("f", "ret", "cba")?;
+        prog.define_ret("ret", 0)?;
+        let report = prog.infer_arity();
+        assert_eq!(
+            report.issues(),
+            &[ArityIssue::TooFewValues {
+                label: "f".into(),
+                arity: 2,
+                required: 3,
+            }]
+        );
+        Ok(())
+    }
+    #[test]
+    fn test_infer_arity_examples() -> Result<(), Error> {
+        for json in [
+            include_str!("../../fact.json"),
+            include_str!("../../bint.json"),
+        ]
+        .iter()
+        {
+            let prog: PreCompileProgram = serde_json::from_str(json)?;
+            assert!(prog.infer_arity().issues().is_empty());
+        }
+        Ok(())
+    }
+    #[test]
+    fn test_circular_reference() -> Result<(), Error> {
+        let mut prog: PreCompileProgram = Default::default();
+        prog.define_jmp("a", "b", "")?;
+        prog.define_jmp("b", "c", "ba")?;
+        prog.define_jmp("c", "a", "")?;
+        prog.set_export("a")?;
+        let err = prog.compile(vec![].into_iter()).err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "circular jmp detected: a -> b -> c -> a"
+        );
+        Ok(())
+    }
+    #[test]
+    fn test_forward_reference() -> Result<(), Error> {
+        // loop calls dec with a counter, which returns to body and then loop again
+        let mut prog: PreCompileProgram = Default::default();
+        prog.define_call("loop", "dec", 1, "body")?;
+        prog.define_jmp("body", "loop", "")?;
+        prog.set_export("loop")?;
+
+        let cprog = prog.compile(
+            vec![ExternEntry::Eval {
+                name: "dec".into(),
+                eval: EvalFn::stateless(|c| {
+                    let cont = c.pop()?;
+                    let n = unwrap::<i32>(c.pop()?)?;
+                    if n == 0 {
+                        c.push(wrap(42i32));
+                        return Ok(Termination);
+                    }
+                    c.push(wrap(n - 1));
+                    lincoln_compiled::eval_closure(cont, c, 0)
+                }),
+            }]
+            .into_iter(),
+        )?;
+        let mut ctx = default_context();
+        ctx.push(wrap(3i32));
+        cprog.run(&mut *ctx, "loop", 0, None)?;
+        assert_eq!(unwrap::<i32>(ctx.pop()?)?, 42);
+        Ok(())
+    }
+    #[test]
+    fn test_strict_externs() -> Result<(), Error> {
+        let mut prog: PreCompileProgram = Default::default();
+        let _ = prog.declare_extern("mul")?;
+        prog.define_call("fact", "mull", 2, "k")?;
+        prog.define_ret("k", 0)?;
+        prog.set_export("fact")?;
+        // without strict mode, mull is an extern
+        assert_eq!(prog.externs().len(), 2);
+        let mul = || ExternEntry::Eval {
+            name: "mul".into(),
+            eval: EvalFn::stateless(|_| Ok(Termination)),
+        };
+        let err = prog.compile(vec![mul()].into_iter()).err().unwrap();
+        assert_eq!(format!("{}", err), "Extern entry not found mull");
+
+        prog.set_strict(true);
+        let undeclared = prog.undeclared_externs();
+        assert_eq!(
+            undeclared,
+            vec![UndeclaredExtern {
+                name: "mull".into(),
+                used_by: vec!["fact".into()],
+                suggestions: vec!["mul".into()],
+            }]
+        );
+        let err = prog.compile(vec![mul()].into_iter()).err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "mull is neither defined nor declared extern (used by fact), did you mean mul?"
+        );
+
+        prog.define_call("fact", "mul", 2, "k")?;
+        assert!(prog.undeclared_externs().is_empty());
+        assert_eq!(
+            prog.unused_externs(vec!["mul", "add"].into_iter()),
+            vec!["add".to_string()]
+        );
+        let _ = prog.compile(vec![mul()].into_iter())?;
+        Ok(())
+    }
+    #[test]
+    fn test_named_variants() -> Result<(), Error> {
+        let mut prog = PreCompileProgram::parse(
+            "main: call check 0 k\ncheck: ret err\nk: group ok=done err=fail\n\
+             extern done\nextern fail\nexport main\nexport k\n",
+        )?;
+        let ext = |name: &'static str, value: i32| ExternEntry::Eval {
+            name: name.into(),
+            eval: EvalFn::stateful(Box::new(move |c| {
+                c.push(wrap(value));
+                Ok(Termination)
+            })),
+        };
+        let externs = || vec![ext("done", 0), ext("fail", 1)].into_iter();
+        let cprog = prog.compile(externs())?;
+        let mut ctx = default_context();
+        ctx.push(wrap(7i32));
+        cprog.run(&mut *ctx, "main", 0, None)?;
+        assert_eq!(unwrap::<i32>(ctx.pop()?)?, 1);
+        let k = cprog.get_export("k")?;
+        assert_eq!(cprog.labels().variant(k, "err"), Some(1));
+        let check = cprog.get_export_ent("main", 0)?;
+        let ret = cprog.eval(&mut *ctx, &check)?;
+        assert_eq!(cprog.labels().locate(&cprog, ret), "check (ret err)");
+
+        // a continuation numbering err differently
+        prog.define_call("main2", "check", 0, "k2")?;
+        prog.define_named_group("k2", &[(Some("err"), "fail"), (Some("ok"), "done")])?;
+        prog.set_export("main2")?;
+        let err = prog.compile(externs()).err().unwrap();
+        assert_eq!(
+            format!("{}", err),
+            "check returns variant err, which has different numbers in k (1), k2 (0)"
+        );
+        // a continuation without err
+        prog.define_group("k2", &["fail", "done"])?;
+        let (_, errors) = prog.resolve_variants(&crate::arity::find_resumes(&prog));
+        assert_eq!(
+            errors,
+            vec![VariantError::Missing {
+                label: "check".into(),
+                name: "err".into(),
+                call: "main2".into(),
+                cont: "k2".into(),
+            }]
+        );
+        assert!(prog
+            .define_named_group("k3", &[(Some("ok"), "done"), (Some("ok"), "fail")])
+            .is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_refactor() -> Result<(), Error> {
+        let mut prog = PreCompileProgram::parse(
+            "main: jmp swap #!ba\nswap: jmp call_f #!ba\ncall_f: call f 1 k\n\
+             k: jmp r #!\nr: ret 0\nunused: ret 1\nextern f\nexport main\n",
+        )?;
+        let error = |r: Result<(), Error>| r.err().unwrap().downcast::<RefactorError>().unwrap();
+        assert_eq!(
+            error(prog.safe_delete("r")),
+            RefactorError::StillUsed {
+                label: "r".into(),
+                used_by: vec!["k".into()],
+            }
+        );
+        assert_eq!(
+            error(prog.rename("unused", "main")),
+            RefactorError::AlreadyDefined {
+                label: "main".into()
+            }
+        );
+        prog.safe_delete("unused")?;
+        prog.rename("f", "g")?;
+        prog.rename("main", "start")?;
+        prog.inline_jmp("start")?;
+        prog.inline_jmp("k")?;
+        assert_eq!(
+            error(prog.inline_jmp("r")),
+            RefactorError::NotJmp { label: "r".into() }
+        );
+        // a permutation out of range, as deserialized from JSON
+        let _ = prog.define_jmp("bad", "swap", Permutation(u64::MAX))?;
+        assert_eq!(
+            error(prog.inline_jmp("bad")),
+            RefactorError::InvalidPermutation {
+                label: "bad".into()
+            }
+        );
+        prog.safe_delete("bad")?;
+        prog.safe_delete("swap")?;
+        let extracted = prog.extract_group("cont", &[(Some("ok"), "call_f")])?;
+        assert_eq!(
+            format!("{}", prog),
+            "start: jmp call_f #!\ncall_f: call g 1 k\nk: ret 0\nr: ret 0\n\
+             \nextern g\n\nexport start\n"
+        );
+        assert_eq!(
+            format!("{}", extracted),
+            "call_f: call g 1 k\nk: ret 0\ncont: group ok=call_f \n\nextern g\n\nexport cont\n"
+        );
+        assert!(prog.extract_group("start", &[(None, "k")]).is_err());
+        assert!(extracted.extract_group("bad", &[(None, "cont")]).is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_merge_modes() -> Result<(), Error> {
+        let ours =
+            || PreCompileProgram::parse("main: call util 0 k\nk: ret 0\nutil: jmp helper #!\n");
+        let theirs = PreCompileProgram::parse(
+            "util: call helper 1 k\nk: ret 0\nhelper: ret 1\nextern log\nexport util\n",
+        )?;
+        assert_eq!(ours()?.merge_conflicts(&theirs), vec!["util".to_string()]);
+
+        let mut prog = ours()?;
+        let err = prog.merge_with(&theirs, MergeMode::Fail).err().unwrap();
+        assert_eq!(
+            err.downcast::<MergeConflict>()?.labels,
+            vec!["util".to_string()]
+        );
+        assert_eq!(format!("{}", prog), format!("{}", ours()?));
+
+        let report = prog.merge_with(&theirs, MergeMode::PreferOurs)?;
+        assert_eq!(report.kept, vec!["util".to_string()]);
+        assert_eq!(report.added, vec!["helper".to_string()]);
+        assert!(format!("{}", prog).contains("util: jmp helper #!\n"));
+        // unused externs of the other program are kept
+        assert!(format!("{}", prog).contains("extern log\n"));
+
+        let mut prog = ours()?;
+        let report = prog.merge_with(&theirs, MergeMode::PreferTheirs)?;
+        assert_eq!(report.overwritten, vec!["util".to_string()]);
+        assert!(format!("{}", prog).contains("util: call helper 1 k\n"));
+
+        let mut prog = ours()?;
+        let report = prog.merge_with(&theirs, MergeMode::Prefix("lib_".into()))?;
+        assert_eq!(
+            report.renamed,
+            vec![("util".to_string(), "lib_util".to_string())]
+        );
+        assert_eq!(format!("{}", report), "1 added, renamed util to lib_util");
+        let text = format!("{}", prog);
+        assert!(text.contains("util: jmp helper #!\n"));
+        assert!(text.contains("lib_util: call helper 1 k\n"));
+        assert!(text.contains("export lib_util\n"));
+
+        // A failed merge changes nothing, even after defining some labels
+        let mut unnamed = PreCompileProgram::parse("done: ret 0\nlost: ret 1\n")?;
+        let _ = unnamed.defined_ent.remove("lost");
+        let mut prog = ours()?;
+        let before = format!("{}", prog);
+        assert!(prog.merge_with(&unnamed, MergeMode::Fail).is_err());
+        assert_eq!(format!("{}", prog), before);
+        Ok(())
+    }
 }
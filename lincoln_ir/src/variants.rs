@@ -0,0 +1,126 @@
+use crate::arity::Resume;
+use crate::entry::{Entry, EntryRef};
+use crate::program::PreCompileProgram;
+use lincoln_common::Access;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Errors resolving the variant names of rets
+#[derive(Fail, Debug, Clone, PartialEq, Eq)]
+pub enum VariantError {
+    #[fail(display = "{} returns variant {}, which no group has", label, name)]
+    Unknown { label: String, name: String },
+
+    #[fail(
+        display = "{} returns variant {} to the continuation {} of {}, which has no such variant",
+        label, name, cont, call
+    )]
+    Missing {
+        label: String,
+        name: String,
+        call: String,
+        cont: String,
+    },
+
+    #[fail(
+        display = "{} returns variant {}, which has different numbers in {}",
+        label, name, groups
+    )]
+    Ambiguous {
+        label: String,
+        name: String,
+        groups: String,
+    },
+}
+
+impl PreCompileProgram {
+    /// Whether a ret refers to its variant by name
+    pub(crate) fn is_named_ret(&self, ent: EntryRef) -> bool {
+        self.find_name(ent)
+            .map(|label| self.named_returns.contains_key(label))
+            .unwrap_or(false)
+    }
+    /// The number of a named variant of a group
+    fn variant_index(&self, group: &str, name: &str) -> Option<u8> {
+        self.variant_names
+            .get(group)?
+            .iter()
+            .position(|n| n.as_ref().map(String::as_str) == Some(name))
+            .map(|v| v as u8)
+    }
+    /// Resolve the variant names of rets to numbers.
+    ///
+    /// A ret resuming the continuations of known calls takes the number
+    /// the name has in all those continuations. Other rets take the number
+    /// the name has in every group naming it.
+    ///
+    /// resumes: the calls resumed by rets
+    ///
+    /// returns: the numbers of the resolved rets, and the errors of the others
+    ///
+    pub(crate) fn resolve_variants(
+        &self,
+        resumes: &[Resume],
+    ) -> (BTreeMap<EntryRef, u8>, Vec<VariantError>) {
+        let mut resolved = BTreeMap::new();
+        let mut errors = vec![];
+        for (label, name) in self.named_returns.iter() {
+            let ret = match self.defined_ent.get(label) {
+                Some(ret) => *ret,
+                None => continue,
+            };
+            let mut found = BTreeSet::new();
+            let mut missing = false;
+            for resume in resumes.iter().filter(|r| r.ret == ret) {
+                let cont = match resume.call.access(self) {
+                    Ok(Entry::Call { callcont, .. }) => *callcont,
+                    _ => continue,
+                };
+                let cont = self.find_name(cont).unwrap_or("?");
+                match self.variant_index(cont, name) {
+                    Some(v) => {
+                        let _ = found.insert((cont, v));
+                    }
+                    None => {
+                        missing = true;
+                        errors.push(VariantError::Missing {
+                            label: label.clone(),
+                            name: name.clone(),
+                            call: self.find_name(resume.call).unwrap_or("?").into(),
+                            cont: cont.into(),
+                        });
+                    }
+                }
+            }
+            if missing {
+                continue;
+            }
+            if found.is_empty() {
+                found = self
+                    .variant_names
+                    .keys()
+                    .filter_map(|g| Some((g.as_str(), self.variant_index(g, name)?)))
+                    .collect();
+            }
+            let numbers: BTreeSet<u8> = found.iter().map(|(_, v)| *v).collect();
+            match numbers.len() {
+                0 => errors.push(VariantError::Unknown {
+                    label: label.clone(),
+                    name: name.clone(),
+                }),
+                1 => {
+                    let _ = resolved.insert(ret, *numbers.iter().next().expect("one number"));
+                }
+                _ => errors.push(VariantError::Ambiguous {
+                    label: label.clone(),
+                    name: name.clone(),
+                    groups: found
+                        .iter()
+                        .map(|(g, v)| format!("{} ({})", g, v))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                }),
+            }
+        }
+        (resolved, errors)
+    }
+}
@@ -4,11 +4,15 @@ use crate::externs::print;
 use core::fmt::{Display, Formatter};
 use failure::Error;
 use lincoln_common::{Access, Context, ContextExt, Value};
-use lincoln_compiled::{CodeRef, Program};
-use lincoln_ir::PreCompileProgram;
+use lincoln_compiled::{
+    BreakCondition, BreakLocation, Breakpoint, CodeRef, DebugStop, Debugger, ExternEntry,
+    LinearityChecker, Machine, MachineState, Profiler, Program, ProgramImage, TraceFormat,
+    TraceWriter,
+};
+use lincoln_ir::{MergeMode, PreCompileProgram};
 use regex::{Captures, Regex};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 
 pub fn commands() -> Regex {
     Regex::new(concat!(
@@ -19,32 +23,83 @@ pub fn commands() -> Regex {
         r#"^\s*(?P<call>((?P<calllabel>\p{XID_Start}\p{XID_Continue}*):\s+"#,
         r#"call\s+(?P<callee>\p{XID_Start}\p{XID_Continue}*)\s+(?P<callcnt>([1-9][0-9]*|0))\s+"#,
         r#"(?P<callcont>\p{XID_Start}\p{XID_Continue}*)))(\s*//.*)?\s*$|"#,
-        // <retlabel> ret [variant]
+        // <retlabel> ret <variant>
         r#"^\s*(?P<ret>((?P<retlabel>\p{XID_Start}\p{XID_Continue}*):\s+"#,
-        r#"ret\s+(?P<variant>([1-9][0-9]*|0))))(\s*//.*)?\s*$|"#,
+        r#"ret\s+(?P<variant>([1-9][0-9]*|0|\p{XID_Start}\p{XID_Continue}*))))(\s*//.*)?\s*$|"#,
         // <grouplabel> group <element>*
         r#"^\s*(?P<group>((?P<grouplabel>\p{XID_Start}\p{XID_Continue}*):\s+"#,
-        r#"group\s+(?P<elements>(\p{XID_Start}\p{XID_Continue}*\s*)*)))(\s*//.*)?\s*$|"#,
+        r#"group\s+(?P<elements>((\p{XID_Start}\p{XID_Continue}*=)?\p{XID_Start}\p{XID_Continue}*\s*)*)))"#,
+        r#"(\s*//.*)?\s*$|"#,
         // setexport <label>
         r#"^\s*(?P<setexport>set\s+export\s+(?P<exportlabel>\p{XID_Start}\p{XID_Continue}*))(\s*//.*)?\s*$|"#,
+        // extern <name>
+        r#"^\s*(?P<declareextern>extern\s+(?P<externname>\p{XID_Start}\p{XID_Continue}*))(\s*//.*)?\s*$|"#,
+        // set strict <on|off>
+        r#"^\s*(?P<setstrict>set\s+strict\s+(?P<strictmode>on|off))\s*$|"#,
         // delete <label>
         r#"^\s*(?P<delete>delete\s+(?P<deletelabel>\p{XID_Start}\p{XID_Continue}*))\s*$|"#,
+        // rename <label> <newlabel>
+        r#"^\s*(?P<rename>rename\s+(?P<renamefrom>\p{XID_Start}\p{XID_Continue}*)\s+"#,
+        r#"(?P<renameto>\p{XID_Start}\p{XID_Continue}*))\s*$|"#,
+        // inline <jmplabel>
+        r#"^\s*(?P<inline>inline\s+(?P<inlinelabel>\p{XID_Start}\p{XID_Continue}*))\s*$|"#,
+        // extract <grouplabel> <element>+ into <filename>
+        r#"^\s*(?P<extract>extract\s+(?P<extractlabel>\p{XID_Start}\p{XID_Continue}*)\s+"#,
+        r#"(?P<extractelements>((\p{XID_Start}\p{XID_Continue}*=)?\p{XID_Start}\p{XID_Continue}*\s*)+)"#,
+        r#"\s+into\s+(?P<extractfilename>[^*?"<>|]+))\s*$|"#,
+        // save bytecode <filename>
+        r#"^\s*(?P<savebytecode>(save\s+bytecode\s+(?P<savebytecodefilename>[^*?"<>|]+)))\s*$|"#,
+        // save compiled <filename>
+        r#"^\s*(?P<savecompiled>(save\s+compiled\s+(?P<savecompiledfilename>[^*?"<>|]+)))\s*$|"#,
+        // load compiled <filename> <external set>
+        r#"^\s*(?P<loadcompiled>(load\s+compiled\s+(?P<loadcompiledfilename>[^\s*?"<>|]+)"#,
+        r#"\s+(?P<externalset_load>\p{XID_Start}\p{XID_Continue}*)))\s*$|"#,
         // save <filename>
         r#"^\s*(?P<save>(save\s+(?P<savefilename>[^*?"<>|]+)))\s*$|"#,
+        // load <filename> <fail|ours|theirs|prefix <prefix>>
+        r#"^\s*(?P<loadmerge>(load\s+(?P<mergefilename>[^\s*?"<>|]+)\s+"#,
+        r#"(?P<mergemode>fail|ours|theirs|prefix\s+(?P<mergeprefix>\p{XID_Continue}+))))\s*$|"#,
         // load <filename>
         r#"^\s*(?P<load>(load\s+(?P<loadfilename>[^*?"<>|]+)))\s*$|"#,
         // show program
         r#"^\s*(?P<showprog>show\s+program)\s*$|"#,
+        // show arity
+        r#"^\s*(?P<showarity>show\s+arity)\s*$|"#,
         // show external set
         r#"^\s*(?P<showexternset>show\s+external\s+set)\s*$|"#,
         // compile <external set>
         r#"^\s*(?P<compile>compile\s+(?P<externalset_compile>\p{XID_Start}\p{XID_Continue}*))\s*$|"#,
         // run <external set> variant <value>
         // run <external set> variant <value> step
+        // run <external set> variant <value> trace <filename> [chrome] [deterministic]
         r#"^\s*(?P<run>run\s+(?P<exportlabel_run>\p{XID_Start}\p{XID_Continue}*)\s+"#,
-        r#"(?P<runvariant>([1-9][0-9]*|0))\s+"(?P<value>[^"]*)"(\s+(?P<runstep>step))?)\s*$|"#,
+        r#"(?P<runvariant>([1-9][0-9]*|0|\p{XID_Start}\p{XID_Continue}*))\s+"(?P<value>[^"]*)"(\s+(?P<runstep>step)|"#,
+        r#"\s+trace\s+(?P<tracefilename>[^\s*?"<>|]+)(\s+(?P<tracechrome>chrome))?"#,
+        r#"(\s+(?P<tracedeterministic>deterministic))?)?)\s*$|"#,
+        // profile <external set> variant <value>
+        // profile <external set> variant <value> json
+        r#"^\s*(?P<profile>profile\s+(?P<exportlabel_profile>\p{XID_Start}\p{XID_Continue}*)\s+"#,
+        r#"(?P<profilevariant>([1-9][0-9]*|0|\p{XID_Start}\p{XID_Continue}*))\s+"(?P<profilevalue>[^"]*)"(\s+(?P<profilejson>json))?)\s*$|"#,
+        // check <external set> variant <value>
+        r#"^\s*(?P<check>check\s+(?P<exportlabel_check>\p{XID_Start}\p{XID_Continue}*)\s+"#,
+        r#"(?P<checkvariant>([1-9][0-9]*|0|\p{XID_Start}\p{XID_Continue}*))\s+"(?P<checkvalue>[^"]*)")\s*$|"#,
         // step
         r#"^\s*(?P<step>step)\s*$|"#,
+        // back [count]
+        r#"^\s*(?P<back>back(\s+(?P<backcount>[0-9]+))?)\s*$|"#,
+        // next
+        r#"^\s*(?P<next>next)\s*$|"#,
+        // finish
+        r#"^\s*(?P<finish>finish)\s*$|"#,
+        // continue
+        r#"^\s*(?P<cont>continue)\s*$|"#,
+        // break <entry|extern|export> <target> [if <condition>]
+        r#"^\s*(?P<breakpoint>break\s+(?P<breakkind>entry|extern|export)\s+(?P<breaktarget>\p{XID_Continue}+)"#,
+        r#"(\s+if\s+(?P<breakcond>len\s+[0-9]+|ret\s+[0-9]+|slot\s+[0-9]+\s+is\s+[\p{XID_Continue}:<>]+))?)\s*$|"#,
+        // clear <breakpoint id>
+        r#"^\s*(?P<clear>clear\s+(?P<clearid>[0-9]+))\s*$|"#,
+        // show breakpoints
+        r#"^\s*(?P<showbreakpoints>show\s+breakpoints)\s*$|"#,
         // empty line
         r#"^\s*(//.*)?\s*$|"#,
         // exit
@@ -57,13 +112,12 @@ pub enum CommandContext {
     Idle {
         program: PreCompileProgram,
         compiled: Option<Program>,
+        debugger: Debugger,
     },
     Stepping {
         program: PreCompileProgram,
-        compiled: Program,
-        context: Box<dyn Context>,
-        current: CodeRef,
-        round: usize,
+        machine: Machine<Program>,
+        debugger: Debugger,
     },
 }
 impl Display for CommandContext {
@@ -71,7 +125,9 @@ impl Display for CommandContext {
         use CommandContext::*;
         let empty = "".into();
         match self {
-            Idle { program, compiled } => write!(
+            Idle {
+                program, compiled, ..
+            } => write!(
                 fmt,
                 "program:\n{}\ncompiled:\n{}",
                 program,
@@ -81,17 +137,23 @@ impl Display for CommandContext {
                     .unwrap_or(empty)
             ),
             Stepping {
-                program,
-                compiled,
-                context,
-                current,
-                round,
+                program, machine, ..
             } => {
-                write!(fmt, "program:\n{}\ncompiled:\n{:?}", program, compiled)?;
+                write!(
+                    fmt,
+                    "program:\n{}\ncompiled:\n{:?}",
+                    program,
+                    machine.program()
+                )?;
                 write!(
                     fmt,
                     "context:\n{}\ncurrent:\n{}\nround:{}",
-                    context, current, round
+                    machine.context(),
+                    machine
+                        .program()
+                        .labels()
+                        .locate(machine.program(), machine.current()),
+                    machine.steps()
                 )
             }
         }
@@ -102,6 +164,7 @@ impl Default for CommandContext {
         CommandContext::Idle {
             program: Default::default(),
             compiled: Default::default(),
+            debugger: Default::default(),
         }
     }
 }
@@ -124,21 +187,47 @@ impl CommandContext {
             Idle { program, .. } | Stepping { program, .. } => program,
         }
     }
+    fn debugger(&self) -> &Debugger {
+        use CommandContext::*;
+        match self {
+            Idle { debugger, .. } | Stepping { debugger, .. } => debugger,
+        }
+    }
+    fn debugger_mut(&mut self) -> &mut Debugger {
+        use CommandContext::*;
+        match self {
+            Idle { debugger, .. } | Stepping { debugger, .. } => debugger,
+        }
+    }
+    /// Programs in files ending with `.lcir` are in the text format, otherwise JSON
+    fn is_lcir(filename: &str) -> bool {
+        filename.ends_with(".lcir")
+    }
     fn save(&mut self, c: Captures) -> Result<bool, Error> {
         let filename = c
             .name("savefilename")
             .expect("savefilename is none")
             .as_str()
             .trim();
+        Self::save_program(self.program(), filename)?;
+        Ok(true)
+    }
+    /// Save a program to a file, in the text format if the file ends with .lcir
+    ///
+    fn save_program(program: &PreCompileProgram, filename: &str) -> Result<(), Error> {
         if let Ok(..) = std::fs::metadata(filename) {
             if !prompt_and_ask(format!("{} is already exist. override?", filename))? {
-                return Ok(true);
+                return Ok(());
             }
         }
         let mut file = File::create(filename)?;
-        file.write_all(serde_json::to_string_pretty(&json!(self.program()))?.as_bytes())?;
+        if Self::is_lcir(filename) {
+            file.write_all(format!("{}", program).as_bytes())?;
+        } else {
+            file.write_all(serde_json::to_string_pretty(&json!(program))?.as_bytes())?;
+        }
         println!("saved to {}!", filename);
-        Ok(true)
+        Ok(())
     }
     fn load(&mut self, c: Captures) -> Result<bool, Error> {
         let filename = c
@@ -146,10 +235,112 @@ impl CommandContext {
             .expect("loadfilename is none")
             .as_str()
             .trim();
+        self.load_file(filename, MergeMode::PreferTheirs)
+    }
+    fn loadmerge(&mut self, c: Captures) -> Result<bool, Error> {
+        let filename = c
+            .name("mergefilename")
+            .expect("mergefilename is none")
+            .as_str();
+        let mode = match c.name("mergemode").expect("mergemode is none").as_str() {
+            "fail" => MergeMode::Fail,
+            "ours" => MergeMode::PreferOurs,
+            "theirs" => MergeMode::PreferTheirs,
+            _ => MergeMode::Prefix(
+                c.name("mergeprefix")
+                    .expect("mergeprefix is none")
+                    .as_str()
+                    .into(),
+            ),
+        };
+        self.load_file(filename, mode)
+    }
+    fn load_file(&mut self, filename: &str, mode: MergeMode) -> Result<bool, Error> {
+        print!("loading {} ..", filename);
+        let p: PreCompileProgram = if Self::is_lcir(filename) {
+            PreCompileProgram::parse(&std::fs::read_to_string(filename)?)?
+        } else {
+            serde_json::from_reader(File::open(filename)?)?
+        };
+        let report = self.program_mut().merge_with(&p, mode)?;
+        println!(" loaded: {}.", report);
+        Ok(true)
+    }
+    fn savecompiled(&mut self, c: Captures) -> Result<bool, Error> {
+        let filename = c
+            .name("savecompiledfilename")
+            .expect("savecompiledfilename is none")
+            .as_str()
+            .trim();
+        let compiled = self.compiled()?;
+        if std::fs::metadata(filename).is_ok()
+            && !prompt_and_ask(format!("{} is already exist. override?", filename))?
+        {
+            return Ok(true);
+        }
+        let mut file = File::create(filename)?;
+        file.write_all(serde_json::to_string_pretty(compiled)?.as_bytes())?;
+        println!("saved to {}!", filename);
+        Ok(true)
+    }
+    fn savebytecode(&mut self, c: Captures) -> Result<bool, Error> {
+        let filename = c
+            .name("savebytecodefilename")
+            .expect("savebytecodefilename is none")
+            .as_str()
+            .trim();
+        let compiled = self.compiled()?;
+        if std::fs::metadata(filename).is_ok()
+            && !prompt_and_ask(format!("{} is already exist. override?", filename))?
+        {
+            return Ok(true);
+        }
+        let mut file = File::create(filename)?;
+        file.write_all(&compiled.to_bytecode())?;
+        println!("saved to {}!", filename);
+        Ok(true)
+    }
+    /// The compiled program, either idle or being stepped into
+    ///
+    fn compiled(&self) -> Result<&Program, Error> {
+        use CommandContext::*;
+        match self {
+            Idle {
+                compiled: Some(compiled),
+                ..
+            } => Ok(compiled),
+            Stepping { machine, .. } => Ok(machine.program()),
+            _ => bail!("Program is not compiled. Please compile it first (use compile command)"),
+        }
+    }
+    fn loadcompiled(&mut self, c: Captures) -> Result<bool, Error> {
+        use CommandContext::*;
+        let filename = c
+            .name("loadcompiledfilename")
+            .expect("loadcompiledfilename is none")
+            .as_str();
+        let externs = c
+            .name("externalset_load")
+            .expect("externalset_load is none")
+            .as_str();
+        if let Stepping { .. } = self {
+            if !prompt_and_ask("You are stepping into the program. Quit?")? {
+                return Ok(true);
+            }
+            self.stop_stepping();
+        }
         print!("loading {} ..", filename);
-        let file = File::open(filename)?;
-        let p: PreCompileProgram = serde_json::from_reader(file)?;
-        self.program_mut().merge(&p)?;
+        let bytes = std::fs::read(filename)?;
+        let image: ProgramImage = if bytes.starts_with(b"LNBC") {
+            ProgramImage::from_bytecode(&bytes)?
+        } else {
+            serde_json::from_slice(&bytes)?
+        };
+        let prog = image.bind(Self::extern_set(externs)?)?;
+        Self::validate(&prog)?;
+        if let Idle { compiled, .. } = self {
+            *compiled = Some(prog);
+        }
         println!(" loaded.");
         Ok(true)
     }
@@ -179,23 +370,21 @@ impl CommandContext {
     }
     fn ret(&mut self, c: Captures) -> Result<bool, Error> {
         let retlabel = c.name("retlabel").expect("retlabel is none").as_str();
-        let variant = c
-            .name("variant")
-            .expect("callcnt is none")
-            .as_str()
-            .parse::<u8>()?;
+        let variant = c.name("variant").expect("variant is none").as_str();
         let pm = self.program_mut();
-        pm.define_ret(retlabel, variant)
-            .map(|e| info!("{:?}", e.access(&pm)))?;
+        match variant_number(variant)? {
+            Some(variant) => pm.define_ret(retlabel, variant),
+            None => pm.define_named_ret(retlabel, variant),
+        }
+        .map(|e| info!("{:?}", e.access(&pm)))?;
         Ok(true)
     }
     fn group(&mut self, c: Captures) -> Result<bool, Error> {
         let grouplabel = c.name("grouplabel").expect("grouplabel is none").as_str();
         let elements = c.name("elements").expect("elements is none").as_str();
-        let elements: Vec<&str> = group_elements().split(elements).collect();
-        let elements: &[&str] = &elements;
+        let elements = named_elements(elements);
         let pm = self.program_mut();
-        pm.define_group(grouplabel, elements)
+        pm.define_named_group(grouplabel, &elements)
             .map(|e| info!("{:?}", e.access(&pm)))?;
         Ok(true)
     }
@@ -205,11 +394,25 @@ impl CommandContext {
         pm.set_export(exportlabel)?;
         Ok(true)
     }
+    fn declareextern(&mut self, c: Captures) -> Result<bool, Error> {
+        let name = c.name("externname").expect("externname is none").as_str();
+        let _ = self.program_mut().declare_extern(name)?;
+        Ok(true)
+    }
+    fn setstrict(&mut self, c: Captures) -> Result<bool, Error> {
+        let mode = c.name("strictmode").expect("strictmode is none").as_str();
+        self.program_mut().set_strict(mode == "on");
+        Ok(true)
+    }
     fn showprog(&mut self, _c: Captures) -> Result<bool, Error> {
         let pm = self.program();
         println!("{}", pm);
         Ok(true)
     }
+    fn showarity(&mut self, _c: Captures) -> Result<bool, Error> {
+        print!("{}", self.program().infer_arity());
+        Ok(true)
+    }
     fn showexternset(&mut self, _: Captures) -> Result<bool, Error> {
         println!("fact");
         println!("bint");
@@ -217,7 +420,40 @@ impl CommandContext {
     }
     fn delete(&mut self, c: Captures) -> Result<bool, Error> {
         let label = c.name("deletelabel").expect("deletelabel is none").as_str();
-        self.program_mut().delete_ent(label)?;
+        self.program_mut().safe_delete(label)?;
+        Ok(true)
+    }
+    fn rename(&mut self, c: Captures) -> Result<bool, Error> {
+        let from = c.name("renamefrom").expect("renamefrom is none").as_str();
+        let to = c.name("renameto").expect("renameto is none").as_str();
+        self.program_mut().rename(from, to)?;
+        Ok(true)
+    }
+    fn inline(&mut self, c: Captures) -> Result<bool, Error> {
+        let label = c.name("inlinelabel").expect("inlinelabel is none").as_str();
+        let pm = self.program_mut();
+        pm.inline_jmp(label)?;
+        info!("{}", pm);
+        Ok(true)
+    }
+    fn extract(&mut self, c: Captures) -> Result<bool, Error> {
+        let label = c
+            .name("extractlabel")
+            .expect("extractlabel is none")
+            .as_str();
+        let elements = c
+            .name("extractelements")
+            .expect("extractelements is none")
+            .as_str();
+        let filename = c
+            .name("extractfilename")
+            .expect("extractfilename is none")
+            .as_str()
+            .trim();
+        let elements = named_elements(elements.trim());
+        let extracted = self.program().extract_group(label, &elements)?;
+        info!("{}", extracted);
+        Self::save_program(&extracted, filename)?;
         Ok(true)
     }
     fn compile(&mut self, c: Captures) -> Result<bool, Error> {
@@ -225,31 +461,40 @@ impl CommandContext {
             .name("externalset_compile")
             .expect("externalset_compile is none")
             .as_str();
-        let externs = match externs {
-            "fact" => FACT_EXTERNS,
-            "bint" => BINT_EXTERNS,
-            _ => bail!("extern set not found: {}", externs),
+        let externs: Vec<_> = Self::extern_set(externs)?.collect();
+        for name in self
+            .program()
+            .unused_externs(externs.iter().map(|e| e.name()))
+        {
+            println!("warning: extern {} is provided but never used", name);
         }
-        .iter()
-        .map(|f| f());
+        let externs = externs.into_iter();
         use CommandContext::*;
         match self {
             Idle {
                 program,
                 ref mut compiled,
+                ..
             } => {
-                *compiled = Some(program.compile(externs)?);
+                let prog = program.compile(externs)?;
+                Self::validate(&prog)?;
+                *compiled = Some(prog);
                 Ok(true)
             }
             Stepping {
-                ref mut program, ..
+                ref mut program,
+                ref mut debugger,
+                ..
             } => {
                 if !prompt_and_ask("You are in stepping mode. Quit?")? {
                     let program = std::mem::replace(program, Default::default());
+                    let debugger = std::mem::take(debugger);
                     let compiled = program.compile(externs)?;
+                    Self::validate(&compiled)?;
                     *self = Idle {
                         program,
                         compiled: Some(compiled),
+                        debugger,
                     };
                 }
                 Ok(true)
@@ -257,6 +502,40 @@ impl CommandContext {
         }
     }
 
+    /// Check a compiled program before keeping it, printing all problems found
+    ///
+    fn validate(prog: &Program) -> Result<(), Error> {
+        if let Err(errors) = prog.validate() {
+            for error in errors.iter() {
+                println!("  {}", error);
+            }
+            bail!("The program has {} structural problem(s)", errors.len());
+        }
+        Ok(())
+    }
+
+    /// Find a set of external functions by name
+    ///
+    fn extern_set(name: &str) -> Result<impl Iterator<Item = ExternEntry>, Error> {
+        Ok(match name {
+            "fact" => FACT_EXTERNS,
+            "bint" => BINT_EXTERNS,
+            _ => bail!("extern set not found: {}", name),
+        }
+        .iter()
+        .map(|f| f()))
+    }
+    /// Create the values to run a program with: the `print` continuation
+    /// followed by the given values
+    ///
+    fn initial_context(values: &str) -> Result<Box<dyn Context>, Error> {
+        let mut ctx: Box<dyn Context> = lincoln_common::default_context();
+        ctx.push(lincoln_compiled::native_closure("print", |c, _| print(c)));
+        for value in Self::parse_string(values)? {
+            ctx.push(value);
+        }
+        Ok(ctx)
+    }
     fn parse_string(values: &str) -> Result<Vec<Box<dyn Value>>, Error> {
         let us = Regex::new("(?P<value>[1-9]?[0-9]*|0)usize")?;
         let mut r = vec![];
@@ -276,79 +555,254 @@ impl CommandContext {
             .name("exportlabel_run")
             .expect("exportlabel_run is none")
             .as_str();
-        let variant = c
-            .name("runvariant")
-            .expect("runvariant is none")
-            .as_str()
-            .parse::<u8>()?;
-        let values = c.name("value").expect("value is none").as_str();
-        let values = Self::parse_string(values)?;
+        let variant = c.name("runvariant").expect("runvariant is none").as_str();
+        let values = c.name("value").expect("value is none").as_str().to_string();
+        let mut ctx = Self::initial_context(&values)?;
         let step = c.name("runstep").map(|_| true).unwrap_or(false);
-        let mut ctx: Box<dyn Context> = lincoln_common::default_context();
-        ctx.push(lincoln_compiled::native_closure("print", |c, _| print(c)));
-        for value in values {
-            ctx.push(value);
-        }
-        let (program, compiled, is_stepping) = match self {
-            Idle { compiled: None, .. } => {
-                bail!("Program is not compiled. Please compile it first (use compile command)")
+        if let Stepping { .. } = self {
+            if !prompt_and_ask("You are stepping into the program. Restart?")? {
+                return Ok(true);
             }
+            self.stop_stepping();
+        }
+        let (program, compiled, debugger) = match self {
             Idle {
                 compiled: Some(compiled),
                 program,
-            } => (program, compiled, false),
-            Stepping {
-                program, compiled, ..
-            } => (program, compiled, true),
+                debugger,
+            } => (program, compiled, debugger),
+            _ => bail!("Program is not compiled. Please compile it first (use compile command)"),
         };
+        let variant = export_variant(compiled, entry, variant)?;
 
-        if is_stepping && !prompt_and_ask("You are stepping into the program. Restart?")? {
-            return Ok(true);
-        }
-
-        if !step {
+        if let Some(filename) = c.name("tracefilename") {
+            let format = match c.name("tracechrome") {
+                Some(_) => TraceFormat::Chrome,
+                None => TraceFormat::JsonLines,
+            };
+            let out = BufWriter::new(File::create(filename.as_str())?);
+            let mut writer = TraceWriter::new(out, format, compiled.labels())
+                .deterministic(c.name("tracedeterministic").is_some());
+            let mut machine = Machine::start(&*compiled, ctx, entry, variant)?;
+            let state = machine.run_observed(&mut writer);
+            let _ = writer.finish()?;
+            if let MachineState::Failed(e) = state {
+                return Err(e.into());
+            }
+        } else if !step && !debugger.has_breakpoints() {
             compiled.run(&mut *ctx, entry, variant, None)?;
         } else {
-            let entry = compiled.get_export_ent(entry, variant)?;
-            println!("{:?} {}", entry, ctx);
-            let program = std::mem::replace(program, Default::default());
-            let compiled = std::mem::replace(compiled, Default::default());
+            let program = std::mem::take(program);
+            let compiled = std::mem::take(compiled);
+            let mut debugger = std::mem::take(debugger);
+            debugger.reset();
+            let initial =
+                move || Self::initial_context(&values).expect("values were parsed before");
+            let machine = Machine::start_with_history(compiled, initial, entry, variant)?;
+            println!("{}", machine);
             *self = Stepping {
                 program,
-                compiled,
-                current: entry,
-                context: ctx,
-                round: 1,
+                machine,
+                debugger,
             };
+            if !step {
+                return self.debug(Debugger::continue_run);
+            }
         };
 
         Ok(true)
     }
+    fn profile(&mut self, c: Captures) -> Result<bool, Error> {
+        use CommandContext::*;
+        let entry = c
+            .name("exportlabel_profile")
+            .expect("exportlabel_profile is none")
+            .as_str();
+        let variant = c
+            .name("profilevariant")
+            .expect("profilevariant is none")
+            .as_str();
+        let values = c
+            .name("profilevalue")
+            .expect("profilevalue is none")
+            .as_str();
+        let ctx = Self::initial_context(values)?;
+        let compiled = match self {
+            Idle {
+                compiled: Some(compiled),
+                ..
+            } => compiled,
+            Idle { .. } => {
+                bail!("Program is not compiled. Please compile it first (use compile command)")
+            }
+            Stepping { .. } => bail!("Cannot profile in stepping mode."),
+        };
+        let variant = export_variant(compiled, entry, variant)?;
+        let mut profiler = Profiler::new();
+        let mut machine = Machine::start(&*compiled, ctx, entry, variant)?;
+        let state = machine.run_observed(&mut profiler);
+        let report = profiler.report(compiled);
+        if c.name("profilejson").is_some() {
+            println!("{}", report.to_json()?);
+        } else {
+            println!("{}", report);
+        }
+        if let MachineState::Failed(e) = state {
+            return Err(e.into());
+        }
+        Ok(true)
+    }
+    fn check(&mut self, c: Captures) -> Result<bool, Error> {
+        use CommandContext::*;
+        let entry = c
+            .name("exportlabel_check")
+            .expect("exportlabel_check is none")
+            .as_str();
+        let variant = c
+            .name("checkvariant")
+            .expect("checkvariant is none")
+            .as_str();
+        let values = c.name("checkvalue").expect("checkvalue is none").as_str();
+        let compiled = match self {
+            Idle {
+                compiled: Some(compiled),
+                ..
+            } => compiled,
+            Idle { .. } => {
+                bail!("Program is not compiled. Please compile it first (use compile command)")
+            }
+            Stepping { .. } => bail!("Cannot check in stepping mode."),
+        };
+        let variant = export_variant(compiled, entry, variant)?;
+        let mut checker = LinearityChecker::new();
+        let ctx = Self::initial_context(values)?;
+        let mut machine = Machine::start(&*compiled, ctx, entry, variant)?;
+        let state = machine.run_observed(&mut checker);
+        println!("{}", checker.finish());
+        if let MachineState::Failed(e) = state {
+            return Err(e.into());
+        }
+        Ok(true)
+    }
     fn step(&mut self, _c: Captures) -> Result<bool, Error> {
+        self.debug(Debugger::step)
+    }
+    fn back(&mut self, c: Captures) -> Result<bool, Error> {
         use CommandContext::*;
-        let (program, compiled, context, current, round) = match self {
+        let count = match c.name("backcount") {
+            Some(count) => count.as_str().parse::<usize>()?,
+            None => 1,
+        };
+        match self {
             Stepping {
-                compiled,
-                context,
-                current,
-                round,
-                program,
-            } => (program, compiled, context, current, round),
+                machine, debugger, ..
+            } => {
+                machine.back(count)?;
+                debugger.reset();
+                println!("{}", machine);
+            }
+            _ => bail!("Not in stepping mode. Run the program in step mode first."),
+        }
+        Ok(true)
+    }
+    fn next(&mut self, _c: Captures) -> Result<bool, Error> {
+        self.debug(Debugger::step_over)
+    }
+    fn finish(&mut self, _c: Captures) -> Result<bool, Error> {
+        self.debug(Debugger::step_out)
+    }
+    fn cont(&mut self, _c: Captures) -> Result<bool, Error> {
+        self.debug(Debugger::continue_run)
+    }
+    /// Drive the stepping program with the debugger, then report where it stopped
+    ///
+    fn debug(
+        &mut self,
+        f: impl FnOnce(&mut Debugger, &mut Machine<Program>) -> DebugStop,
+    ) -> Result<bool, Error> {
+        use CommandContext::*;
+        let (machine, debugger) = match self {
+            Stepping {
+                machine, debugger, ..
+            } => (machine, debugger),
             _ => bail!("Not in stepping mode. Run the program in step mode first."),
         };
-        let next = compiled.eval(&mut **context, &current)?;
-        *round += 1;
-        if let CodeRef::Termination = next {
-            *self = Idle {
-                program: std::mem::replace(program, Default::default()),
-                compiled: Some(std::mem::replace(compiled, Default::default())),
+        match f(debugger, machine) {
+            DebugStop::Failed(e) => return Err(e.into()),
+            DebugStop::Terminated => self.stop_stepping(),
+            DebugStop::Stepped => println!("{}", machine),
+            DebugStop::Breakpoint(id) => {
+                if let Some((_, bp)) = debugger.breakpoints().find(|(i, _)| *i == id) {
+                    println!("breakpoint {}: {}", id, bp);
+                }
+                println!("{}", machine);
             }
-        } else {
-            println!("{}: {:?} {}", round, next, context);
-            *current = next;
         }
         Ok(true)
     }
+    fn breakpoint(&mut self, c: Captures) -> Result<bool, Error> {
+        let kind = c.name("breakkind").expect("breakkind is none").as_str();
+        let target = c.name("breaktarget").expect("breaktarget is none").as_str();
+        let location = match kind {
+            "entry" => BreakLocation::Code(CodeRef::entry(target.parse()?)),
+            "extern" => BreakLocation::Extern(target.into()),
+            _ => BreakLocation::Export(target.into()),
+        };
+        let mut bp = Breakpoint::new(location);
+        if let Some(cond) = c.name("breakcond") {
+            let words: Vec<&str> = group_elements().split(cond.as_str()).collect();
+            bp = bp.when(match words[..] {
+                ["len", len] => BreakCondition::ContextLen(len.parse()?),
+                ["ret", variant] => BreakCondition::ReturnVariant(variant.parse()?),
+                ["slot", slot, "is", type_name] => BreakCondition::ValueType {
+                    slot: slot.parse()?,
+                    type_name: type_name.into(),
+                },
+                _ => bail!("invalid condition: {}", cond.as_str()),
+            });
+        }
+        let id = self.debugger_mut().add_breakpoint(bp);
+        println!("breakpoint {} set", id);
+        Ok(true)
+    }
+    fn clear(&mut self, c: Captures) -> Result<bool, Error> {
+        let id = c
+            .name("clearid")
+            .expect("clearid is none")
+            .as_str()
+            .parse()?;
+        if self.debugger_mut().remove_breakpoint(id).is_none() {
+            bail!("breakpoint {} not found", id);
+        }
+        Ok(true)
+    }
+    fn showbreakpoints(&mut self, _c: Captures) -> Result<bool, Error> {
+        for (id, bp) in self.debugger().breakpoints() {
+            println!("{}: {}", id, bp);
+        }
+        Ok(true)
+    }
+    /// Leave the stepping mode, keep the program and the compiled program
+    ///
+    fn stop_stepping(&mut self) {
+        use CommandContext::*;
+        let old = std::mem::take(self);
+        *self = match old {
+            Stepping {
+                program,
+                machine,
+                debugger,
+            } => {
+                let (compiled, _, _) = machine.into_parts();
+                Idle {
+                    program,
+                    compiled: Some(compiled),
+                    debugger,
+                }
+            }
+            idle => idle,
+        }
+    }
 }
 macro_rules! handle_cmd {
     ($cmd: ident, $c:expr, $pm:expr) => {
@@ -360,18 +814,37 @@ macro_rules! handle_cmd {
 
 pub fn process(c: Captures, ctx: &mut CommandContext) -> Result<bool, Error> {
     handle_cmd!(showprog, c, ctx);
+    handle_cmd!(showarity, c, ctx);
     handle_cmd!(showexternset, c, ctx);
+    handle_cmd!(savebytecode, c, ctx);
+    handle_cmd!(savecompiled, c, ctx);
+    handle_cmd!(loadcompiled, c, ctx);
     handle_cmd!(save, c, ctx);
+    handle_cmd!(loadmerge, c, ctx);
     handle_cmd!(load, c, ctx);
     handle_cmd!(jmp, c, ctx);
     handle_cmd!(call, c, ctx);
     handle_cmd!(ret, c, ctx);
     handle_cmd!(group, c, ctx);
     handle_cmd!(setexport, c, ctx);
+    handle_cmd!(declareextern, c, ctx);
+    handle_cmd!(setstrict, c, ctx);
     handle_cmd!(compile, c, ctx);
     handle_cmd!(run, c, ctx);
+    handle_cmd!(profile, c, ctx);
+    handle_cmd!(check, c, ctx);
     handle_cmd!(step, c, ctx);
+    handle_cmd!(back, c, ctx);
+    handle_cmd!(next, c, ctx);
+    handle_cmd!(finish, c, ctx);
+    handle_cmd!(cont, c, ctx);
+    handle_cmd!(breakpoint, c, ctx);
+    handle_cmd!(clear, c, ctx);
+    handle_cmd!(showbreakpoints, c, ctx);
     handle_cmd!(delete, c, ctx);
+    handle_cmd!(rename, c, ctx);
+    handle_cmd!(inline, c, ctx);
+    handle_cmd!(extract, c, ctx);
     if c.name("exit").is_some() {
         println!("{}", ctx);
         return Ok(false);
@@ -380,6 +853,39 @@ pub fn process(c: Captures, ctx: &mut CommandContext) -> Result<bool, Error> {
     Ok(true)
 }
 
+/// The number of a variant of an exported entry, given as a number or a name
+fn export_variant(compiled: &Program, entry: &str, variant: &str) -> Result<u8, Error> {
+    if let Some(variant) = variant_number(variant)? {
+        return Ok(variant);
+    }
+    compiled
+        .labels()
+        .variant(compiled.get_export(entry)?, variant)
+        .ok_or_else(|| format_err!("{} has no variant named {}", entry, variant))
+}
+
+/// The number of a variant given as a number, or None if it is a name
+fn variant_number(variant: &str) -> Result<Option<u8>, Error> {
+    if variant.is_empty() || !variant.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(None);
+    }
+    match variant.parse() {
+        Ok(variant) => Ok(Some(variant)),
+        Err(_) => bail!("Variant {} is out of range, variants are 0 to 255", variant),
+    }
+}
+
+/// Split group elements written as `<label>` or `<name>=<label>`
+fn named_elements(elements: &str) -> Vec<(Option<&str>, &str)> {
+    group_elements()
+        .split(elements)
+        .map(|e| match e.find('=') {
+            Some(idx) => (Some(&e[..idx]), &e[idx + 1..]),
+            None => (None, e),
+        })
+        .collect()
+}
+
 fn group_elements() -> Regex {
     Regex::new(r"\s+").unwrap()
 }
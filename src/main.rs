@@ -13,6 +13,7 @@ pub mod externs;
 
 use crate::command::{commands, process, CommandContext};
 use failure::Error;
+use lincoln_compiled::EvalError;
 use rustyline::Editor;
 
 fn print_help() {
@@ -20,18 +21,60 @@ fn print_help() {
         r#"Syntax (<label> are identifiers, [number:u8] are numbers): 
     <jmplabel>: jmp <jmpcont> #!<permutation>
     <calllabel>: call <callee> [callcnt:u8] <callcont>
-    <retlabel>: ret [variant:u8]
+    <retlabel>: ret <variant>
     <grouplabel>: group <element>*
     setexport <label>
+    delete <label>
+    rename <label> <newlabel>
+    inline <jmplabel>
+    extract <grouplabel> <element>+ into <filename>
+    extern <name>
+    set strict <on|off>
     show external set
     show program
+    show arity
     compile <enternal set>
-    run <entry> [variant:u8] "<value>"
-    run <entry> [variant:u8] "<value>" step
+    run <entry> <variant> "<value>"
+    run <entry> <variant> "<value>" step
+    run <entry> <variant> "<value>" trace <filename> [chrome] [deterministic]
+    profile <entry> <variant> "<value>"
+    profile <entry> <variant> "<value>" json
+    check <entry> <variant> "<value>"
+    step
+    back [count]
+    next
+    finish
+    continue
+    break entry <index> [if <condition>]
+    break extern <name> [if <condition>]
+    break export <name> [if <condition>]
+    clear <breakpoint>
+    show breakpoints
     save <filename>
     load <filename>
+    load <filename> <fail|ours|theirs|prefix <prefix>>
+    save compiled <filename>
+    save bytecode <filename>
+    load compiled <filename> <external set>
     exit
     
+A <variant> is a number or the name of a variant. Group elements can name their variants as
+<name>=<label>, e.g. `k: group ok=done err=fail`, and `ret err` then returns on the variant
+named err of the continuation. Names are resolved to numbers when compiling.
+
+`delete` removes an entry only if it is neither used nor exported, otherwise it reports the users.
+`rename` renames an entry, keeping all references. `inline` merges the target of a jmp into the
+jmp. `extract` saves the entries reachable from the elements to a file, as a program exporting
+a new group of the elements.
+
+In strict mode, compiling fails if a name is neither defined nor declared with extern.
+
+`load` merges a program into the current one. Labels defined differently in both are
+overwritten by default; `fail` stops the load, `ours` keeps the current definitions and `prefix`
+renames the loaded labels. What was added, overwritten, kept or renamed is reported.
+
+Programs in files ending with .lcir are saved and loaded in the text format, otherwise in JSON.
+
 Permutations are strings contains charactor a-t to specify permutations. Examples:
 
     "" or "a" or "ab" or "abc"... - the identical permutation;
@@ -41,12 +84,35 @@ Permutations are strings contains charactor a-t to specify permutations. Example
     third with the first.
 
 and so on.
+
+Breakpoint conditions are one of:
+
+    len [count:u8] - the context has exactly this number of values;
+    ret [variant:u8] - the entry returns on this variant;
+    slot [index:u8] is <type> - the value at this position has the type (e.g. usize).
+
+`run ... trace` writes every step to a file, as JSON lines or in the Chrome trace-event
+format (`chrome`). In `deterministic` mode no time is recorded, so traces can be compared.
+
+`profile` runs the program and reports how many times each entry, external function and
+group variant was evaluated, the time spent in external functions, the peak context length
+and the number of closures created.
+
+`check` runs the program and reports the linear values that were dropped without being
+used, or are still in the context when the program terminates. `usize` values can be
+copied and dropped, values of other host types are linear unless registered otherwise.
+
+`next` steps over a call (until its continuation is resumed), `finish` runs until the
+continuation of the innermost call is resumed. `back` goes back a number of steps (1 by
+default) by replaying the program from the start, so external functions are called again.
 "#
     );
 }
 
 fn main() -> Result<(), Error> {
     env_logger::init();
+    // The numbers of the externs can be copied and dropped by closures capturing them
+    lincoln_common::register_unrestricted::<usize>();
     let commands = commands();
 
     let mut cmdctx = CommandContext::default();
@@ -62,7 +128,13 @@ fn main() -> Result<(), Error> {
             match process(c, &mut cmdctx) {
                 Ok(true) => continue,
                 Ok(false) => return Ok(()),
-                Err(e) => error!("{}", e),
+                Err(e) => {
+                    error!("{}", e);
+                    if let Some(report) = e.downcast_ref::<EvalError>().and_then(EvalError::report)
+                    {
+                        println!("{}", report);
+                    }
+                }
             }
         } else {
             print_help();